@@ -1,17 +1,132 @@
 use crate::{Event, EventBusSender};
 use std::io;
+use std::io::Write;
 
 #[derive(Debug, Clone)]
 pub enum AdminEvent {
     Message(String),
 }
 
+/*
+Admin CLI
+    <message>           sent to the bot as if typed in chat
+    /test <trigger> [args] [=> <unsaved response>]
+    /repl               runs each entry with !eval, once its brackets balance or at a blank line
+    /exit               leaves the repl
+*/
 pub fn cli_run(sender: EventBusSender) {
+    let mut repl = false;
+    let mut script = String::new();
     loop {
+        if repl {
+            print!(
+                "{}",
+                if script.is_empty() {
+                    "rhai> "
+                } else {
+                    "....> "
+                }
+            );
+            io::stdout().flush().unwrap();
+        }
         let mut buffer = String::new();
         io::stdin().read_line(&mut buffer).unwrap();
         let buffer = buffer.trim();
-        let event = AdminEvent::Message(buffer.to_string());
+
+        if repl {
+            if script.is_empty() && buffer == "/exit" {
+                repl = false;
+                continue;
+            }
+            // Keep reading while the entry is unfinished.
+            if !buffer.is_empty() {
+                script += buffer;
+                script += "\n";
+                if is_unfinished(&script) {
+                    continue;
+                }
+            }
+            if script.trim().is_empty() {
+                continue;
+            }
+            let event = AdminEvent::Message(format!("!eval {}", script.trim()));
+            sender.send(Event::AdminEvent(event));
+            script.clear();
+            continue;
+        }
+
+        if buffer == "/repl" {
+            repl = true;
+            continue;
+        }
+        let event = AdminEvent::Message(expand_shorthand(buffer));
         sender.send(Event::AdminEvent(event));
     }
 }
+
+// Shorthand for a dry run: /test <trigger> [args] [=> <unsaved response>]
+fn expand_shorthand(line: &str) -> String {
+    if line.starts_with("/test ") {
+        format!("!command test {}", &line["/test ".len()..])
+    } else {
+        line.to_string()
+    }
+}
+
+// Unbalanced brackets mean the entry continues on the next line. Brackets in strings and
+// comments don't count, and a string left open ends with its line.
+fn is_unfinished(script: &str) -> bool {
+    let mut depth = 0;
+    let mut chars = script.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            '/' if chars.peek() == Some(&'/') => {
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            '"' | '\'' => {
+                while let Some(next) = chars.next() {
+                    match next {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '\n' => break,
+                        next if next == c => break,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+#[test]
+fn test_is_unfinished() {
+    assert!(is_unfinished("fn greet(x) {\n"));
+    assert!(is_unfinished("let x = [1,\n"));
+    assert!(!is_unfinished("fn greet(x) {\n\"Hi \" + x\n}\n"));
+    assert!(!is_unfinished("\"(\"\n"));
+    assert!(!is_unfinished("'{'\n"));
+    assert!(!is_unfinished("\"\\\"(\"\n"));
+    assert!(!is_unfinished("1 // (\n"));
+    assert!(!is_unfinished("\"unclosed (\n"));
+    assert!(is_unfinished("\"}\" + (\n"));
+}
+
+#[test]
+fn test_expand_shorthand() {
+    assert_eq!(
+        expand_shorthand("/test !hello bob => Hi $1"),
+        "!command test !hello bob => Hi $1"
+    );
+    assert_eq!(expand_shorthand("!hello"), "!hello");
+    assert_eq!(expand_shorthand("/testing"), "/testing");
+}
@@ -3,20 +3,41 @@ use crate::command::CommandExt;
 use crate::command::Commands;
 use crate::database::Database;
 use crate::discord::DiscordEvent;
+#[cfg(test)]
+use crate::models::CacheSettings;
 use crate::models::{
-    Action, ActionError, Command, EditType, Message, Source, User, Variable, VariableValue,
+    is_admin_identity, Action, ActionError, Command, EditType, HttpSettings, Message,
+    MessageTarget, ScheduledTask, ScriptAction, ScriptError, ScriptLimits, ScriptModule,
+    ScriptTier, Source, StringItem, User, Variable, VariableChange, VariableValue, VariableWatcher,
 };
+use crate::output_cache::OutputCache;
+use crate::schedule::Schedule;
+use crate::script_runner;
+use crate::script_runner::ScriptContext;
+use crate::search;
 use crate::twitch::TwitchEvent;
 use crate::{special_command, Event, EventBusSender};
+use chrono::{TimeZone, Utc};
 use crossbeam::channel::Receiver;
 use regex::Regex;
 use rusqlite::Error;
 use serde::{Deserialize, Serialize};
 use serenity::http::AttachmentType as DiscordAttachmentType;
-use serenity::model::id::ChannelId;
+use serenity::model::id::{ChannelId, GuildId, UserId};
 use serenity::prelude::Context as DiscordContext;
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::Timespec;
+use twitchchat::Writer as TwitchWriter;
+
+// Pending tasks a single user can have at once.
+const MAX_SCHEDULED_TASKS: i64 = 10;
+// How late a task can still run, e.g. while the chat clients connect after a restart.
+const MISSED_TASK_SECS: i64 = 60;
+const TWITCH_MAX_LENGTH: usize = 500;
+const DISCORD_MAX_LENGTH: usize = 2000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BotEvent {
@@ -33,6 +54,11 @@ pub enum BotEvent {
     AddVariable(Variable, User),
     EditVariable(Variable, User),
     DeleteVariable(Variable, User),
+
+    // User script module actions.
+    AddModule(ScriptModule, User),
+    EditModule(ScriptModule, User),
+    DeleteModule(ScriptModule, User),
 }
 
 pub struct Bot {
@@ -43,15 +69,38 @@ pub struct Bot {
     pub event_rx: Receiver<Event>,
 
     pub database: Database,
+    output_cache: OutputCache,
 
     notification_channel: Option<(Box<Arc<Mutex<DiscordContext>>>, ChannelId)>,
+    twitch_writer: Option<TwitchWriter>,
 }
 
 impl Bot {
     pub fn new(sender: EventBusSender, event_rx: Receiver<Event>) -> Result<Bot, Error> {
-        let database = Database::new()?;
+        Bot::with_database(sender, event_rx, Database::new()?)
+    }
+
+    fn with_database(
+        sender: EventBusSender,
+        event_rx: Receiver<Event>,
+        database: Database,
+    ) -> Result<Bot, Error> {
         let mut commands = special_command::commands();
-        commands.append(database.get_commands()?.as_mut());
+        let mut database_commands = database.get_commands()?;
+        // Commands from before authors were kept can only read, until someone edits them.
+        for command in database_commands.iter() {
+            if command.author.is_empty()
+                && command.tier.is_none()
+                && command.response.contains("{{")
+            {
+                println!(
+                    "Command {} has no author, its scripts can only read. \
+                     Edit it with !command edit to run them as yours.",
+                    command.trigger
+                );
+            }
+        }
+        commands.append(database_commands.as_mut());
         for command in commands.iter() {
             sender.send(Event::BotEvent(BotEvent::LoadCommand(command.clone())));
         }
@@ -64,7 +113,9 @@ impl Bot {
             sender,
             event_rx,
             database,
+            output_cache: OutputCache::new(),
             notification_channel: None,
+            twitch_writer: None,
         };
         Ok(stovbot)
     }
@@ -80,12 +131,14 @@ impl Bot {
 
     pub fn run(&mut self) {
         loop {
-            let message = match self.event_rx.recv() {
+            // Wakes up at least once a second for the scheduler.
+            let message = match self.event_rx.recv_timeout(Duration::from_secs(1)) {
                 Ok(event) => match event {
                     Event::BotEvent(_) => None,
                     Event::TwitchEvent(event) => match event {
                         TwitchEvent::Ready(writer) => {
                             writer.join("stovoy").unwrap();
+                            self.twitch_writer = Some(writer);
                             None
                         }
                         TwitchEvent::PrivMsg(writer, msg) => Some(Message {
@@ -121,88 +174,317 @@ impl Bot {
                 },
                 Err(_) => None,
             };
-            match message {
-                None => {}
-                Some(message) => match self.respond(&message) {
-                    None => {}
-                    Some(response) => self.send_message(&message.source, &response.text),
-                },
+            if let Some(message) = message {
+                self.handle_message(&message);
+            }
+            self.run_due_tasks(Utc::now().timestamp());
+            self.delete_expired_variables();
+        }
+    }
+
+    fn handle_message(&mut self, message: &Message) {
+        if let Some(response) = self.respond(message) {
+            self.send_message(&message.source, &response.text);
+            for failure in response.script_failures.iter() {
+                self.send_script_failure(&message.source, failure);
+            }
+        }
+    }
+
+    // Tasks stay due until their platform is connected, so nothing is lost on a restart.
+    fn run_due_tasks(&mut self, now: i64) {
+        let tasks = match self.database.get_due_tasks(now) {
+            Ok(tasks) => tasks,
+            Err(e) => {
+                println!("Error getting scheduled tasks: {}", e);
+                return;
+            }
+        };
+        for task in tasks {
+            // Late output is confusing, so a missed run is skipped instead.
+            let missed = now - task.time_due > MISSED_TASK_SECS;
+            if !missed && !self.can_reach(&task.target) {
+                continue;
+            }
+            // Moved along before running, so a failing task doesn't run over and over.
+            let next_due = match Schedule::parse(&task.repeat) {
+                Ok(schedule) if schedule.repeats() => schedule.next_due(now),
+                _ => None,
+            };
+            let result = match next_due {
+                Some(time_due) => self.database.reschedule_task(task.id, time_due),
+                None => self.database.delete_scheduled_task(task.id),
             };
+            if let Err(e) = result {
+                println!("Error updating scheduled task {}: {}", task.id, e);
+                continue;
+            }
+            if missed {
+                println!("Skipped scheduled task {}, it's too late to run", task.id);
+                continue;
+            }
+
+            if task.is_command {
+                self.handle_message(&Message {
+                    sender: User {
+                        username: task.username,
+                    },
+                    text: task.text,
+                    source: Source::Scheduled(task.target, task.author),
+                });
+            } else {
+                self.send_to_target(&task.target, &task.text);
+            }
+        }
+    }
+
+    // Expired variables already read as missing, this removes them for good.
+    fn delete_expired_variables(&mut self) {
+        let now = Utc::now().timestamp();
+        let variables = match self.database.get_expired_variables(now) {
+            Ok(variables) => variables,
+            Err(e) => {
+                println!("Error getting expired variables: {}", e);
+                return;
+            }
+        };
+        for variable in variables {
+            match self.database.delete_expired_variable(&variable.name, now) {
+                Ok(0) => {}
+                Ok(_) => self.sender.send(Event::BotEvent(BotEvent::DeleteVariable(
+                    variable,
+                    User {
+                        username: self.username.clone(),
+                    },
+                ))),
+                Err(e) => println!("Error deleting variable {}: {}", variable.name, e),
+            }
+        }
+    }
+
+    fn can_reach(&self, target: &MessageTarget) -> bool {
+        match target {
+            MessageTarget::Twitch(_) => self.twitch_writer.is_some(),
+            MessageTarget::Discord(_) => self.notification_channel.is_some(),
+            MessageTarget::Admin => true,
+        }
+    }
+
+    fn check_action(&self, action: &Action) -> Option<ActionError> {
+        match action {
+            Action::AddCommand(command) => {
+                if self.commands.contains(command) {
+                    Some(ActionError::CommandAlreadyExists)
+                } else {
+                    None
+                }
+            }
+            Action::EditCommand(command) => {
+                if !self.commands.contains(command) {
+                    Some(ActionError::CommandDoesNotExist)
+                } else if self.is_builtin_command(command) {
+                    Some(ActionError::CannotModifyBuiltInCommand)
+                } else {
+                    None
+                }
+            }
+            Action::DeleteCommand(command) => {
+                if !self.commands.contains(command) {
+                    Some(ActionError::CommandDoesNotExist)
+                } else if self.is_builtin_command(command) {
+                    Some(ActionError::CannotDeleteBuiltInCommand)
+                } else {
+                    None
+                }
+            }
+            Action::AddVariable(variable) => match self.database.get_variable(&variable.name) {
+                Ok(_) => Some(ActionError::VariableAlreadyExists),
+                Err(_) => None,
+            },
+            Action::EditVariable(_, EditType::Decrement(by)) if by.checked_neg().is_none() => {
+                Some(ActionError::BadVariable(by.to_string()))
+            }
+            Action::EditVariable(variable, edit_type) => {
+                // TODO: Catch other DB connection errors.
+                match self.database.get_variable(&variable.name) {
+                    Ok(old_variable) => match edit_type {
+                        EditType::Reset() => None,
+                        EditType::RemoveAt(_) => match old_variable.value {
+                            VariableValue::Map(_) => Some(ActionError::VariableWrongType),
+                            _ => None,
+                        },
+                        EditType::SetKey(_) => match old_variable.value {
+                            VariableValue::Map(_) => None,
+                            _ => Some(ActionError::VariableWrongType),
+                        },
+                        EditType::RemoveKey(key) => match old_variable.value {
+                            VariableValue::Map(map) if map.contains_key(key) => None,
+                            VariableValue::Map(_) => Some(ActionError::VariableKeyDoesNotExist),
+                            _ => Some(ActionError::VariableWrongType),
+                        },
+                        EditType::Increment(_) | EditType::Decrement(_) => {
+                            match old_variable.value {
+                                VariableValue::Text(text)
+                                    if text.trim().is_empty()
+                                        || text.trim().parse::<i64>().is_ok() =>
+                                {
+                                    None
+                                }
+                                _ => Some(ActionError::VariableWrongType),
+                            }
+                        }
+                        _ => match (&variable.value, old_variable.value) {
+                            (VariableValue::Text(_), VariableValue::Text(_)) => None,
+                            (VariableValue::StringList(_), VariableValue::StringList(_)) => None,
+                            (VariableValue::Map(_), VariableValue::Map(_)) => None,
+                            _ => Some(ActionError::VariableWrongType),
+                        },
+                    },
+                    Err(_) => Some(ActionError::VariableDoesNotExist),
+                }
+            }
+            Action::DeleteVariable(variable) => {
+                // TODO: Catch other DB connection errors.
+                match self.database.get_variable(&variable.name) {
+                    Ok(_) => None,
+                    Err(_) => Some(ActionError::VariableDoesNotExist),
+                }
+            }
+            Action::SendLiveNotification => match &self.notification_channel {
+                None => Some(ActionError::NotificationChannelNotFound),
+                _ => None,
+            },
+            Action::SendMessage(target, _) => {
+                if self.can_reach(target) {
+                    None
+                } else {
+                    Some(ActionError::MessageTargetNotFound)
+                }
+            }
+            Action::AddModule(module) => match self.database.get_script_module(&module.name) {
+                Ok(_) => Some(ActionError::ModuleAlreadyExists),
+                Err(_) => self.check_module_source(module),
+            },
+            Action::EditModule(module) => match self.database.get_script_module(&module.name) {
+                Ok(_) => self.check_module_source(module),
+                Err(_) => Some(ActionError::ModuleDoesNotExist),
+            },
+            Action::DeleteModule(module) => match self.database.get_script_module(&module.name) {
+                Ok(_) => None,
+                Err(_) => Some(ActionError::ModuleDoesNotExist),
+            },
+            Action::SetScriptLimits(trigger, _) => match trigger {
+                Some(trigger) if self.commands.get(trigger).is_none() => {
+                    Some(ActionError::CommandDoesNotExist)
+                }
+                _ => None,
+            },
+            Action::SetOutputCache(trigger, _) => match self.commands.get(trigger) {
+                Some(command) if command.actor.is_some() => {
+                    Some(ActionError::CannotModifyBuiltInCommand)
+                }
+                Some(_) => None,
+                None => Some(ActionError::CommandDoesNotExist),
+            },
+            Action::ClearOutputCache(trigger) => match trigger {
+                Some(trigger) if self.commands.get(trigger).is_none() => {
+                    Some(ActionError::CommandDoesNotExist)
+                }
+                _ => None,
+            },
+            Action::EvalScript(_) | Action::ListScheduledTasks => None,
+            Action::ShowUserVariables(_) | Action::ClearUserVariables(_) => None,
+            Action::SetScriptTier(_, _) => None,
+            Action::ShowCommand(trigger, _) => match self.commands.get(trigger) {
+                Some(_) => None,
+                None => Some(ActionError::CommandDoesNotExist),
+            },
+            Action::ShowVariable(name, _, _) | Action::FindInVariable(name, _, _) => {
+                match self.database.get_variable(name) {
+                    Ok(_) => None,
+                    Err(_) => Some(ActionError::VariableDoesNotExist),
+                }
+            }
+            Action::ScheduleTask(task) => self.check_task_limit(&task.author, 0),
+            Action::DeleteScheduledTask(id) => match self.database.get_scheduled_tasks() {
+                Ok(tasks) if tasks.iter().any(|task| task.id == *id) => None,
+                _ => Some(ActionError::ScheduledTaskDoesNotExist),
+            },
+            Action::AddWatcher(_) | Action::ListWatchers(_) => None,
+            Action::DeleteWatcher(id) => match self.database.get_watchers(None) {
+                Ok(watchers) if watchers.iter().any(|watcher| watcher.id == *id) => None,
+                _ => Some(ActionError::WatcherDoesNotExist),
+            },
+            Action::AllowHttpDomain(_) | Action::SetHttpLimits(_) => None,
+            Action::DisallowHttpDomain(domain) => match self.database.get_http_settings() {
+                Ok(settings) if settings.allowed_domains.contains(domain) => None,
+                _ => Some(ActionError::HttpDomainNotAllowed),
+            },
+            Action::TestCommand(text, response) => {
+                if response.is_none() && self.get_triggered_command(text).is_none() {
+                    Some(ActionError::CommandDoesNotExist)
+                } else {
+                    None
+                }
+            }
         }
     }
 
+    // A dry run renders the command without keeping variable writes or applying any actions.
     fn process_command(
         &self,
         command: &Command,
         message: &Message,
+        dry_run: bool,
     ) -> Result<(BotMessage, Option<Action>), ActionError> {
+        let dry_run_command;
+        let command = if dry_run {
+            dry_run_command = command.clone().with_dry_run(true).build();
+            &dry_run_command
+        } else {
+            command
+        };
         let mut deferred_action = None;
         let action_error = match &command.actor {
             None => None,
-            Some(actor) => match actor.0(&command, message) {
-                // TODO: Add GetCommand and GetVariable which respond with the raw data.
+            Some(actor) => match actor.0(&command, message)
+                .and_then(|action| self.resolve_key_edit(action, command, message))
+            {
+                // Tested right away, since it doesn't change anything.
+                Ok(Action::TestCommand(text, response)) => {
+                    return self
+                        .test_command(text, response, message)
+                        .map(|response| (response, None));
+                }
+                Ok(Action::EvalScript(script)) => {
+                    return Ok((self.eval_script(&script, message, dry_run), None));
+                }
+                Ok(Action::ListScheduledTasks) => {
+                    return Ok((self.list_scheduled_tasks(), None));
+                }
+                Ok(Action::ShowUserVariables(user)) => {
+                    return Ok((self.show_user_variables(&user), None));
+                }
+                Ok(Action::ShowCommand(trigger, page)) => {
+                    return self
+                        .show_command(&trigger, page, message)
+                        .map(|response| (response, None));
+                }
+                Ok(Action::ShowVariable(name, index, page)) => {
+                    return self
+                        .show_variable(&name, index, page, message)
+                        .map(|response| (response, None));
+                }
+                Ok(Action::FindInVariable(name, query, page)) => {
+                    return self
+                        .find_in_variable(&name, &query, page, message)
+                        .map(|response| (response, None));
+                }
+                Ok(Action::ListWatchers(name)) => {
+                    return Ok((self.list_watchers(name.as_deref()), None));
+                }
                 Ok(action) => {
-                    let action_error = match &action {
-                        Action::AddCommand(command) => {
-                            if self.commands.contains(command) {
-                                Some(ActionError::CommandAlreadyExists)
-                            } else {
-                                None
-                            }
-                        }
-                        Action::EditCommand(command) => {
-                            if !self.commands.contains(command) {
-                                Some(ActionError::CommandDoesNotExist)
-                            } else if self.is_builtin_command(command) {
-                                Some(ActionError::CannotModifyBuiltInCommand)
-                            } else {
-                                None
-                            }
-                        }
-                        Action::DeleteCommand(command) => {
-                            if !self.commands.contains(command) {
-                                Some(ActionError::CommandDoesNotExist)
-                            } else if self.is_builtin_command(command) {
-                                Some(ActionError::CannotDeleteBuiltInCommand)
-                            } else {
-                                None
-                            }
-                        }
-                        Action::AddVariable(variable) => {
-                            match self.database.get_variable(&variable.name) {
-                                Ok(_) => Some(ActionError::VariableAlreadyExists),
-                                Err(_) => None,
-                            }
-                        }
-                        Action::EditVariable(variable, edit_type) => {
-                            // TODO: Catch other DB connection errors.
-                            match self.database.get_variable(&variable.name) {
-                                Ok(old_variable) => match edit_type {
-                                    EditType::RemoveAt(_) => None,
-                                    _ => match (&variable.value, old_variable.value) {
-                                        (VariableValue::Text(_), VariableValue::Text(_)) => None,
-                                        (
-                                            VariableValue::StringList(_),
-                                            VariableValue::StringList(_),
-                                        ) => None,
-                                        _ => Some(ActionError::VariableWrongType),
-                                    },
-                                },
-                                Err(_) => Some(ActionError::VariableDoesNotExist),
-                            }
-                        }
-                        Action::DeleteVariable(variable) => {
-                            // TODO: Catch other DB connection errors.
-                            match self.database.get_variable(&variable.name) {
-                                Ok(_) => None,
-                                Err(_) => Some(ActionError::VariableDoesNotExist),
-                            }
-                        }
-                        Action::SendLiveNotification => match &self.notification_channel {
-                            None => Some(ActionError::NotificationChannelNotFound),
-                            _ => None,
-                        },
-                    };
+                    let action_error = self.check_action(&action);
                     match action_error {
                         None => deferred_action = Some(action),
                         Some(_) => {}
@@ -219,15 +501,27 @@ impl Bot {
                     match self.get_triggered_command(&response.text) {
                         None => Err(ActionError::BadCommandAlias),
                         Some(command) => {
+                            let mut script_failures = response.script_failures;
+                            let mut actions = response.actions;
+                            let mut variable_changes = response.variable_changes;
                             self.process_command(
                                 command,
                                 &Message {
                                     sender: message.sender.clone(),
                                     text: response.text,
-                                    // TODO: Way to move or clone the source?
-                                    source: Source::Admin,
+                                    source: message.source.clone(),
                                 },
+                                dry_run,
                             )
+                            .map(|(mut response, action)| {
+                                script_failures.append(&mut response.script_failures);
+                                response.script_failures = script_failures;
+                                actions.append(&mut response.actions);
+                                response.actions = actions;
+                                variable_changes.append(&mut response.variable_changes);
+                                response.variable_changes = variable_changes;
+                                (response, action)
+                            })
                         }
                     }
                 } else {
@@ -238,6 +532,308 @@ impl Bot {
         }
     }
 
+    fn test_command(
+        &self,
+        text: String,
+        response: Option<String>,
+        message: &Message,
+    ) -> Result<BotMessage, ActionError> {
+        let command = match response {
+            Some(response) => {
+                let trigger = text.split(' ').next().unwrap_or("").to_string();
+                let mut command = Command::new(trigger, response)
+                    .with_author(message.sender_identity())
+                    .build();
+                command.database_path = self.database.path.clone();
+                command
+            }
+            None => match self.get_triggered_command(&text) {
+                Some(command) => command.clone(),
+                None => return Err(ActionError::CommandDoesNotExist),
+            },
+        };
+        let test_message = Message {
+            sender: message.sender.clone(),
+            text,
+            source: message.source.clone(),
+        };
+        let (mut response, action) = self.process_command(&command, &test_message, true)?;
+
+        let mut lines = vec![format!("Test of {}: {}", command.trigger, response.text)];
+        for change in response.variable_changes.iter() {
+            lines.push(format!("would change {}", change));
+        }
+        if let Some(action) = action {
+            lines.push(format!("would {}", describe_action(&action)));
+        }
+        for script_action in response.actions.drain(..) {
+            lines.push(
+                match self.script_action(script_action, &command.author, message) {
+                    Ok(action) => format!("would {}", describe_action(&action)),
+                    Err(e) => format!("would fail ({:?})", e),
+                },
+            );
+        }
+        response.text = lines.join(" | ");
+        // The tester already sees the errors, so don't bother the author.
+        response.script_failures.clear();
+        response.variable_changes.clear();
+        Ok(response)
+    }
+
+    // Running a module on its own only defines its functions, so this catches syntax errors and
+    // missing imports before any script uses it.
+    fn check_module_source(&self, module: &ScriptModule) -> Option<ActionError> {
+        let output = script_runner::run(
+            &format!("{}\n\"\"", module.source),
+            &ScriptContext {
+                database_path: &self.database.path,
+                limits: &ScriptLimits::default(),
+                dry_run: true,
+                user: &module.author,
+                command: "",
+                tier: ScriptTier::ReadOnly,
+                inputs: &BTreeMap::new(),
+            },
+        );
+        match output.result {
+            Ok(_) => None,
+            Err(error) => Some(ActionError::BadModuleSource(error.short())),
+        }
+    }
+
+    // Results and errors are shown in full, and requested actions are listed before they run.
+    fn eval_script(&self, script: &str, message: &Message, dry_run: bool) -> BotMessage {
+        let output = script_runner::run(
+            script,
+            &ScriptContext {
+                database_path: &self.database.path,
+                limits: &ScriptLimits::default(),
+                dry_run,
+                user: &message.sender_identity(),
+                command: "",
+                tier: ScriptTier::for_author(&message.sender_identity(), Some(&self.database)),
+                inputs: &BTreeMap::new(),
+            },
+        );
+        let mut lines = vec![match &output.result {
+            Ok(result) => format!("=> {:?}", result),
+            Err(error) => error.diagnostic(),
+        }];
+        for action in output.actions.iter() {
+            lines.push(format!("requested {:?}", action));
+        }
+        let mut response = BotMessage::new(lines.join("\n"));
+        response.actions = output.actions;
+        response.variable_changes = output.variable_changes;
+        response
+    }
+
+    fn list_scheduled_tasks(&self) -> BotMessage {
+        let tasks = match self.database.get_scheduled_tasks() {
+            Ok(tasks) => tasks,
+            Err(e) => return BotMessage::new(format!("Error getting scheduled tasks: {}", e)),
+        };
+        if tasks.is_empty() {
+            return BotMessage::new("Nothing is scheduled".to_string());
+        }
+        let now = Utc::now().timestamp();
+        let tasks: Vec<String> = tasks
+            .iter()
+            .map(|task| {
+                format!(
+                    "#{} in {}s{}: {}",
+                    task.id,
+                    max(task.time_due - now, 0),
+                    if task.repeat.is_empty() {
+                        "".to_string()
+                    } else {
+                        format!(" ({})", task.repeat)
+                    },
+                    task.text
+                )
+            })
+            .collect();
+        BotMessage::new(tasks.join(" | "))
+    }
+
+    fn list_watchers(&self, name: Option<&str>) -> BotMessage {
+        let watchers = match self.database.get_watchers(name) {
+            Ok(watchers) => watchers,
+            Err(e) => return BotMessage::new(format!("Error getting watchers: {}", e)),
+        };
+        if watchers.is_empty() {
+            return BotMessage::new("Nothing is being watched".to_string());
+        }
+        let watchers: Vec<String> = watchers
+            .iter()
+            .map(|watcher| describe_watcher(watcher))
+            .collect();
+        BotMessage::new(watchers.join(" | "))
+    }
+
+    fn show_command(
+        &self,
+        trigger: &str,
+        page: usize,
+        message: &Message,
+    ) -> Result<BotMessage, ActionError> {
+        let command = match self.commands.get(trigger) {
+            Some(command) => command,
+            None => return Err(ActionError::CommandDoesNotExist),
+        };
+        let mut lines = vec![if command.is_alias {
+            format!("{} is an alias of {}", command.trigger, command.response)
+        } else {
+            format!("{}: {}", command.trigger, command.response)
+        }];
+        if command.actor.is_some() {
+            lines.push("built in".to_string());
+        } else {
+            // Commands added since startup only have placeholder times, so they come from the
+            // database.
+            match self.database.get_command(trigger) {
+                Ok(stored) => {
+                    lines.push(format!(
+                        "author: {}",
+                        if stored.author.is_empty() {
+                            "unknown"
+                        } else {
+                            stored.author.as_str()
+                        }
+                    ));
+                    lines.push(format!(
+                        "created {}, modified {}",
+                        format_time(stored.time_created),
+                        format_time(stored.time_modified)
+                    ));
+                }
+                Err(e) => lines.push(format!("Error getting command: {}", e)),
+            }
+        }
+        if let Some(cache) = &command.cache {
+            lines.push(format!("cache: {}", cache));
+        }
+        match self.database.get_command_states(trigger) {
+            Ok(states) if states.is_empty() => {}
+            Ok(states) => {
+                let states: Vec<String> = states
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, value))
+                    .collect();
+                lines.push(format!("state: {}", states.join(", ")));
+            }
+            Err(e) => lines.push(format!("Error getting state: {}", e)),
+        }
+        paginate(lines, page, message, &format!("!command show {}", trigger))
+    }
+
+    // Indexes start at 0, like !variable edit's.
+    fn show_variable(
+        &self,
+        name: &str,
+        index: Option<usize>,
+        page: usize,
+        message: &Message,
+    ) -> Result<BotMessage, ActionError> {
+        let variable = match self.database.get_variable(name) {
+            Ok(variable) => variable,
+            Err(_) => return Err(ActionError::VariableDoesNotExist),
+        };
+        let mut times = format!(
+            "created {}, modified {}",
+            format_time(variable.time_created),
+            format_time(variable.time_modified)
+        );
+        if let Some(time_expires) = variable.time_expires {
+            times += &format!(", expires {}", format_time(Timespec::new(time_expires, 0)));
+        }
+        let lines = match (&variable.value, index) {
+            (VariableValue::Text(_), Some(_)) => return Err(ActionError::VariableWrongType),
+            (VariableValue::Text(text), None) => vec![
+                format!(
+                    "{}: text, {} characters, {}",
+                    name,
+                    text.chars().count(),
+                    times
+                ),
+                text.clone(),
+            ],
+            (VariableValue::StringList(items), Some(index)) => match items.get(index) {
+                Some(item) => vec![format!(
+                    "{}[{}], added {}: {}",
+                    name,
+                    index,
+                    describe_item(item),
+                    item.value
+                )],
+                None => return Err(ActionError::VariableBadEditIndex),
+            },
+            (VariableValue::StringList(items), None) => {
+                let mut lines = vec![format!("{}: list, {} items, {}", name, items.len(), times)];
+                for (i, item) in items.iter().enumerate() {
+                    lines.push(format!("[{}] {}: {}", i, describe_item(item), item.value));
+                }
+                lines
+            }
+            (VariableValue::Map(_), Some(_)) => return Err(ActionError::VariableWrongType),
+            (VariableValue::Map(map), None) => {
+                let mut lines = vec![format!("{}: map, {} keys, {}", name, map.len(), times)];
+                for (key, value) in map.iter() {
+                    lines.push(format!("{}.{}: {}", name, key, value));
+                }
+                lines
+            }
+        };
+        paginate(lines, page, message, &format!("!variable show {}", name))
+    }
+
+    fn find_in_variable(
+        &self,
+        name: &str,
+        query: &str,
+        page: usize,
+        message: &Message,
+    ) -> Result<BotMessage, ActionError> {
+        let items = match self.database.get_variable(name) {
+            Ok(variable) => match variable.value {
+                VariableValue::StringList(items) => items,
+                _ => return Err(ActionError::VariableWrongType),
+            },
+            Err(_) => return Err(ActionError::VariableDoesNotExist),
+        };
+        let found = search::search(&items, query);
+        if found.is_empty() {
+            return Ok(BotMessage::new(format!("Nothing in {} matches", name)));
+        }
+        let lines: Vec<String> = found
+            .into_iter()
+            .map(|index| format!("[{}] {}", index, items[index].value))
+            .collect();
+        paginate(
+            lines,
+            page,
+            message,
+            &format!("!variable find {} {}", name, query),
+        )
+    }
+
+    fn show_user_variables(&self, user: &str) -> BotMessage {
+        match self.database.get_user_variables(user) {
+            Ok(variables) if variables.is_empty() => {
+                BotMessage::new(format!("{} has no variables", user))
+            }
+            Ok(variables) => {
+                let variables: Vec<String> = variables
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, value))
+                    .collect();
+                BotMessage::new(format!("{}: {}", user, variables.join(", ")))
+            }
+            Err(e) => BotMessage::new(format!("Error getting variables for {}: {}", user, e)),
+        }
+    }
+
     fn get_triggered_command(&self, text: &String) -> Option<&Command> {
         self.commands
             .iter()
@@ -250,177 +846,761 @@ impl Bot {
             return None;
         }
 
-        let triggered_command = self.get_triggered_command(&message.text);
-        let (response, action) = match triggered_command {
+        let triggered_command = self.get_triggered_command(&message.text).cloned();
+        let author = triggered_command
+            .as_ref()
+            .map(|command| command.author.clone())
+            .unwrap_or_default();
+        let (mut response, action) = match triggered_command {
             None => (None, None),
-            Some(command) => match self.process_command(command, message) {
-                Err(e) => match e {
-                    ActionError::None => (None, None),
-                    _ => (
-                        Some(BotMessage {
-                            text: format!("{:?}", e),
-                        }),
-                        None,
-                    ),
+            Some(command) => match self.cached_output(&command, message) {
+                Some(text) => (Some(BotMessage::new(text)), None),
+                None => match self.process_command(&command, message, false) {
+                    Err(e) => match e {
+                        ActionError::None => (None, None),
+                        _ => (Some(BotMessage::new(format!("{:?}", e))), None),
+                    },
+                    Ok((response, action)) => {
+                        self.cache_output(&command, message, &response);
+                        (Some(response), action)
+                    }
                 },
-                Ok((response, action)) => (Some(response), action),
             },
         };
 
+        let mut actions: Vec<Action> = action.into_iter().collect();
+        if let Some(response) = &mut response {
+            let script_actions: Vec<ScriptAction> = response.actions.drain(..).collect();
+            for script_action in script_actions {
+                match self.script_action(script_action, &author, message) {
+                    // Tasks from earlier in the same script aren't in the database yet.
+                    Ok(Action::ScheduleTask(task)) => {
+                        let pending = actions
+                            .iter()
+                            .filter(|action| match action {
+                                Action::ScheduleTask(other) => other.author == task.author,
+                                _ => false,
+                            })
+                            .count() as i64;
+                        match self.check_task_limit(&task.author, pending) {
+                            Some(e) => response.text += &format!(" ({:?})", e),
+                            None => actions.push(Action::ScheduleTask(task)),
+                        }
+                    }
+                    Ok(action) => actions.push(action),
+                    Err(e) => response.text += &format!(" ({:?})", e),
+                }
+            }
+        }
+
+        // Scripts write to the database themselves, so their watchers are run from here.
+        let mut watcher_output = Vec::new();
+        let variable_changes = match &response {
+            Some(response) => response.variable_changes.clone(),
+            None => Vec::new(),
+        };
+        for change in variable_changes {
+            if let Some(value) = change.new {
+                let variable = Variable::new(change.name, value);
+                watcher_output.extend(self.run_watchers(
+                    &variable,
+                    change.old.as_ref(),
+                    &message.sender,
+                ));
+            }
+        }
+
         // Deferred because it modifies self.commands,
         // but it'd be nice to propagate these error messages properly.
         // TODO: We could do the database bits first, then defer only adding to commands.
-        let event = match action {
-            None => None,
-            Some(action) => match action {
-                Action::AddCommand(command) => {
-                    if let Err(e) = self.database.add_command(&command) {
-                        println!("Error adding command {}: {}", command.trigger, e)
+        watcher_output.extend(self.apply_actions(actions, &message.sender));
+        for (target, text) in watcher_output {
+            self.send_to_target(&target, &text);
+        }
+
+        response
+    }
+
+    // Returns what the watchers of added and edited variables had to say, and where.
+    fn apply_actions(
+        &mut self,
+        actions: Vec<Action>,
+        sender: &User,
+    ) -> Vec<(MessageTarget, String)> {
+        let mut watcher_output = Vec::new();
+        for action in actions {
+            let old_value = match &action {
+                Action::AddVariable(variable) | Action::EditVariable(variable, _) => self
+                    .database
+                    .get_variable(&variable.name)
+                    .ok()
+                    .map(|variable| variable.value),
+                _ => None,
+            };
+            match self.apply_action(action, sender) {
+                None => {}
+                Some(event) => {
+                    if let BotEvent::AddVariable(variable, sender)
+                    | BotEvent::EditVariable(variable, sender) = &event
+                    {
+                        watcher_output.extend(self.run_watchers(
+                            variable,
+                            old_value.as_ref(),
+                            sender,
+                        ));
                     }
-                    self.commands.update_command(&command);
-                    Some(BotEvent::AddCommand(command, message.sender.clone()))
+                    self.sender.send(Event::BotEvent(event))
                 }
-                Action::EditCommand(command) => {
-                    if let Err(e) = self.database.update_command(&command) {
-                        println!("Error updating command {}: {}", command.trigger, e)
+            };
+        }
+        watcher_output
+    }
+
+    // Actions the watchers ask for are applied, but neither they nor the watchers' own writes
+    // set off watchers again, so they can't loop.
+    fn run_watchers(
+        &mut self,
+        variable: &Variable,
+        old_value: Option<&VariableValue>,
+        sender: &User,
+    ) -> Vec<(MessageTarget, String)> {
+        let watchers = match self.database.get_watchers(Some(&variable.name)) {
+            Ok(watchers) => watchers,
+            Err(e) => {
+                println!("Error getting watchers for {}: {}", variable.name, e);
+                return Vec::new();
+            }
+        };
+        let old_value = old_value.map(|value| value.to_string()).unwrap_or_default();
+        let new_value = variable.value.to_string();
+        let mut output = Vec::new();
+        for watcher in watchers {
+            let trigger = format!("!watch:{}", watcher.variable);
+            let mut command = Command::new(trigger, watcher.template.clone())
+                .with_author(watcher.author.clone())
+                .with_input("old", old_value.clone())
+                .with_input("new", new_value.clone())
+                .build();
+            command.database_path = self.database.path.clone();
+            // Runs like a scheduled command, as the author and towards the target.
+            let message = Message {
+                sender: sender.clone(),
+                text: command.trigger.clone(),
+                source: Source::Scheduled(watcher.target.clone(), watcher.author.clone()),
+            };
+            let mut response = command.respond_no_check(&message);
+            if !response.text.trim().is_empty() {
+                output.push((watcher.target.clone(), response.text.clone()));
+            }
+            for failure in response.script_failures.iter() {
+                self.send_script_failure(&message.source, failure);
+            }
+            for script_action in response.actions.drain(..) {
+                let action = match self.script_action(script_action, &watcher.author, &message) {
+                    Ok(action) => action,
+                    Err(e) => {
+                        println!("Watcher #{} failed: {:?}", watcher.id, e);
+                        continue;
                     }
-                    self.commands.update_command(&command);
-                    Some(BotEvent::EditCommand(command, message.sender.clone()))
+                };
+                if let Some(event) = self.apply_action(action, sender) {
+                    self.sender.send(Event::BotEvent(event));
                 }
-                Action::DeleteCommand(command) => {
-                    if let Err(e) = self.database.delete_command(&command) {
-                        println!("Error deleting command {}: {}", command.trigger, e)
-                    }
-                    self.commands.delete_command(&command);
-                    Some(BotEvent::DeleteCommand(command, message.sender.clone()))
+            }
+        }
+        output
+    }
+
+    fn cached_output(&mut self, command: &Command, message: &Message) -> Option<String> {
+        let settings = command.cache.as_ref()?;
+        let key = settings.key(
+            message.after_trigger(&command.trigger),
+            &message.sender_identity(),
+        );
+        self.output_cache.get(
+            &command.trigger,
+            &key,
+            settings,
+            &self.database,
+            Utc::now().timestamp(),
+        )
+    }
+
+    // Only the text is kept, so failed scripts and outputs that requested actions aren't cached.
+    fn cache_output(&mut self, command: &Command, message: &Message, response: &BotMessage) {
+        let settings = match &command.cache {
+            Some(settings) => settings,
+            None => return,
+        };
+        if !response.script_failures.is_empty() || !response.actions.is_empty() {
+            return;
+        }
+        let key = settings.key(
+            message.after_trigger(&command.trigger),
+            &message.sender_identity(),
+        );
+        self.output_cache.insert(
+            &command.trigger,
+            &key,
+            &response.text,
+            settings,
+            &self.database,
+            Utc::now().timestamp(),
+        );
+    }
+
+    // name.key only edits a key when name is a map. Otherwise the dot is part of the name, like it
+    // was before there were maps.
+    fn resolve_key_edit(
+        &self,
+        action: Action,
+        command: &Command,
+        message: &Message,
+    ) -> Result<Action, ActionError> {
+        let name = match &action {
+            Action::EditVariable(variable, EditType::SetKey(_))
+            | Action::EditVariable(variable, EditType::RemoveKey(_)) => &variable.name,
+            _ => return Ok(action),
+        };
+        match self.database.get_variable(name) {
+            Ok(Variable {
+                value: VariableValue::Map(_),
+                ..
+            }) => Ok(action),
+            _ => special_command::edit_variable_by_name(command, message),
+        }
+    }
+
+    // pending counts tasks that were accepted but haven't been added to the database yet.
+    fn check_task_limit(&self, author: &str, pending: i64) -> Option<ActionError> {
+        match self.database.count_scheduled_tasks(author) {
+            Ok(count) if count + pending >= MAX_SCHEDULED_TASKS => {
+                Some(ActionError::TooManyScheduledTasks)
+            }
+            _ => None,
+        }
+    }
+
+    // Scripts act on behalf of whoever triggered the command, with the same checks. The author
+    // is whoever wrote the script.
+    fn script_action(
+        &self,
+        script_action: ScriptAction,
+        author: &str,
+        message: &Message,
+    ) -> Result<Action, ActionError> {
+        let action = match script_action {
+            ScriptAction::SendMessage(target, text) => {
+                if !can_message_anywhere(author, message) {
+                    return Err(ActionError::PermissionDenied);
                 }
-                Action::AddVariable(variable) => {
-                    if let Err(e) = self.database.set_variable(&variable) {
-                        println!("Error adding variable {}: {}", variable.name, e)
-                    }
-                    Some(BotEvent::AddVariable(variable, message.sender.clone()))
-                }
-                Action::EditVariable(mut variable, edit_type) => {
-                    if edit_type != EditType::Overwrite() {
-                        let old_variable = match self.database.get_variable(&variable.name) {
-                            Ok(v) => Some(v),
-                            Err(e) => {
-                                println!("Error editing variable {}: {}", variable.name, e);
-                                None
-                            }
-                        };
-                        if let Some(old_variable) = old_variable {
-                            match edit_type {
-                                EditType::Append() => match (&variable.value, old_variable.value) {
-                                    (
-                                        VariableValue::Text(new_text),
-                                        VariableValue::Text(old_text),
-                                    ) => {
-                                        variable.value = VariableValue::Text(old_text + new_text);
+                Action::SendMessage(target, text)
+            }
+            ScriptAction::AddCommand(trigger, _) | ScriptAction::EditCommand(trigger, _)
+                if !trigger.starts_with('!') =>
+            {
+                return Err(ActionError::BadCommandTriggerPrefix)
+            }
+            ScriptAction::AddCommand(trigger, response) => Action::AddCommand(
+                Command::new(trigger, response)
+                    .with_author(message.sender_identity())
+                    .build(),
+            ),
+            ScriptAction::EditCommand(trigger, response) => Action::EditCommand(
+                Command::new(trigger, response)
+                    .with_author(message.sender_identity())
+                    .build(),
+            ),
+            ScriptAction::DeleteCommand(trigger) => {
+                Action::DeleteCommand(Command::new(trigger, "".to_string()))
+            }
+            ScriptAction::DeleteVariable(name) => {
+                Action::DeleteVariable(Variable::new(name, VariableValue::Text("".to_string())))
+            }
+            ScriptAction::SendLiveNotification => {
+                if !can_message_anywhere(author, message) {
+                    return Err(ActionError::PermissionDenied);
+                }
+                Action::SendLiveNotification
+            }
+            ScriptAction::SayAfter(seconds, text) => {
+                self.scheduled_task(&format!("{}s", seconds), text, false, message)?
+            }
+            // Any command can be scheduled, so it takes an author trusted with actions, whoever
+            // triggered the script.
+            ScriptAction::Schedule(when, command) => {
+                if ScriptTier::for_author(author, Some(&self.database)) < ScriptTier::Actions {
+                    return Err(ActionError::PermissionDenied);
+                }
+                self.scheduled_task(&when, command, true, message)?
+            }
+        };
+        match self.check_action(&action) {
+            None => Ok(action),
+            Some(e) => Err(e),
+        }
+    }
+
+    fn scheduled_task(
+        &self,
+        when: &str,
+        text: String,
+        is_command: bool,
+        message: &Message,
+    ) -> Result<Action, ActionError> {
+        let schedule =
+            Schedule::parse(when).map_err(|e| ActionError::BadSchedule(e.to_string()))?;
+        let time_due = schedule
+            .next_due(Utc::now().timestamp())
+            .ok_or_else(|| ActionError::BadSchedule(when.to_string()))?;
+        let target = message
+            .reply_target()
+            .ok_or(ActionError::MessageTargetNotFound)?;
+        Ok(Action::ScheduleTask(ScheduledTask {
+            id: 0,
+            time_due,
+            repeat: if schedule.repeats() {
+                when.to_string()
+            } else {
+                "".to_string()
+            },
+            target,
+            text,
+            is_command,
+            username: message.sender.username.clone(),
+            author: message.sender_identity(),
+        }))
+    }
+
+    fn apply_action(&mut self, action: Action, sender: &User) -> Option<BotEvent> {
+        match action {
+            Action::AddCommand(command) => {
+                if let Err(e) = self.database.add_command(&command) {
+                    println!("Error adding command {}: {}", command.trigger, e)
+                }
+                self.commands.update_command(&command);
+                Some(BotEvent::AddCommand(command, sender.clone()))
+            }
+            Action::EditCommand(mut command) => {
+                // Only the response and author change, the database keeps the rest.
+                if let Some(existing) = self.commands.get(&command.trigger) {
+                    command.id = existing.id;
+                    command.time_created = existing.time_created;
+                    command.limits = existing.limits.clone();
+                    command.cache = existing.cache.clone();
+                }
+                if let Err(e) = self.database.update_command(&command) {
+                    println!("Error updating command {}: {}", command.trigger, e)
+                }
+                self.output_cache.clear(
+                    Some(command.trigger.as_str()),
+                    &self.database,
+                    Utc::now().timestamp(),
+                );
+                self.commands.update_command(&command);
+                Some(BotEvent::EditCommand(command, sender.clone()))
+            }
+            Action::DeleteCommand(command) => {
+                if let Err(e) = self.database.delete_command(&command) {
+                    println!("Error deleting command {}: {}", command.trigger, e)
+                }
+                self.output_cache.clear(
+                    Some(command.trigger.as_str()),
+                    &self.database,
+                    Utc::now().timestamp(),
+                );
+                self.commands.delete_command(&command);
+                Some(BotEvent::DeleteCommand(command, sender.clone()))
+            }
+            Action::AddVariable(variable) => {
+                if let Err(e) = self.database.set_variable(&variable) {
+                    println!("Error adding variable {}: {}", variable.name, e)
+                }
+                Some(BotEvent::AddVariable(variable, sender.clone()))
+            }
+            Action::EditVariable(variable, EditType::Increment(by)) => {
+                self.increment_variable(&variable.name, by, sender)
+            }
+            Action::EditVariable(variable, EditType::Decrement(by)) => {
+                // check_action turns away amounts that can't be negated.
+                self.increment_variable(&variable.name, by.checked_neg()?, sender)
+            }
+            Action::EditVariable(variable, EditType::Reset()) => {
+                if let Err(e) = self.database.reset_variable(&variable.name) {
+                    println!("Error resetting variable {}: {}", variable.name, e)
+                }
+                self.edited_variable_event(&variable.name, sender)
+            }
+            Action::EditVariable(mut variable, edit_type) => {
+                if edit_type != EditType::Overwrite() {
+                    let old_variable = match self.database.get_variable(&variable.name) {
+                        Ok(v) => Some(v),
+                        Err(e) => {
+                            println!("Error editing variable {}: {}", variable.name, e);
+                            None
+                        }
+                    };
+                    if let Some(old_variable) = old_variable {
+                        match edit_type {
+                            EditType::Append() => match (&variable.value, old_variable.value) {
+                                (VariableValue::Text(new_text), VariableValue::Text(old_text)) => {
+                                    variable.value = VariableValue::Text(old_text + new_text);
+                                }
+                                (
+                                    VariableValue::StringList(new_list),
+                                    VariableValue::StringList(old_list),
+                                ) => {
+                                    let mut list = old_list.clone();
+                                    list.extend(new_list.clone());
+                                    variable.value = VariableValue::StringList(list);
+                                }
+                                (VariableValue::Map(new_map), VariableValue::Map(mut old_map)) => {
+                                    old_map.extend(new_map.clone());
+                                    variable.value = VariableValue::Map(old_map);
+                                }
+                                _ => {}
+                            },
+                            EditType::Remove() => match (&variable.value, old_variable.value) {
+                                (
+                                    VariableValue::Text(text_to_remove),
+                                    VariableValue::Text(old_text),
+                                ) => {
+                                    variable.value =
+                                        VariableValue::Text(old_text.replace(text_to_remove, ""));
+                                }
+                                (
+                                    VariableValue::StringList(new_list),
+                                    VariableValue::StringList(mut old_list),
+                                ) => {
+                                    for item_to_remove in new_list.iter() {
+                                        old_list.retain(|old_item| {
+                                            old_item.value != item_to_remove.value
+                                        });
                                     }
-                                    (
-                                        VariableValue::StringList(new_list),
-                                        VariableValue::StringList(old_list),
-                                    ) => {
-                                        let mut list = old_list.clone();
-                                        list.extend(new_list.clone());
-                                        variable.value = VariableValue::StringList(list);
+                                    variable.value = VariableValue::StringList(old_list);
+                                }
+                                (VariableValue::Map(new_map), VariableValue::Map(mut old_map)) => {
+                                    for key in new_map.keys() {
+                                        old_map.remove(key);
                                     }
-                                    _ => {}
-                                },
-                                EditType::Remove() => match (&variable.value, old_variable.value) {
+                                    variable.value = VariableValue::Map(old_map);
+                                }
+                                _ => {}
+                            },
+                            EditType::InsertAt(index) => {
+                                match (&variable.value, old_variable.value) {
                                     (
-                                        VariableValue::Text(text_to_remove),
-                                        VariableValue::Text(old_text),
+                                        VariableValue::Text(text_to_insert),
+                                        VariableValue::Text(mut old_text),
                                     ) => {
-                                        variable.value = VariableValue::Text(
-                                            old_text.replace(text_to_remove, ""),
-                                        );
+                                        let index = min(index, old_text.len());
+                                        old_text.insert_str(index, text_to_insert);
+                                        variable.value = VariableValue::Text(old_text);
                                     }
                                     (
                                         VariableValue::StringList(new_list),
                                         VariableValue::StringList(mut old_list),
                                     ) => {
-                                        for item_to_remove in new_list.iter() {
-                                            old_list.retain(|old_item| {
-                                                old_item.value != item_to_remove.value
-                                            });
+                                        let index = min(index, old_list.len());
+                                        for item_to_insert in new_list.iter().rev() {
+                                            old_list.insert(index, item_to_insert.clone());
                                         }
                                         variable.value = VariableValue::StringList(old_list);
                                     }
                                     _ => {}
-                                },
-                                EditType::InsertAt(index) => {
-                                    match (&variable.value, old_variable.value) {
-                                        (
-                                            VariableValue::Text(text_to_insert),
-                                            VariableValue::Text(mut old_text),
-                                        ) => {
-                                            let index = min(index, old_text.len());
-                                            old_text.insert_str(index, text_to_insert);
-                                            variable.value = VariableValue::Text(old_text);
-                                        }
-                                        (
-                                            VariableValue::StringList(new_list),
-                                            VariableValue::StringList(mut old_list),
-                                        ) => {
-                                            let index = min(index, old_list.len());
-                                            for item_to_insert in new_list.iter().rev() {
-                                                old_list.insert(index, item_to_insert.clone());
-                                            }
-                                            variable.value = VariableValue::StringList(old_list);
-                                        }
-                                        _ => {}
-                                    }
                                 }
-                                EditType::RemoveAt(index) => match old_variable.value {
-                                    VariableValue::Text(mut old_text) => {
-                                        let index = min(index, old_text.len());
-                                        old_text.remove(index);
-                                        variable.value = VariableValue::Text(old_text);
-                                    }
-                                    VariableValue::StringList(mut old_list) => {
-                                        let index = min(index, old_list.len());
-                                        old_list.remove(index);
-                                        variable.value = VariableValue::StringList(old_list);
-                                    }
-                                },
-                                _ => {}
                             }
+                            EditType::RemoveAt(index) => match old_variable.value {
+                                VariableValue::Text(mut old_text) => {
+                                    let index = min(index, old_text.len());
+                                    old_text.remove(index);
+                                    variable.value = VariableValue::Text(old_text);
+                                }
+                                VariableValue::StringList(mut old_list) => {
+                                    let index = min(index, old_list.len());
+                                    old_list.remove(index);
+                                    variable.value = VariableValue::StringList(old_list);
+                                }
+                                VariableValue::Map(_) => {}
+                            },
+                            EditType::SetKey(key) => {
+                                if let (VariableValue::Text(text), VariableValue::Map(mut map)) =
+                                    (&variable.value, old_variable.value)
+                                {
+                                    map.insert(key, text.clone());
+                                    variable.value = VariableValue::Map(map);
+                                }
+                            }
+                            EditType::RemoveKey(key) => {
+                                if let VariableValue::Map(mut map) = old_variable.value {
+                                    map.remove(&key);
+                                    variable.value = VariableValue::Map(map);
+                                }
+                            }
+                            _ => {}
                         }
-                    };
-                    if let Err(e) = self.database.set_variable(&variable) {
-                        println!("Error editing variable {}: {}", variable.name, e)
                     }
-                    Some(BotEvent::EditVariable(variable, message.sender.clone()))
+                };
+                if let Err(e) = self.database.set_variable(&variable) {
+                    println!("Error editing variable {}: {}", variable.name, e)
                 }
-                Action::DeleteVariable(variable) => {
-                    if let Err(e) = self.database.delete_variable(&variable) {
-                        println!("Error deleting variable {}: {}", variable.name, e)
-                    }
-                    Some(BotEvent::DeleteVariable(variable, message.sender.clone()))
+                Some(BotEvent::EditVariable(variable, sender.clone()))
+            }
+            Action::DeleteVariable(variable) => {
+                if let Err(e) = self.database.delete_variable(&variable) {
+                    println!("Error deleting variable {}: {}", variable.name, e)
                 }
-                Action::SendLiveNotification => {
-                    let (ctx, channel_id) = self.notification_channel.as_ref().unwrap();
-                    channel_id.send_message(
-                        ctx.lock().unwrap().http.clone(),
-                        |m| {
-                            m.content("Hey @everyone, Stovoy is now live! Come watch over at https://www.twitch.tv/stovoy !");
+                Some(BotEvent::DeleteVariable(variable, sender.clone()))
+            }
+            Action::SendLiveNotification => {
+                let (ctx, channel_id) = self.notification_channel.as_ref().unwrap();
+                channel_id.send_message(
+                    ctx.lock().unwrap().http.clone(),
+                    |m| {
+                        m.content("Hey @everyone, Stovoy is now live! Come watch over at https://www.twitch.tv/stovoy !");
 
-                            m
-                        },
-                    ).unwrap();
+                        m
+                    },
+                ).unwrap();
+                None
+            }
+            Action::SendMessage(target, text) => {
+                self.send_to_target(&target, &text);
+                None
+            }
+            Action::AddModule(module) => {
+                if let Err(e) = self.database.set_script_module(&module) {
+                    println!("Error adding module {}: {}", module.name, e)
+                }
+                Some(BotEvent::AddModule(module, sender.clone()))
+            }
+            Action::EditModule(module) => {
+                if let Err(e) = self.database.set_script_module(&module) {
+                    println!("Error editing module {}: {}", module.name, e)
+                }
+                Some(BotEvent::EditModule(module, sender.clone()))
+            }
+            Action::DeleteModule(module) => {
+                if let Err(e) = self.database.delete_script_module(&module) {
+                    println!("Error deleting module {}: {}", module.name, e)
+                }
+                Some(BotEvent::DeleteModule(module, sender.clone()))
+            }
+            Action::TestCommand(_, _) | Action::EvalScript(_) | Action::ListScheduledTasks => None,
+            Action::ShowUserVariables(_) | Action::ShowCommand(_, _) => None,
+            Action::ShowVariable(_, _, _) | Action::FindInVariable(_, _, _) => None,
+            Action::SetScriptTier(user, tier) => {
+                if let Err(e) = self.database.set_script_tier(&user, tier) {
+                    println!("Error setting the script tier for {}: {}", user, e)
+                }
+                None
+            }
+            Action::ClearUserVariables(user) => {
+                if let Err(e) = self.database.delete_user_variables(&user) {
+                    println!("Error clearing variables for {}: {}", user, e)
+                }
+                None
+            }
+            Action::ScheduleTask(task) => {
+                if let Err(e) = self.database.add_scheduled_task(&task) {
+                    println!("Error scheduling {}: {}", task.text, e)
+                }
+                None
+            }
+            Action::DeleteScheduledTask(id) => {
+                if let Err(e) = self.database.delete_scheduled_task(id) {
+                    println!("Error deleting scheduled task {}: {}", id, e)
+                }
+                None
+            }
+            Action::AddWatcher(watcher) => {
+                if let Err(e) = self.database.add_watcher(&watcher) {
+                    println!("Error adding a watcher for {}: {}", watcher.variable, e)
+                }
+                None
+            }
+            Action::DeleteWatcher(id) => {
+                if let Err(e) = self.database.delete_watcher(id) {
+                    println!("Error deleting watcher {}: {}", id, e)
+                }
+                None
+            }
+            Action::ListWatchers(_) => None,
+            Action::AllowHttpDomain(domain) => {
+                self.update_http_settings(|settings| {
+                    if !settings.allowed_domains.contains(&domain) {
+                        settings.allowed_domains.push(domain);
+                    }
+                });
+                None
+            }
+            Action::DisallowHttpDomain(domain) => {
+                self.update_http_settings(|settings| {
+                    settings
+                        .allowed_domains
+                        .retain(|allowed| *allowed != domain)
+                });
+                None
+            }
+            Action::SetHttpLimits(limits) => {
+                self.update_http_settings(|settings| {
+                    settings.timeout_ms = limits.timeout_ms;
+                    settings.max_response_bytes = limits.max_response_bytes;
+                    settings.cache_ttl_secs = limits.cache_ttl_secs;
+                });
+                None
+            }
+            Action::SetOutputCache(trigger, cache) => {
+                if let Err(e) = self.database.set_command_cache(&trigger, cache.as_ref()) {
+                    println!("Error setting the output cache for {}: {}", trigger, e)
+                }
+                self.output_cache.clear(
+                    Some(trigger.as_str()),
+                    &self.database,
+                    Utc::now().timestamp(),
+                );
+                match self.commands.get(&trigger) {
+                    None => None,
+                    Some(command) => {
+                        let mut command = command.clone();
+                        command.cache = cache;
+                        self.commands.update_command(&command);
+                        Some(BotEvent::EditCommand(command, sender.clone()))
+                    }
+                }
+            }
+            Action::ClearOutputCache(trigger) => {
+                self.output_cache
+                    .clear(trigger.as_deref(), &self.database, Utc::now().timestamp());
+                None
+            }
+            Action::SetScriptLimits(trigger, limits) => match trigger {
+                None => {
+                    if let Err(e) = self.database.set_script_limits(&limits) {
+                        println!("Error setting global script limits: {}", e)
+                    }
                     None
                 }
+                Some(trigger) => {
+                    if let Err(e) = self.database.set_command_limits(&trigger, &limits) {
+                        println!("Error setting script limits for {}: {}", trigger, e)
+                    }
+                    match self.commands.get(&trigger) {
+                        None => None,
+                        Some(command) => {
+                            let mut command = command.clone();
+                            command.limits = limits;
+                            self.commands.update_command(&command);
+                            Some(BotEvent::EditCommand(command, sender.clone()))
+                        }
+                    }
+                }
             },
-        };
+        }
+    }
 
-        match event {
-            None => {}
-            Some(event) => self.sender.send(Event::BotEvent(event)),
-        };
+    // In the database, so updates from scripts running at the same time aren't lost.
+    fn increment_variable(&self, name: &str, by: i64, sender: &User) -> Option<BotEvent> {
+        if let Err(e) = self.database.increment_variable(name, by) {
+            println!("Error incrementing variable {}: {}", name, e)
+        }
+        self.edited_variable_event(name, sender)
+    }
 
-        response
+    fn edited_variable_event(&self, name: &str, sender: &User) -> Option<BotEvent> {
+        match self.database.get_variable(name) {
+            Ok(variable) => Some(BotEvent::EditVariable(variable, sender.clone())),
+            Err(e) => {
+                println!("Error getting variable {}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    fn update_http_settings<F: FnOnce(&mut HttpSettings)>(&self, update: F) {
+        let result = self.database.get_http_settings().and_then(|mut settings| {
+            update(&mut settings);
+            self.database.set_http_settings(&settings)
+        });
+        if let Err(e) = result {
+            println!("Error updating http settings: {}", e)
+        }
+    }
+
+    fn send_to_target(&self, target: &MessageTarget, text: &str) {
+        match target {
+            MessageTarget::Twitch(channel) => {
+                if let Some(writer) = &self.twitch_writer {
+                    let source = Source::Twitch(writer.clone(), channel.to_lowercase());
+                    self.send_message(&source, text);
+                }
+            }
+            MessageTarget::Discord(channel) => {
+                if let Some((ctx, _)) = &self.notification_channel {
+                    let channel_id = match channel.parse::<u64>() {
+                        Ok(id) => Some(ChannelId(id)),
+                        Err(_) => self.find_discord_channel(ctx, channel),
+                    };
+                    match channel_id {
+                        Some(channel_id) => {
+                            let http = ctx.lock().unwrap().http.clone();
+                            if let Err(e) = channel_id.say(&http, text) {
+                                println!("Error sending message to {}: {:?}", channel, e);
+                            }
+                        }
+                        None => println!("Discord channel {} not found", channel),
+                    }
+                }
+            }
+            MessageTarget::Admin => println!("{}", text),
+        }
+    }
+
+    fn find_discord_channel(
+        &self,
+        ctx: &Arc<Mutex<DiscordContext>>,
+        name: &str,
+    ) -> Option<ChannelId> {
+        let ctx = ctx.lock().unwrap();
+        let name = name.trim_start_matches('#');
+        let guilds: Vec<GuildId> = ctx.cache.read().guilds.keys().cloned().collect();
+        for guild_id in guilds {
+            if let Ok(channels) = guild_id.channels(&ctx.http) {
+                for (channel_id, channel) in channels {
+                    if channel.name == name {
+                        return Some(channel_id);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // The full diagnostic goes privately to whoever last edited the command.
+    fn send_script_failure(&self, source: &Source, failure: &ScriptFailure) {
+        let text = format!(
+            "Script error in {}:\n{}",
+            failure.trigger,
+            failure.error.diagnostic()
+        );
+        if failure.author.starts_with("discord:") {
+            let ctx = match source {
+                Source::Discord(ctx, _) => Some(ctx),
+                _ => self.notification_channel.as_ref().map(|(ctx, _)| ctx),
+            };
+            let user_id = failure.author["discord:".len()..].parse::<u64>();
+            if let (Some(ctx), Ok(user_id)) = (ctx, user_id) {
+                let http = ctx.lock().unwrap().http.clone();
+                let result = UserId(user_id)
+                    .create_dm_channel(&http)
+                    .and_then(|channel| channel.say(&http, format!("```\n{}\n```", text)));
+                match result {
+                    Ok(_) => return,
+                    Err(e) => println!("Error sending script error to {}: {:?}", failure.author, e),
+                }
+            }
+        }
+        println!("{}", text);
     }
 
     fn send_message(&self, source: &Source, text: &str) {
@@ -453,6 +1633,7 @@ impl Bot {
             #[cfg(test)]
             Source::None => {}
             Source::Admin => println!("{}", text),
+            Source::Scheduled(target, _) => self.send_to_target(target, text),
             Source::Twitch(writer, channel) => match png {
                 Some(_) => {
                     let text = format!("{} (only works in discord)", text).to_string();
@@ -463,8 +1644,17 @@ impl Bot {
                 }
             },
             Source::Discord(ctx, msg) => {
+                let attached = text.chars().count() > DISCORD_MAX_LENGTH;
                 if let Err(e) = msg.channel_id.send_message(&ctx.lock().unwrap().http, |m| {
-                    m.content(text);
+                    if attached {
+                        m.content("That's too long for a message, so it's attached");
+                        m.add_file(DiscordAttachmentType::Bytes {
+                            data: std::borrow::Cow::Borrowed(text.as_bytes()),
+                            filename: "response.txt".to_string(),
+                        });
+                    } else {
+                        m.content(text);
+                    }
                     if let Some(ref png) = png {
                         m.embed(|e| {
                             e.image("attachment://waifu.png");
@@ -486,6 +1676,175 @@ impl Bot {
 
 pub struct BotMessage {
     pub text: String,
+    pub script_failures: Vec<ScriptFailure>,
+    pub actions: Vec<ScriptAction>,
+    // What its scripts wrote, for dry runs and watchers.
+    pub variable_changes: Vec<VariableChange>,
+}
+
+impl BotMessage {
+    pub fn new(text: String) -> BotMessage {
+        BotMessage {
+            text,
+            script_failures: Vec::new(),
+            actions: Vec::new(),
+            variable_changes: Vec::new(),
+        }
+    }
+}
+
+// Watchers and scheduled commands have nobody sending them, so they go by who wrote the script.
+fn can_message_anywhere(author: &str, message: &Message) -> bool {
+    match &message.source {
+        Source::Scheduled(_, _) => is_admin_identity(author),
+        _ => message.is_admin(),
+    }
+}
+
+fn format_time(time: Timespec) -> String {
+    Utc.timestamp(time.sec, 0)
+        .format("%Y-%m-%d %H:%M UTC")
+        .to_string()
+}
+
+// When, and who added it where, as far as that's known.
+fn describe_item(item: &StringItem) -> String {
+    let mut description = format_time(item.time_created);
+    if !item.added_by.is_empty() {
+        description += &format!(" by {}", item.added_by);
+    }
+    match &item.added_in {
+        Some(MessageTarget::Twitch(channel)) => description += &format!(" in twitch #{}", channel),
+        Some(MessageTarget::Discord(channel)) => description += &format!(" in discord {}", channel),
+        Some(MessageTarget::Admin) | None => {}
+    }
+    for (name, value) in item.fields.iter() {
+        description += &format!(", {}: {}", name, value);
+    }
+    description
+}
+
+// Splits long output into pages where the platform would cut it off, with a pointer to the
+// next page. Elsewhere it's sent whole, one line each.
+fn paginate(
+    lines: Vec<String>,
+    page: usize,
+    message: &Message,
+    command: &str,
+) -> Result<BotMessage, ActionError> {
+    let max_length = match message.max_length() {
+        None => return Ok(BotMessage::new(lines.join("\n"))),
+        // Room for the page number and the next command.
+        Some(max_length) => max(max_length.saturating_sub(command.len() + 40), 40),
+    };
+    let mut pages = vec![String::new()];
+    for line in lines {
+        let chars: Vec<char> = line.chars().collect();
+        for chunk in chars.chunks(max_length) {
+            let chunk: String = chunk.iter().collect();
+            let current = pages.last_mut().unwrap();
+            if current.is_empty() {
+                *current = chunk;
+            } else if current.chars().count() + 3 + chunk.chars().count() <= max_length {
+                *current += " | ";
+                *current += &chunk;
+            } else {
+                pages.push(chunk);
+            }
+        }
+    }
+    let count = pages.len();
+    if page == 0 || page > count {
+        return Err(ActionError::PageDoesNotExist);
+    }
+    let text = pages.swap_remove(page - 1);
+    if count == 1 {
+        Ok(BotMessage::new(text))
+    } else if page < count {
+        Ok(BotMessage::new(format!(
+            "{} (page {}/{}, next: {} page {})",
+            text,
+            page,
+            count,
+            command,
+            page + 1
+        )))
+    } else {
+        Ok(BotMessage::new(format!(
+            "{} (page {}/{})",
+            text, page, count
+        )))
+    }
+}
+
+fn describe_watcher(watcher: &VariableWatcher) -> String {
+    let target = match &watcher.target {
+        MessageTarget::Twitch(channel) => format!("twitch:{}", channel),
+        MessageTarget::Discord(channel) => format!("discord:{}", channel),
+        MessageTarget::Admin => "admin".to_string(),
+    };
+    format!(
+        "#{} {} to {}: {}",
+        watcher.id, watcher.variable, target, watcher.template
+    )
+}
+
+// For showing what a dry run would have done.
+fn describe_action(action: &Action) -> String {
+    match action {
+        Action::AddCommand(command) => format!("add command {}", command.trigger),
+        Action::EditCommand(command) => format!("edit command {}", command.trigger),
+        Action::DeleteCommand(command) => format!("delete command {}", command.trigger),
+        Action::AddVariable(variable) => format!("add variable {}", variable.name),
+        Action::EditVariable(variable, _) => format!("edit variable {}", variable.name),
+        Action::DeleteVariable(variable) => format!("delete variable {}", variable.name),
+        Action::SendLiveNotification => "send the live notification".to_string(),
+        Action::SendMessage(target, text) => format!("send {:?}: {}", target, text),
+        Action::AddModule(module) => format!("add module {}", module.name),
+        Action::EditModule(module) => format!("edit module {}", module.name),
+        Action::DeleteModule(module) => format!("delete module {}", module.name),
+        Action::SetScriptLimits(trigger, limits) => match trigger {
+            Some(trigger) => format!("set script limits for {} to {}", trigger, limits),
+            None => format!("set global script limits to {}", limits),
+        },
+        Action::TestCommand(text, _) => format!("test {}", text),
+        Action::EvalScript(script) => format!("eval {}", script),
+        Action::AllowHttpDomain(domain) => format!("allow http from {}", domain),
+        Action::DisallowHttpDomain(domain) => format!("disallow http from {}", domain),
+        Action::SetHttpLimits(limits) => format!("set http limits to {}", limits),
+        Action::ScheduleTask(task) if task.is_command => {
+            format!("schedule {} ({})", task.text, task.repeat)
+        }
+        Action::ScheduleTask(task) => format!("say {}", task.text),
+        Action::DeleteScheduledTask(id) => format!("delete scheduled task #{}", id),
+        Action::ListScheduledTasks => "list scheduled tasks".to_string(),
+        Action::ShowUserVariables(user) => format!("show variables for {}", user),
+        Action::ClearUserVariables(user) => format!("clear variables for {}", user),
+        Action::ShowCommand(trigger, _) => format!("show {}", trigger),
+        Action::ShowVariable(name, _, _) => format!("show variable {}", name),
+        Action::FindInVariable(name, query, _) => format!("search {} for {}", name, query),
+        Action::SetScriptTier(user, tier) => {
+            format!("set the script tier for {} to {}", user, tier)
+        }
+        Action::SetOutputCache(trigger, cache) => match cache {
+            Some(cache) => format!("cache the output of {} with {}", trigger, cache),
+            None => format!("stop caching the output of {}", trigger),
+        },
+        Action::ClearOutputCache(trigger) => match trigger {
+            Some(trigger) => format!("clear the cached output of {}", trigger),
+            None => "clear all cached output".to_string(),
+        },
+        Action::AddWatcher(watcher) => format!("watch {}", watcher.variable),
+        Action::DeleteWatcher(id) => format!("delete watcher #{}", id),
+        Action::ListWatchers(_) => "list watchers".to_string(),
+    }
+}
+
+// A script error, along with the command it came from so the author can be told about it.
+pub struct ScriptFailure {
+    pub trigger: String,
+    pub author: String,
+    pub error: ScriptError,
 }
 
 impl Message {
@@ -500,6 +1859,54 @@ impl Message {
         }
     }
 
+    // The CLI and the broadcaster.
+    pub fn is_admin(&self) -> bool {
+        match &self.source {
+            #[cfg(test)]
+            Source::None => false,
+            Source::Admin => true,
+            Source::Twitch(_, _) => self.sender.username == "stovoy",
+            Source::Discord(_, _) => false,
+            Source::Scheduled(_, _) => false,
+        }
+    }
+
+    // A name for the sender that stays the same across renames where the platform allows it.
+    pub fn sender_identity(&self) -> String {
+        match &self.source {
+            #[cfg(test)]
+            Source::None => format!("test:{}", self.sender.username),
+            Source::Admin => "admin".to_string(),
+            Source::Twitch(_, _) => format!("twitch:{}", self.sender.username.to_lowercase()),
+            Source::Discord(_, msg) => format!("discord:{}", msg.author.id),
+            Source::Scheduled(_, author) => author.clone(),
+        }
+    }
+
+    // Where longer responses get cut off. Discord gets longer responses as a file instead.
+    pub fn max_length(&self) -> Option<usize> {
+        match &self.source {
+            #[cfg(test)]
+            Source::None => Some(TWITCH_MAX_LENGTH),
+            Source::Admin | Source::Discord(_, _) => None,
+            Source::Twitch(_, _) => Some(TWITCH_MAX_LENGTH),
+            Source::Scheduled(MessageTarget::Twitch(_), _) => Some(TWITCH_MAX_LENGTH),
+            Source::Scheduled(_, _) => None,
+        }
+    }
+
+    // Where a reply to this message goes, for replies that come later.
+    pub fn reply_target(&self) -> Option<MessageTarget> {
+        match &self.source {
+            #[cfg(test)]
+            Source::None => None,
+            Source::Admin => Some(MessageTarget::Admin),
+            Source::Twitch(_, channel) => Some(MessageTarget::Twitch(channel.clone())),
+            Source::Discord(_, msg) => Some(MessageTarget::Discord(msg.channel_id.to_string())),
+            Source::Scheduled(target, _) => Some(target.clone()),
+        }
+    }
+
     pub fn after_trigger(&self, trigger: &str) -> &str {
         if trigger.len() + 1 > self.text.len() {
             ""
@@ -509,3 +1916,323 @@ impl Message {
         }
     }
 }
+
+#[test]
+fn test_paginate() {
+    let message = Message::new("!variable show list".to_string());
+    let short = paginate(vec!["a".to_string(), "b".to_string()], 1, &message, "!x").unwrap();
+    assert_eq!(short.text, "a | b");
+
+    let lines: Vec<String> = (0..100).map(|i| format!("line {}", i)).collect();
+    let first = paginate(lines.clone(), 1, &message, "!x").unwrap().text;
+    assert!(first.starts_with("line 0 | line 1 | "));
+    assert!(first.ends_with(", next: !x page 2)"));
+    assert!(first.chars().count() <= TWITCH_MAX_LENGTH);
+    let second = paginate(lines.clone(), 2, &message, "!x").unwrap().text;
+    assert!(!second.contains("line 0 "));
+    assert!(paginate(lines, 100, &message, "!x").is_err());
+
+    // A line longer than a page is split rather than cut off.
+    let long = "x".repeat(TWITCH_MAX_LENGTH * 2);
+    let first = paginate(vec![long.clone()], 1, &message, "!x")
+        .unwrap()
+        .text;
+    let second = paginate(vec![long.clone()], 2, &message, "!x")
+        .unwrap()
+        .text;
+    let third = paginate(vec![long], 3, &message, "!x").unwrap().text;
+    let count = |text: &str| {
+        let (text, _) = text.split_at(text.find(" (page").unwrap_or(text.len()));
+        text.chars().filter(|c| *c == 'x').count()
+    };
+    assert_eq!(
+        count(&first) + count(&second) + count(&third),
+        TWITCH_MAX_LENGTH * 2
+    );
+}
+
+#[cfg(test)]
+fn test_bot(database: Database) -> Bot {
+    let (_, sender) = crate::EventBus::new();
+    let (_, event_rx) = crossbeam::channel::unbounded();
+    Bot::with_database(sender, event_rx, database).unwrap()
+}
+
+#[test]
+fn test_scheduled_task_limit() {
+    crate::database::with_test_db(|connection| {
+        let script = "say_after(60, \"hi\"); ".repeat(MAX_SCHEDULED_TASKS as usize + 2);
+        connection.add_command(
+            &Command::new("!spam".to_string(), format!("{{{{{}\"ok\"}}}}", script))
+                .with_author("admin".to_string())
+                .build(),
+        )?;
+        let mut bot = test_bot(connection);
+        let message = Message {
+            sender: User {
+                username: "foo".to_string(),
+            },
+            text: "!spam".to_string(),
+            source: Source::Admin,
+        };
+        let response = bot.respond(&message).unwrap();
+        assert!(response.text.starts_with("ok (TooManyScheduledTasks)"));
+        assert_eq!(
+            bot.database.count_scheduled_tasks("admin")?,
+            MAX_SCHEDULED_TASKS
+        );
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_edit_command_keeps_settings() {
+    crate::database::with_test_db(|connection| {
+        let mut command = Command::new("!hi".to_string(), "hi".to_string());
+        command.limits.max_operations = Some(1000);
+        command.cache = Some(CacheSettings::parse("ttl=60").unwrap());
+        connection.add_command(&command)?;
+        connection.set_command_limits("!hi", &command.limits)?;
+        connection.set_command_cache("!hi", command.cache.as_ref())?;
+        let mut bot = test_bot(connection);
+        let message = Message {
+            sender: User {
+                username: "foo".to_string(),
+            },
+            text: "!command edit !hi hello".to_string(),
+            source: Source::Admin,
+        };
+        bot.respond(&message);
+        let edited = bot.commands.get("!hi").unwrap();
+        assert_eq!(edited.response, "hello");
+        assert_eq!(edited.limits, command.limits);
+        assert_eq!(edited.cache, command.cache);
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_decrement_limits() {
+    crate::database::with_test_db(|connection| {
+        connection.set_variable(&Variable::new(
+            "deaths".to_string(),
+            VariableValue::Text("5".to_string()),
+        ))?;
+        let mut bot = test_bot(connection);
+        let message = Message {
+            sender: User {
+                username: "foo".to_string(),
+            },
+            text: "!variable edit deaths-- -9223372036854775808".to_string(),
+            source: Source::Admin,
+        };
+        let response = bot.respond(&message).unwrap();
+        assert!(response.text.contains("BadVariable"));
+        let message = Message {
+            text: "!variable edit deaths-- 2".to_string(),
+            ..message
+        };
+        bot.respond(&message);
+        match bot.database.get_variable("deaths")?.value {
+            VariableValue::Text(text) => assert_eq!(text, "3"),
+            _ => panic!("Expected Text"),
+        }
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_variable_watchers() {
+    crate::database::with_test_db(|connection| {
+        connection.add_watcher(&VariableWatcher {
+            id: 0,
+            variable: "goal".to_string(),
+            template: "Goal $old -> $new{{set(\"goal\", \"10\"); \"\"}}".to_string(),
+            target: MessageTarget::Discord("general".to_string()),
+            author: "admin".to_string(),
+        })?;
+        let mut bot = test_bot(connection);
+        let user = User {
+            username: "foo".to_string(),
+        };
+        let goal =
+            |value: &str| Variable::new("goal".to_string(), VariableValue::Text(value.to_string()));
+        let said = |text: &str| {
+            vec![(
+                MessageTarget::Discord("general".to_string()),
+                text.to_string(),
+            )]
+        };
+
+        let output = bot.apply_actions(vec![Action::AddVariable(goal("5"))], &user);
+        assert_eq!(output, said("Goal  -> 5"));
+        // The watcher's own write doesn't set it off again.
+        let output = bot.apply_actions(
+            vec![Action::EditVariable(goal("12"), EditType::Overwrite())],
+            &user,
+        );
+        assert_eq!(output, said("Goal 10 -> 12"));
+
+        // Values are only ever text, not part of the template.
+        let value = "{{set(\"other\", \"1\")}} $old";
+        let output = bot.apply_actions(
+            vec![Action::EditVariable(goal(value), EditType::Overwrite())],
+            &user,
+        );
+        assert_eq!(output, said(&format!("Goal 10 -> {}", value)));
+        assert!(bot.database.get_variable("other").is_err());
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_watchers_see_script_writes() {
+    crate::database::with_test_db(|connection| {
+        connection.add_command(
+            &Command::new(
+                "!death".to_string(),
+                "{{increment(\"deaths\")}}".to_string(),
+            )
+            .with_author("admin".to_string())
+            .build(),
+        )?;
+        connection.add_watcher(&VariableWatcher {
+            id: 0,
+            variable: "deaths".to_string(),
+            template: "{{set(\"seen\", input(\"old\") + \" -> \" + input(\"new\")); \"\"}}"
+                .to_string(),
+            target: MessageTarget::Admin,
+            author: "admin".to_string(),
+        })?;
+        let mut bot = test_bot(connection);
+        let message = Message {
+            sender: User {
+                username: "foo".to_string(),
+            },
+            text: "!death".to_string(),
+            source: Source::Admin,
+        };
+        bot.respond(&message);
+        bot.respond(&message);
+        assert_eq!(
+            bot.database.get_variable("seen")?.value,
+            VariableValue::Text("1 -> 2".to_string())
+        );
+
+        // Watchers run as their author, who may be an admin.
+        let message = Message {
+            text: "!watch:deaths".to_string(),
+            source: Source::Scheduled(MessageTarget::Admin, "admin".to_string()),
+            ..message
+        };
+        let send = || ScriptAction::SendMessage(MessageTarget::Admin, "hi".to_string());
+        assert!(bot.script_action(send(), "admin", &message).is_ok());
+        assert!(bot.script_action(send(), "twitch:foo", &message).is_err());
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_dotted_variable_names() {
+    crate::database::with_test_db(|connection| {
+        let text = |value: &str| VariableValue::Text(value.to_string());
+        connection.set_variable(&Variable::new("a".to_string(), text("1")))?;
+        connection.set_variable(&Variable::new("a.b".to_string(), text("2")))?;
+        connection.set_variable(&Variable::new(
+            "socials".to_string(),
+            VariableValue::Map(BTreeMap::new()),
+        ))?;
+        let mut bot = test_bot(connection);
+        let admin_message = |text: &str| Message {
+            sender: User {
+                username: "foo".to_string(),
+            },
+            text: text.to_string(),
+            source: Source::Admin,
+        };
+
+        bot.respond(&admin_message("!variable edit a.b 3"));
+        assert_eq!(bot.database.get_variable("a.b")?.value, text("3"));
+        assert_eq!(bot.database.get_variable("a")?.value, text("1"));
+
+        bot.respond(&admin_message("!variable edit socials.twitter @stovoy"));
+        let mut map = BTreeMap::new();
+        map.insert("twitter".to_string(), "@stovoy".to_string());
+        assert_eq!(
+            bot.database.get_variable("socials")?.value,
+            VariableValue::Map(map)
+        );
+
+        bot.respond(&admin_message("!variable delete a.b"));
+        assert!(bot.database.get_variable("a.b").is_err());
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_missed_tasks() {
+    crate::database::with_test_db(|connection| {
+        let task = ScheduledTask {
+            id: 0,
+            time_due: 1000,
+            repeat: "".to_string(),
+            target: MessageTarget::Twitch("stovoy".to_string()),
+            text: "hi".to_string(),
+            is_command: false,
+            username: "foo".to_string(),
+            author: "admin".to_string(),
+        };
+        connection.add_scheduled_task(&task)?;
+        connection.add_scheduled_task(&ScheduledTask {
+            repeat: "every 10m".to_string(),
+            ..task
+        })?;
+        let mut bot = test_bot(connection);
+        // Twitch isn't connected, so they wait for it for a while.
+        bot.run_due_tasks(1000 + MISSED_TASK_SECS);
+        let tasks = bot.database.get_scheduled_tasks()?;
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks.iter().all(|task| task.time_due == 1000));
+        // Then the one-off is dropped and the repeating one waits for its next run.
+        bot.run_due_tasks(1000 + MISSED_TASK_SECS + 1);
+        let tasks = bot.database.get_scheduled_tasks()?;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].repeat, "every 10m");
+        assert!(tasks[0].time_due > 1000 + MISSED_TASK_SECS);
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_schedule_from_author() {
+    crate::database::with_test_db(|connection| {
+        connection.add_command(
+            &Command::new(
+                "!later".to_string(),
+                "{{schedule(\"30s\", \"!hi\"); \"ok\"}}".to_string(),
+            )
+            .with_author("admin".to_string())
+            .build(),
+        )?;
+        let mut bot = test_bot(connection);
+        // Not an admin, but the command's author is.
+        let message = Message {
+            sender: User {
+                username: "foo".to_string(),
+            },
+            text: "!later".to_string(),
+            source: Source::Scheduled(MessageTarget::Admin, "twitch:foo".to_string()),
+        };
+        assert_eq!(bot.respond(&message).unwrap().text, "ok");
+        assert_eq!(bot.database.count_scheduled_tasks("twitch:foo")?, 1);
+        Ok(())
+    })
+    .unwrap();
+}
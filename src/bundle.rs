@@ -0,0 +1,383 @@
+use crate::database::Database;
+use crate::models::{Command, Variable};
+use rusqlite::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::io;
+
+// Bumped when older versions of the bot can't read a bundle anymore.
+pub const BUNDLE_VERSION: u32 = 1;
+
+// Commands (aliases included) and variables, for moving them between bots with
+// `stovbot export <file>` and `stovbot import <file>`. Written as TOML when the file ends in
+// .toml, JSON otherwise. Built in commands are left out, every bot already has them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    #[serde(default)]
+    pub commands: Vec<Command>,
+    #[serde(default)]
+    pub variables: Vec<Variable>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStrategy {
+    // Only adds what doesn't exist yet.
+    Merge,
+    // Also replaces what does.
+    Overwrite,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub added: Vec<String>,
+    pub replaced: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum BundleError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    TomlRead(toml::de::Error),
+    TomlWrite(toml::ser::Error),
+    Database(Error),
+    UnsupportedVersion(u32),
+    BadStrategy(String),
+    UnknownChatBot(String),
+    BadCsv(String),
+}
+
+impl Display for BundleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            BundleError::Io(e) => write!(f, "Can't access the file: {}", e),
+            BundleError::Json(e) => write!(f, "Bad JSON: {}", e),
+            BundleError::TomlRead(e) => write!(f, "Bad TOML: {}", e),
+            BundleError::TomlWrite(e) => write!(f, "Can't write TOML: {}", e),
+            BundleError::Database(e) => write!(f, "Database error: {}", e),
+            BundleError::UnsupportedVersion(version) => write!(
+                f,
+                "Bundle version {} is newer than this bot supports ({})",
+                version, BUNDLE_VERSION
+            ),
+            BundleError::BadStrategy(strategy) => write!(
+                f,
+                "Unknown import strategy {}, use merge or overwrite",
+                strategy
+            ),
+            BundleError::UnknownChatBot(name) => write!(
+                f,
+                "Can't import from {}, use nightbot, streamelements or streamlabs",
+                name
+            ),
+            BundleError::BadCsv(e) => write!(f, "Bad CSV: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for BundleError {
+    fn from(e: io::Error) -> BundleError {
+        BundleError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for BundleError {
+    fn from(e: serde_json::Error) -> BundleError {
+        BundleError::Json(e)
+    }
+}
+
+impl From<toml::de::Error> for BundleError {
+    fn from(e: toml::de::Error) -> BundleError {
+        BundleError::TomlRead(e)
+    }
+}
+
+impl From<toml::ser::Error> for BundleError {
+    fn from(e: toml::ser::Error) -> BundleError {
+        BundleError::TomlWrite(e)
+    }
+}
+
+impl From<Error> for BundleError {
+    fn from(e: Error) -> BundleError {
+        BundleError::Database(e)
+    }
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Format {
+        if path.to_lowercase().ends_with(".toml") {
+            Format::Toml
+        } else {
+            Format::Json
+        }
+    }
+}
+
+impl ImportStrategy {
+    pub fn parse(text: &str) -> Result<ImportStrategy, BundleError> {
+        match text {
+            "merge" => Ok(ImportStrategy::Merge),
+            "overwrite" => Ok(ImportStrategy::Overwrite),
+            _ => Err(BundleError::BadStrategy(text.to_string())),
+        }
+    }
+}
+
+impl Bundle {
+    pub fn export(database: &Database) -> Result<Bundle, Error> {
+        let mut commands: Vec<Command> = database
+            .get_commands()?
+            .into_iter()
+            .filter(|command| !is_default_command(&command.trigger))
+            .collect();
+        commands.sort_by(|a, b| a.trigger.cmp(&b.trigger));
+        let mut variables = database.get_variables()?;
+        variables.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Bundle {
+            version: BUNDLE_VERSION,
+            commands,
+            variables,
+        })
+    }
+
+    pub fn parse(text: &str, format: Format) -> Result<Bundle, BundleError> {
+        let bundle: Bundle = match format {
+            Format::Json => serde_json::from_str(text)?,
+            Format::Toml => toml::from_str(text)?,
+        };
+        if bundle.version > BUNDLE_VERSION {
+            return Err(BundleError::UnsupportedVersion(bundle.version));
+        }
+        Ok(bundle)
+    }
+
+    pub fn to_string(&self, format: Format) -> Result<String, BundleError> {
+        match format {
+            Format::Json => Ok(serde_json::to_string_pretty(self)?),
+            // Through a toml::Value, which puts tables after plain values like TOML needs.
+            Format::Toml => Ok(toml::to_string_pretty(&toml::Value::try_from(self)?)?),
+        }
+    }
+
+    pub fn read(path: &str) -> Result<Bundle, BundleError> {
+        Bundle::parse(&fs::read_to_string(path)?, Format::from_path(path))
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), BundleError> {
+        fs::write(path, self.to_string(Format::from_path(path))?)?;
+        Ok(())
+    }
+
+    // All or nothing: a dry run, or an import that fails partway, is rolled back.
+    pub fn import(
+        &self,
+        database: &Database,
+        strategy: ImportStrategy,
+        dry_run: bool,
+    ) -> Result<ImportReport, Error> {
+        database.begin()?;
+        let result = self.import_all(database, strategy);
+        match result {
+            Ok(_) if !dry_run => database.commit()?,
+            _ => database.rollback()?,
+        }
+        result
+    }
+
+    fn import_all(
+        &self,
+        database: &Database,
+        strategy: ImportStrategy,
+    ) -> Result<ImportReport, Error> {
+        let mut report = ImportReport::default();
+        for command in self.commands.iter() {
+            let label = format!("command {}", command.trigger);
+            if is_default_command(&command.trigger) {
+                report.skipped.push(label);
+                continue;
+            }
+            match (exists(database.get_command(&command.trigger))?, strategy) {
+                (false, _) => {
+                    database.add_command(command)?;
+                    report.added.push(label);
+                }
+                (true, ImportStrategy::Overwrite) => {
+                    database.update_command(command)?;
+                    report.replaced.push(label);
+                }
+                (true, ImportStrategy::Merge) => {
+                    report.skipped.push(label);
+                    continue;
+                }
+            }
+            database.set_command_limits(&command.trigger, &command.limits)?;
+            database.set_command_cache(&command.trigger, command.cache.as_ref())?;
+        }
+        for variable in self.variables.iter() {
+            let label = format!("variable {}", variable.name);
+            match (exists(database.get_variable(&variable.name))?, strategy) {
+                (false, _) => report.added.push(label),
+                (true, ImportStrategy::Overwrite) => report.replaced.push(label),
+                (true, ImportStrategy::Merge) => {
+                    report.skipped.push(label);
+                    continue;
+                }
+            }
+            database.set_variable(variable)?;
+        }
+        Ok(report)
+    }
+}
+
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "{} added, {} replaced, {} skipped",
+            self.added.len(),
+            self.replaced.len(),
+            self.skipped.len()
+        )?;
+        for (verb, labels) in [
+            ("Added", &self.added),
+            ("Replaced", &self.replaced),
+            ("Skipped", &self.skipped),
+        ]
+        .iter()
+        {
+            for label in labels.iter() {
+                write!(f, "\n{} {}", verb, label)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_default_command(trigger: &str) -> bool {
+    Command::default_commands()
+        .iter()
+        .any(|command| command.trigger == trigger)
+}
+
+fn exists<T>(result: Result<T, Error>) -> Result<bool, Error> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(Error::QueryReturnedNoRows) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+use crate::models::{ScriptLimits, StringItem, VariableValue};
+
+#[cfg(test)]
+fn fill(database: &Database) -> Result<(), Error> {
+    let mut command = Command::new("!hello".to_string(), "Hi $user".to_string())
+        .with_author("twitch:stovoy".to_string())
+        .build();
+    command.limits = ScriptLimits::parse("operations=5000").unwrap();
+    database.add_command(&command)?;
+    database.set_command_limits(&command.trigger, &command.limits)?;
+    database.add_command(&Command::new_alias("!hi".to_string(), "!hello".to_string()).build())?;
+    database.set_variable(&Variable::new(
+        "goal".to_string(),
+        VariableValue::Text("100 subs".to_string()),
+    ))?;
+    let mut quote = StringItem::new("I love dragons");
+    quote.added_by = "foo".to_string();
+    quote
+        .fields
+        .insert("game".to_string(), "Skyrim".to_string());
+    database.set_variable(&Variable::new(
+        "quotes".to_string(),
+        VariableValue::StringList(vec![quote]),
+    ))?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn test_round_trip(format: Format) -> Result<(), Error> {
+    let source = Database::new_in_memory()?;
+    fill(&source)?;
+    let bundle = Bundle::export(&source)?;
+    assert_eq!(bundle.commands.len(), 2);
+    assert_eq!(bundle.variables.len(), 2);
+
+    let text = bundle.to_string(format).unwrap();
+    let bundle = Bundle::parse(&text, format).unwrap();
+    let target = Database::new_in_memory()?;
+    let report = bundle.import(&target, ImportStrategy::Merge, false)?;
+    assert_eq!(report.added.len(), 4);
+
+    let command = target.get_command("!hello")?;
+    assert_eq!(command.response, "Hi $user");
+    assert_eq!(command.author, "twitch:stovoy");
+    assert_eq!(command.limits, source.get_command("!hello")?.limits);
+    assert!(target.get_command("!hi")?.is_alias);
+    assert_eq!(
+        target.get_variable("quotes")?.value,
+        source.get_variable("quotes")?.value
+    );
+    assert_eq!(
+        target.get_variable("goal")?.value,
+        VariableValue::Text("100 subs".to_string())
+    );
+    Ok(())
+}
+
+#[test]
+fn test_json_round_trip() -> Result<(), Error> {
+    test_round_trip(Format::Json)
+}
+
+#[test]
+fn test_toml_round_trip() -> Result<(), Error> {
+    test_round_trip(Format::Toml)
+}
+
+#[test]
+fn test_import_strategies() -> Result<(), Error> {
+    let source = Database::new_in_memory()?;
+    fill(&source)?;
+    let bundle = Bundle::export(&source)?;
+
+    let target = Database::new_in_memory()?;
+    target.set_variable(&Variable::new(
+        "goal".to_string(),
+        VariableValue::Text("50 subs".to_string()),
+    ))?;
+    let goal = || target.get_variable("goal").map(|variable| variable.value);
+
+    let report = bundle.import(&target, ImportStrategy::Overwrite, true)?;
+    assert_eq!(report.replaced, vec!["variable goal".to_string()]);
+    assert_eq!(goal()?, VariableValue::Text("50 subs".to_string()));
+    assert!(target.get_command("!hello").is_err());
+
+    let report = bundle.import(&target, ImportStrategy::Merge, false)?;
+    assert_eq!(report.added.len(), 3);
+    assert_eq!(report.skipped, vec!["variable goal".to_string()]);
+    assert_eq!(goal()?, VariableValue::Text("50 subs".to_string()));
+
+    let report = bundle.import(&target, ImportStrategy::Overwrite, false)?;
+    assert_eq!(report.replaced.len(), 4);
+    assert_eq!(goal()?, VariableValue::Text("100 subs".to_string()));
+
+    let mut future = Bundle::export(&source)?;
+    future.version = BUNDLE_VERSION + 1;
+    let text = future.to_string(Format::Json).unwrap();
+    match Bundle::parse(&text, Format::Json) {
+        Err(BundleError::UnsupportedVersion(_)) => {}
+        _ => panic!("Expected UnsupportedVersion"),
+    }
+    Ok(())
+}
@@ -0,0 +1,589 @@
+use crate::bundle::{Bundle, BundleError, BUNDLE_VERSION};
+use crate::models::{Command, ScriptTier, Variable, VariableValue};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// Command exports from other chat bots, for `stovbot import --from <bot> <file>`:
+//     nightbot         JSON, the commands list from its API
+//     streamelements   JSON, the commands list from its API
+//     streamlabs       CSV, from Streamlabs Chatbot's command export
+// Their variables are translated into templates, and counters into StovBot counter variables.
+// Anything else is kept as is and reported, so it can be fixed by hand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatBot {
+    Nightbot,
+    StreamElements,
+    Streamlabs,
+}
+
+// What the other bot's export turned into, and what didn't make it across.
+pub struct Translation {
+    pub bundle: Bundle,
+    pub problems: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Export<T> {
+    Wrapped { commands: Vec<T> },
+    Bare(Vec<T>),
+}
+
+#[derive(Deserialize)]
+struct NightbotCommand {
+    name: String,
+    message: String,
+    #[serde(default)]
+    count: i64,
+    #[serde(default, rename = "userLevel")]
+    user_level: String,
+    // Fields StovBot doesn't keep, like coolDown.
+    #[serde(flatten)]
+    ignored: BTreeMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct StreamElementsCommand {
+    command: String,
+    reply: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    #[serde(default = "enabled")]
+    enabled: bool,
+    #[serde(default, rename = "accessLevel")]
+    access_level: i64,
+    #[serde(default)]
+    count: i64,
+    // Fields StovBot doesn't keep, like cooldown and cost.
+    #[serde(flatten)]
+    ignored: BTreeMap<String, Value>,
+}
+
+// StreamElements' access level for everyone.
+const STREAMELEMENTS_EVERYONE: i64 = 100;
+
+// Fields that say nothing about what a command does, so they aren't reported as ignored.
+const METADATA_FIELDS: &[&str] = &["_id", "id", "channel", "createdAt", "updatedAt"];
+
+fn enabled() -> bool {
+    true
+}
+
+impl ChatBot {
+    pub fn parse(text: &str) -> Result<ChatBot, BundleError> {
+        match text.to_lowercase().as_ref() {
+            "nightbot" => Ok(ChatBot::Nightbot),
+            "streamelements" => Ok(ChatBot::StreamElements),
+            "streamlabs" => Ok(ChatBot::Streamlabs),
+            _ => Err(BundleError::UnknownChatBot(text.to_string())),
+        }
+    }
+}
+
+fn commands<T>(export: Export<T>) -> Vec<T> {
+    match export {
+        Export::Wrapped { commands } | Export::Bare(commands) => commands,
+    }
+}
+
+pub fn translate(text: &str, chat_bot: ChatBot) -> Result<Translation, BundleError> {
+    let mut translator = Translator::new(chat_bot);
+    match chat_bot {
+        ChatBot::Nightbot => {
+            for command in commands(serde_json::from_str::<Export<NightbotCommand>>(text)?) {
+                if !command.user_level.is_empty() && command.user_level != "everyone" {
+                    translator.problem(
+                        &command.name,
+                        &format!("was only for {}, anyone can use it now", command.user_level),
+                    );
+                }
+                translator.ignored_fields(&command.name, &command.ignored);
+                translator.add_command(&command.name, &command.message, command.count);
+            }
+        }
+        ChatBot::StreamElements => {
+            let export = serde_json::from_str::<Export<StreamElementsCommand>>(text)?;
+            for command in commands(export) {
+                if !command.enabled {
+                    translator.problem(&command.command, "was disabled, so it wasn't imported");
+                    continue;
+                }
+                if command.access_level > STREAMELEMENTS_EVERYONE {
+                    translator.problem(
+                        &command.command,
+                        &format!(
+                            "needed access level {}, anyone can use it now",
+                            command.access_level
+                        ),
+                    );
+                }
+                translator.ignored_fields(&command.command, &command.ignored);
+                let trigger =
+                    translator.add_command(&command.command, &command.reply, command.count);
+                if let Some(trigger) = trigger {
+                    for alias in command.aliases.iter() {
+                        translator.add_alias(alias, &trigger);
+                    }
+                }
+            }
+        }
+        ChatBot::Streamlabs => {
+            let rows = parse_csv(text).map_err(BundleError::BadCsv)?;
+            let mut rows = rows.into_iter();
+            let header: Vec<String> = match rows.next() {
+                Some(header) => header.iter().map(|name| name.trim().to_string()).collect(),
+                None => Vec::new(),
+            };
+            let column = |name: &str| {
+                header
+                    .iter()
+                    .position(|column| column.eq_ignore_ascii_case(name))
+            };
+            let (trigger_column, response_column) = match (column("command"), column("response")) {
+                (Some(trigger), Some(response)) => (trigger, response),
+                _ => {
+                    return Err(BundleError::BadCsv(
+                        "Needs Command and Response columns".to_string(),
+                    ))
+                }
+            };
+            let permission_column = column("permission");
+            let count_column = column("count");
+            let enabled_column = column("enabled");
+            // The other columns are reported when they're set, like Cooldown.
+            let kept_columns = [
+                Some(trigger_column),
+                Some(response_column),
+                permission_column,
+                count_column,
+                enabled_column,
+            ];
+            let ignored_columns: Vec<usize> = (0..header.len())
+                .filter(|index| !kept_columns.contains(&Some(*index)))
+                .collect();
+            for row in rows {
+                let cell = |index: Option<usize>| {
+                    index
+                        .and_then(|index| row.get(index))
+                        .map(|cell| cell.trim())
+                        .unwrap_or("")
+                };
+                let trigger = cell(Some(trigger_column));
+                if trigger.is_empty() {
+                    continue;
+                }
+                if cell(enabled_column).eq_ignore_ascii_case("false") {
+                    translator.problem(trigger, "was disabled, so it wasn't imported");
+                    continue;
+                }
+                let permission = cell(permission_column);
+                if !permission.is_empty() && !permission.eq_ignore_ascii_case("everyone") {
+                    translator.problem(
+                        trigger,
+                        &format!("was only for {}, anyone can use it now", permission),
+                    );
+                }
+                for index in ignored_columns.iter() {
+                    let value = cell(Some(*index));
+                    if !value.is_empty() && value != "0" {
+                        translator.problem(
+                            trigger,
+                            &format!("{} {} was ignored", header[*index], value),
+                        );
+                    }
+                }
+                let count = cell(count_column).parse::<i64>().unwrap_or(0);
+                translator.add_command(trigger, cell(Some(response_column)), count);
+            }
+        }
+    }
+    Ok(translator.finish())
+}
+
+struct Translator {
+    chat_bot: ChatBot,
+    commands: Vec<Command>,
+    // Counters the templates use, with the count to start them at.
+    counters: BTreeMap<String, i64>,
+    // Counters the command being translated uses, and whether it adds to them.
+    used_counters: Vec<(String, bool)>,
+    problems: Vec<String>,
+}
+
+impl Translator {
+    fn new(chat_bot: ChatBot) -> Translator {
+        Translator {
+            chat_bot,
+            commands: Vec::new(),
+            counters: BTreeMap::new(),
+            used_counters: Vec::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    fn problem(&mut self, trigger: &str, problem: &str) {
+        self.problems
+            .push(format!("{}: {}", to_trigger(trigger), problem));
+    }
+
+    fn ignored_fields(&mut self, trigger: &str, fields: &BTreeMap<String, Value>) {
+        for (field, value) in fields.iter() {
+            if METADATA_FIELDS.contains(&field.as_str()) || is_unset(field, value) {
+                continue;
+            }
+            let value = match value {
+                Value::String(text) => text.clone(),
+                value => value.to_string(),
+            };
+            self.problem(trigger, &format!("{} {} was ignored", field, value));
+        }
+    }
+
+    // Returns the trigger it was added under, if it was added.
+    fn add_command(&mut self, trigger: &str, response: &str, count: i64) -> Option<String> {
+        let trigger = to_trigger(trigger);
+        if trigger.contains(char::is_whitespace) {
+            self.problem(&trigger, "has a space in it, so it wasn't imported");
+            return None;
+        }
+        // Their text is never run as a script.
+        if response.contains("{{") {
+            self.problem(
+                &trigger,
+                "has {{ in it, which starts a script here, so it wasn't imported",
+            );
+            return None;
+        }
+        let counter = format!("count_{}", &trigger[1..]);
+        self.used_counters.clear();
+        let response = self.translate_template(&trigger, response, &counter);
+        // The other bot only kept how often the command was used, which is what its counters
+        // were at if nothing else added to them.
+        for (name, adds) in self.used_counters.iter() {
+            if *adds {
+                let start = self.counters.entry(name.clone()).or_insert(0);
+                *start = (*start).max(count);
+            }
+        }
+        // Imported commands have no author to take a tier from.
+        let tier = if !self.used_counters.is_empty() {
+            ScriptTier::VariableWrite
+        } else {
+            ScriptTier::ReadOnly
+        };
+        self.commands.push(
+            Command::new(trigger.clone(), response)
+                .with_tier(tier)
+                .build(),
+        );
+        Some(trigger)
+    }
+
+    fn add_alias(&mut self, alias: &str, trigger: &str) {
+        self.commands
+            .push(Command::new_alias(to_trigger(alias), trigger.to_string()));
+    }
+
+    fn finish(self) -> Translation {
+        let variables = self
+            .counters
+            .into_iter()
+            .map(|(name, count)| Variable::new(name, VariableValue::Text(count.to_string())))
+            .collect();
+        Translation {
+            bundle: Bundle {
+                version: BUNDLE_VERSION,
+                commands: self.commands,
+                variables,
+            },
+            problems: self.problems,
+        }
+    }
+
+    fn translate_template(&mut self, trigger: &str, template: &str, counter: &str) -> String {
+        let mut translated = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('$') {
+            translated += &rest[..start];
+            rest = &rest[start..];
+            let (variable, name, args) = match self.chat_bot {
+                ChatBot::Nightbot => bracketed(rest, '(', ')'),
+                ChatBot::StreamElements => bracketed(rest, '{', '}'),
+                ChatBot::Streamlabs => named(rest),
+            }
+            .unwrap_or(("$", "", ""));
+            rest = &rest[variable.len()..];
+            if name.is_empty() {
+                translated += variable;
+                continue;
+            }
+            match self.translate_variable(name, args, counter) {
+                Some(template) => translated += &template,
+                None => {
+                    self.problem(trigger, &format!("{} was not translated", variable));
+                    translated += variable;
+                }
+            }
+        }
+        translated + rest
+    }
+
+    // The StovBot template for one of their variables, if there is one.
+    fn translate_variable(&mut self, name: &str, args: &str, counter: &str) -> Option<String> {
+        let name = name.to_lowercase();
+        let args = args.trim();
+        if args.contains('$') {
+            return None;
+        }
+        let template = match (name.as_ref(), args) {
+            ("user", "") | ("user.name", "") | ("sender", "") | ("username", "") => {
+                "$user".to_string()
+            }
+            ("touser", "") | ("target", "") | ("targetname", "") | ("1", "") => "$1".to_string(),
+            ("2", "") => "$2".to_string(),
+            ("query", "") | ("1:", "") | ("msg", "") => "$text".to_string(),
+            ("count", "") => self.counter(counter, 1),
+            ("count", name) if is_counter_name(name) => self.counter(name, 1),
+            ("getcount", name) if is_counter_name(name) => self.counter(name, 0),
+            ("randnum", range) => random_int(range, ',')?,
+            (name, "") if name.starts_with("random.") => random_int(&name["random.".len()..], '-')?,
+            _ => return None,
+        };
+        Some(template)
+    }
+
+    fn counter(&mut self, name: &str, by: i64) -> String {
+        self.counters.entry(name.to_string()).or_insert(0);
+        self.used_counters.push((name.to_string(), by != 0));
+        if by == 1 {
+            format!("{{{{increment(\"{}\")}}}}", name)
+        } else {
+            // Creates the counter if it's missing, without changing it.
+            format!("{{{{increment(\"{}\", 0)}}}}", name)
+        }
+    }
+}
+
+fn to_trigger(name: &str) -> String {
+    let name = name.trim();
+    if name.starts_with('!') {
+        name.to_string()
+    } else {
+        format!("!{}", name)
+    }
+}
+
+// Values that leave a command as it would be without the field.
+fn is_unset(field: &str, value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        // StreamElements' enabledOnline and enabledOffline, which are on unless limited.
+        Value::Bool(set) => !set || field.starts_with("enabled"),
+        Value::Number(number) => number.as_f64() == Some(0.0),
+        Value::String(text) => text.is_empty() || (field == "type" && text == "say"),
+        Value::Array(values) => values.is_empty(),
+        Value::Object(values) => values.is_empty(),
+    }
+}
+
+fn is_counter_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// "1,100" or "1-100" as a script picking a number between them.
+fn random_int(range: &str, separator: char) -> Option<String> {
+    let mut bounds = range.splitn(2, separator);
+    let low = bounds.next()?.trim().parse::<i64>().ok()?;
+    let high = bounds.next()?.trim().parse::<i64>().ok()?;
+    Some(format!("{{{{random_int({}, {})}}}}", low, high))
+}
+
+// Nightbot's $(name args) and StreamElements' ${name args}, where the args can hold more
+// variables. Returns the whole variable, its name and its args.
+fn bracketed(text: &str, open: char, close: char) -> Option<(&str, &str, &str)> {
+    if !text[1..].starts_with(open) {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, c) in text.char_indices().skip(1) {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                let inner = text[2..i].trim();
+                let (name, args) = match inner.find(char::is_whitespace) {
+                    Some(space) => (&inner[..space], &inner[space..]),
+                    None => (inner, ""),
+                };
+                return Some((&text[..=i], name, args));
+            }
+        }
+    }
+    None
+}
+
+// Streamlabs Chatbot's $name and $name(args).
+fn named(text: &str) -> Option<(&str, &str, &str)> {
+    let end = text[1..]
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .map_or(text.len(), |i| i + 1);
+    let name = &text[1..end];
+    // Leaves things like "$5" alone.
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    if text[end..].starts_with('(') {
+        if let Some(close) = text[end..].find(')') {
+            let close = end + close;
+            return Some((&text[..=close], name, &text[end + 1..close]));
+        }
+    }
+    Some((&text[..end], name, ""))
+}
+
+// Rows of cells. Cells can be quoted, with "" for a quote inside them.
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if cell.is_empty() => in_quotes = true,
+            (',', false) => row.push(std::mem::replace(&mut cell, String::new())),
+            ('\r', false) => {}
+            ('\n', false) => {
+                row.push(std::mem::replace(&mut cell, String::new()));
+                if row.iter().any(|cell| !cell.is_empty()) {
+                    rows.push(row);
+                }
+                row = Vec::new();
+            }
+            (c, _) => cell.push(c),
+        }
+    }
+    if in_quotes {
+        return Err("A quoted cell never ends".to_string());
+    }
+    row.push(cell);
+    if row.iter().any(|cell| !cell.is_empty()) {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[test]
+fn test_nightbot() {
+    let export = r#"{"commands": [
+        {"_id": "1", "name": "!hug", "message": "$(user) hugs $(touser)!", "count": 0, "userLevel": "everyone", "coolDown": 30},
+        {"name": "!deaths", "message": "Died $(count) times", "count": 41, "userLevel": "everyone"},
+        {"name": "!so", "message": "Go follow $(touser) at $(twitch $(touser) \"{{url}}\")", "userLevel": "moderator"}
+    ]}"#;
+    let translation = translate(export, ChatBot::Nightbot).unwrap();
+    let commands = translation.bundle.commands;
+    assert_eq!(commands.len(), 2);
+    assert_eq!(commands[0].trigger, "!hug");
+    assert_eq!(commands[0].response, "$user hugs $1!");
+    assert_eq!(
+        commands[1].response,
+        "Died {{increment(\"count_deaths\")}} times"
+    );
+    assert_eq!(commands[0].tier, Some(ScriptTier::ReadOnly));
+    assert_eq!(commands[1].tier, Some(ScriptTier::VariableWrite));
+    assert_eq!(translation.bundle.variables.len(), 1);
+    assert_eq!(translation.bundle.variables[0].name, "count_deaths");
+    assert_eq!(
+        translation.bundle.variables[0].value,
+        VariableValue::Text("41".to_string())
+    );
+    assert_eq!(
+        translation.problems,
+        vec![
+            "!hug: coolDown 30 was ignored",
+            "!so: was only for moderator, anyone can use it now",
+            "!so: has {{ in it, which starts a script here, so it wasn't imported",
+        ]
+    );
+}
+
+#[test]
+fn test_streamelements() {
+    let export = r#"[
+        {"command": "dice", "reply": "${sender} rolled ${random.1-6}", "aliases": ["roll"]},
+        {"command": "death", "reply": "${count deaths} deaths, ${1:}", "accessLevel": 500, "count": 7, "cooldown": {"global": 5, "user": 15}},
+        {"command": "old", "reply": "gone", "enabled": false}
+    ]"#;
+    let translation = translate(export, ChatBot::StreamElements).unwrap();
+    let commands = translation.bundle.commands;
+    assert_eq!(commands.len(), 3);
+    assert_eq!(commands[0].trigger, "!dice");
+    assert_eq!(commands[0].response, "$user rolled {{random_int(1, 6)}}");
+    assert!(commands[1].is_alias);
+    assert_eq!(commands[1].trigger, "!roll");
+    assert_eq!(commands[1].response, "!dice");
+    assert_eq!(
+        commands[2].response,
+        "{{increment(\"deaths\")}} deaths, $text"
+    );
+    assert_eq!(commands[2].tier, Some(ScriptTier::VariableWrite));
+    assert_eq!(translation.bundle.variables[0].name, "deaths");
+    assert_eq!(
+        translation.bundle.variables[0].value,
+        VariableValue::Text("7".to_string())
+    );
+    assert_eq!(
+        translation.problems,
+        vec![
+            "!death: needed access level 500, anyone can use it now",
+            "!death: cooldown {\"global\":5,\"user\":15} was ignored",
+            "!old: was disabled, so it wasn't imported",
+        ]
+    );
+}
+
+#[test]
+fn test_streamlabs() {
+    let export = "\u{feff}Command,Permission,Info,Group,Response,Cooldown,Count,Enabled\r\n\
+                  !lurk,Everyone,,,\"$username is lurking, \"\"bye\"\"\",5,0,True\r\n\
+                  !pick,Moderator,,,Picked $randnum(1,10) for $target in $mychannel,5,3,True\r\n\
+                  !off,Everyone,,,nope,5,0,False\r\n";
+    let translation = translate(export, ChatBot::Streamlabs).unwrap();
+    let commands = translation.bundle.commands;
+    assert_eq!(commands.len(), 2);
+    assert_eq!(commands[0].response, "$user is lurking, \"bye\"");
+    assert_eq!(
+        commands[1].response,
+        "Picked {{random_int(1, 10)}} for $1 in $mychannel"
+    );
+    assert_eq!(
+        translation.problems,
+        vec![
+            "!lurk: Cooldown 5 was ignored",
+            "!pick: was only for Moderator, anyone can use it now",
+            "!pick: Cooldown 5 was ignored",
+            "!pick: $mychannel was not translated",
+            "!off: was disabled, so it wasn't imported",
+        ]
+    );
+    assert!(translate("Name,Text\n", ChatBot::Streamlabs).is_err());
+}
+
+#[test]
+fn test_parse_csv() {
+    assert_eq!(
+        parse_csv("a,\"b,c\",\"d\"\"e\"\n\n1,,3").unwrap(),
+        vec![vec!["a", "b,c", "d\"e"], vec!["1", "", "3"]]
+    );
+    assert!(parse_csv("a,\"b").is_err());
+}
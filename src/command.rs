@@ -1,14 +1,23 @@
-use crate::bot::BotMessage;
-use crate::models::{Command, Message};
+use crate::bot::{BotMessage, ScriptFailure};
+use crate::database::Database;
+use crate::dice;
+use crate::models::{Command, Message, ScriptTier};
 use crate::script_runner;
+use crate::script_runner::ScriptContext;
 use logos::Logos;
 use std::collections::hash_map::Values;
 use std::collections::HashMap;
+use std::path::Path;
 
 #[cfg(test)]
 use crate::database;
 #[cfg(test)]
-use crate::models::{StringItem, Variable, VariableValue};
+use crate::models::{
+    HttpSettings, MessageTarget, ScriptAction, ScriptErrorKind, ScriptLimits, ScriptModule,
+    StringItem, Variable, VariableValue,
+};
+#[cfg(test)]
+use std::collections::BTreeMap;
 
 #[derive(Logos, Debug, PartialEq)]
 enum Token {
@@ -33,6 +42,18 @@ enum Token {
     #[token = "$text"]
     Text,
 
+    #[token = "$roll"]
+    Roll,
+
+    #[token = "$old"]
+    Old,
+
+    #[token = "$new"]
+    New,
+
+    #[regex = "\\$\\{uservar:[a-zA-Z0-9_]+\\}"]
+    UserVariable,
+
     #[token = "{{"]
     ScriptStart,
 
@@ -51,7 +72,7 @@ pub trait CommandExt {
     #[cfg(test)]
     fn respond(&self, message: &Message) -> Option<BotMessage>;
     fn respond_no_check(&self, message: &Message) -> BotMessage;
-    fn parse(&self, message: &Message) -> String;
+    fn parse(&self, message: &Message) -> BotMessage;
 }
 
 impl CommandExt for Command {
@@ -68,40 +89,59 @@ impl CommandExt for Command {
     }
 
     fn respond_no_check(&self, message: &Message) -> BotMessage {
-        let response = self.parse(&message);
-        BotMessage { text: response }
+        self.parse(&message)
     }
 
-    fn parse(&self, message: &Message) -> String {
+    fn parse(&self, message: &Message) -> BotMessage {
         let text = message.after_trigger(&self.trigger);
         let mut args = text.split(' ');
         let mut lexer = Token::lexer(self.response.as_str());
         let mut response = "".to_string();
         let mut script = "".to_string();
         let mut in_script = false;
+        let mut bot_message = BotMessage::new("".to_string());
+        let mut database = None;
         let mut accumulator = &mut response;
         loop {
             match lexer.token {
                 Token::ArgOne => {
-                    *accumulator += match args.nth(0) {
-                        Some(text) => text,
-                        None => "",
-                    };
+                    let arg = args.nth(0).unwrap_or("");
+                    *accumulator += &escape_in_script(arg, in_script);
                 }
                 Token::ArgTwo => {
-                    *accumulator += match args.nth(1) {
-                        Some(text) => text,
-                        None => "",
-                    };
+                    let arg = args.nth(1).unwrap_or("");
+                    *accumulator += &escape_in_script(arg, in_script);
                 }
                 Token::User => {
-                    *accumulator += &message.sender.username;
+                    *accumulator += &escape_in_script(&message.sender.username, in_script);
                 }
                 Token::Year => {
                     *accumulator += "YEAR";
                 }
                 Token::Text => {
-                    *accumulator += text;
+                    *accumulator += &escape_in_script(text, in_script);
+                }
+                Token::Roll => {
+                    let notation = if text.trim().is_empty() { "1d6" } else { text };
+                    *accumulator += &match dice::roll(notation) {
+                        Ok(roll) => roll.to_string(),
+                        Err(e) => e.to_string(),
+                    };
+                }
+                Token::Old | Token::New => {
+                    let name = &lexer.slice()[1..];
+                    match self.inputs.get(name) {
+                        // Scripts read the value instead, so it never becomes part of the code.
+                        Some(_) if in_script => *accumulator += &format!("input(\"{}\")", name),
+                        Some(value) => *accumulator += value,
+                        None => *accumulator += lexer.slice(),
+                    }
+                }
+                Token::UserVariable => {
+                    let slice = lexer.slice();
+                    let name = &slice["${uservar:".len()..slice.len() - 1];
+                    let value = user_variable(shared_database(&mut database, self), message, name);
+                    *accumulator += &escape_in_script(&value, in_script);
                 }
                 Token::ScriptStart => {
                     if in_script {
@@ -114,9 +154,15 @@ impl CommandExt for Command {
                 }
                 Token::ScriptEnd => {
                     if in_script {
-                        let script_result = &script_runner::run(&script, &self.database_path);
+                        let script_result = run_script(
+                            self,
+                            message,
+                            &script,
+                            shared_database(&mut database, self),
+                            &mut bot_message,
+                        );
                         accumulator = &mut response;
-                        *accumulator += script_result;
+                        *accumulator += &script_result;
                         in_script = false;
                     } else {
                         *accumulator += lexer.slice();
@@ -125,9 +171,15 @@ impl CommandExt for Command {
                 Token::ScriptEndAndExtra => {
                     if in_script {
                         *accumulator += "}";
-                        let script_result = &script_runner::run(&script, &self.database_path);
+                        let script_result = run_script(
+                            self,
+                            message,
+                            &script,
+                            shared_database(&mut database, self),
+                            &mut bot_message,
+                        );
                         accumulator = &mut response;
-                        *accumulator += script_result;
+                        *accumulator += &script_result;
                         in_script = false;
                     } else {
                         *accumulator += lexer.slice();
@@ -152,7 +204,82 @@ impl CommandExt for Command {
             *accumulator += &script;
         }
 
-        response
+        bot_message.text = response;
+        bot_message
+    }
+}
+
+// What chat sends goes inside "string literals" in scripts, so it mustn't be able to end them.
+fn escape_in_script(text: &str, in_script: bool) -> String {
+    if in_script {
+        text.replace('\\', "\\\\").replace('"', "\\\"")
+    } else {
+        text.to_string()
+    }
+}
+
+// Empty when the user never set it.
+fn user_variable(database: Option<&Database>, message: &Message, name: &str) -> String {
+    match database {
+        Some(database) => database
+            .get_user_variable(&message.sender_identity(), name)
+            .unwrap_or_default(),
+        None => "".to_string(),
+    }
+}
+
+// Doesn't create a database just to find nothing in it.
+fn open_database(command: &Command) -> Option<Database> {
+    let path = &command.database_path;
+    if *path != Database::memory_path() && !Path::new(path).exists() {
+        return None;
+    }
+    Database::connect(Some(path.clone())).ok()
+}
+
+// Opened the first time a response needs it, then kept for the rest of the response.
+fn shared_database<'a>(
+    database: &'a mut Option<Option<Database>>,
+    command: &Command,
+) -> Option<&'a Database> {
+    database
+        .get_or_insert_with(|| open_database(command))
+        .as_ref()
+}
+
+// Errors show a short message inline and are kept so the bot can tell the author.
+fn run_script(
+    command: &Command,
+    message: &Message,
+    script: &str,
+    database: Option<&Database>,
+    bot_message: &mut BotMessage,
+) -> String {
+    let output = script_runner::run(
+        script,
+        &ScriptContext {
+            database_path: &command.database_path,
+            limits: &command.limits,
+            dry_run: command.dry_run,
+            user: &message.sender_identity(),
+            command: &command.trigger,
+            tier: ScriptTier::for_command(command, database),
+            inputs: &command.inputs,
+        },
+    );
+    bot_message.actions.extend(output.actions);
+    bot_message.variable_changes.extend(output.variable_changes);
+    match output.result {
+        Ok(result) => result,
+        Err(error) => {
+            let short = error.short();
+            bot_message.script_failures.push(ScriptFailure {
+                trigger: command.trigger.clone(),
+                author: command.author.clone(),
+                error,
+            });
+            short
+        }
     }
 }
 
@@ -175,6 +302,10 @@ impl Commands {
         self.commands.values()
     }
 
+    pub fn get(&self, trigger: &str) -> Option<&Command> {
+        self.commands.get(trigger)
+    }
+
     pub fn contains(&self, command: &Command) -> bool {
         self.commands.contains_key(&command.trigger)
     }
@@ -231,6 +362,35 @@ fn test_args_command() {
     );
 }
 
+#[test]
+fn test_script_args_stay_in_strings() {
+    let command = Command::new("!echo".to_string(), "{{\"$1 $text\"}}".to_string());
+    let response = command
+        .respond(&Message::new("!echo a\"b\\c \" + \"x".to_string()))
+        .unwrap();
+    assert_eq!(response.text, "a\"b\\c a\"b\\c \" + \"x");
+}
+
+#[test]
+fn test_inputs() {
+    let command = Command::new(
+        "!watch:goal".to_string(),
+        "$old -> $new, doubled {{int($new) * 2}}".to_string(),
+    )
+    .with_input("old", "{{\"x\"}} $new".to_string())
+    .with_input("new", "5".to_string())
+    .build();
+    let response = command
+        .respond(&Message::new("!watch:goal".to_string()))
+        .unwrap();
+    assert_eq!(response.text, "{{\"x\"}} $new -> 5, doubled 10");
+
+    // Without inputs they're left as they are.
+    let command = Command::new("!hi".to_string(), "$old {{\"$new\"}}".to_string());
+    let response = command.respond(&Message::new("!hi".to_string())).unwrap();
+    assert_eq!(response.text, "$old $new");
+}
+
 #[test]
 fn test_simple_script_command() {
     let command = Command::new(
@@ -322,6 +482,178 @@ fn test_infinite_loop() {
     assert!(response.contains("Timeout"));
 }
 
+#[test]
+fn test_script_errors() {
+    let command = Command::new(
+        "!broken".to_string(),
+        "Result: {{let x = 1;\nlet y = ;}}".to_string(),
+    );
+    let response = command
+        .respond(&Message::new("!broken".to_string()))
+        .unwrap();
+    assert!(response.text.starts_with("Result: Script Error (Parse"));
+    assert_eq!(response.script_failures.len(), 1);
+    let failure = &response.script_failures[0];
+    assert_eq!(failure.trigger, "!broken");
+    assert_eq!(failure.error.kind, ScriptErrorKind::Parse);
+    assert_eq!(failure.error.line, Some(2));
+    assert_eq!(failure.error.snippet, Some("let y = ;".to_string()));
+    assert!(failure.error.diagnostic().contains("let y = ;"));
+}
+
+#[test]
+fn test_script_runtime_error() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        let command = Command::new(
+            "!missing".to_string(),
+            "{{get(\"does_not_exist\")}}".to_string(),
+        )
+        .with_database_path(connection.path)
+        .build();
+        let response = command
+            .respond(&Message::new("!missing".to_string()))
+            .unwrap();
+        assert_eq!(
+            response.text,
+            "Script Error (Runtime): Variable does_not_exist does not exist!"
+        );
+        assert_eq!(
+            response.script_failures[0].error.kind,
+            ScriptErrorKind::Runtime
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_script_limits() {
+    let mut command = Command::new(
+        "!busy".to_string(),
+        "{{let i = 0; while i < 1000 { i += 1 } i}}".to_string(),
+    );
+    assert_eq!(
+        command
+            .respond(&Message::new("!busy".to_string()))
+            .unwrap()
+            .text,
+        "1000"
+    );
+    command.limits = ScriptLimits::parse("operations=100").unwrap();
+    let response = command.respond(&Message::new("!busy".to_string())).unwrap();
+    assert!(response
+        .text
+        .starts_with("Script Error (TooManyOperations)"));
+    assert_eq!(
+        response.script_failures[0].error.kind,
+        ScriptErrorKind::TooManyOperations
+    );
+
+    let mut command = Command::new("!long".to_string(), "{{pad(\"\", 50) + \"!\"}}".to_string());
+    command.limits = ScriptLimits::parse("output=20").unwrap();
+    let response = command.respond(&Message::new("!long".to_string())).unwrap();
+    assert_eq!(
+        response.script_failures[0].error.kind,
+        ScriptErrorKind::OutputTooLarge
+    );
+    command.limits = ScriptLimits::parse("strings=20").unwrap();
+    let response = command.respond(&Message::new("!long".to_string())).unwrap();
+    assert_eq!(
+        response.script_failures[0].error.kind,
+        ScriptErrorKind::DataTooLarge
+    );
+
+    let mut command = Command::new("!loop".to_string(), "{{loop{}}}".to_string());
+    command.limits = ScriptLimits::parse("timeout=100").unwrap();
+    let response = command.respond(&Message::new("!loop".to_string())).unwrap();
+    assert_eq!(response.text, "Script Error (Timeout): Timeout after 100ms");
+}
+
+#[test]
+fn test_script_actions() {
+    let command = Command::new(
+        "!actions".to_string(),
+        "{{add_command(\"!new\", \"hi $user\"); delete_variable(\"old\"); \
+         send_message(\"discord\", \"general\", \"hello\"); \"done\"}}"
+            .to_string(),
+    )
+    .with_author("admin".to_string())
+    .build();
+    let response = command
+        .respond(&Message::new("!actions".to_string()))
+        .unwrap();
+    assert_eq!(response.text, "done");
+    assert_eq!(
+        response.actions,
+        vec![
+            ScriptAction::AddCommand("!new".to_string(), "hi foo".to_string()),
+            ScriptAction::DeleteVariable("old".to_string()),
+            ScriptAction::SendMessage(
+                MessageTarget::Discord("general".to_string()),
+                "hello".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_script_modules() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        connection.set_script_module(&ScriptModule::new(
+            "shout".to_string(),
+            "fn shout(x) { upper(x) + exclaim() }".to_string(),
+            "".to_string(),
+        ))?;
+        connection.set_script_module(&ScriptModule::new(
+            "exclaim".to_string(),
+            "import \"shout\";\nfn exclaim() { \"!\" }".to_string(),
+            "".to_string(),
+        ))?;
+        let command = Command::new(
+            "!shout".to_string(),
+            "{{import \"shout\"; import \"exclaim\"; shout(\"$text\")}}".to_string(),
+        )
+        .with_database_path(connection.path.clone())
+        .build();
+        let response = command
+            .respond(&Message::new("!shout hey".to_string()))
+            .unwrap();
+        assert_eq!(response.text, "HEY!");
+
+        let command = Command::new("!missing".to_string(), "{{import \"nope\"; 1}}".to_string())
+            .with_database_path(connection.path.clone())
+            .build();
+        let response = command
+            .respond(&Message::new("!missing".to_string()))
+            .unwrap();
+        assert_eq!(
+            response.text,
+            "Script Error (Runtime): Module nope does not exist!"
+        );
+
+        // Imports in strings are left alone, and errors point into the module.
+        connection.set_script_module(&ScriptModule::new(
+            "broken".to_string(),
+            "fn broken() {\n    let x = ;\n}".to_string(),
+            "".to_string(),
+        ))?;
+        let command = Command::new(
+            "!broken".to_string(),
+            "{{let s = \"import \\\"nope\\\";\";\nimport \"broken\";\nbroken()}}".to_string(),
+        )
+        .with_database_path(connection.path)
+        .build();
+        let response = command
+            .respond(&Message::new("!broken".to_string()))
+            .unwrap();
+        let error = &response.script_failures[0].error;
+        assert_eq!(error.kind, ScriptErrorKind::Parse);
+        assert_eq!(error.module, Some("broken".to_string()));
+        assert_eq!(error.line, Some(2));
+        assert_eq!(error.snippet, Some("    let x = ;".to_string()));
+        Ok(())
+    })
+}
+
 #[test]
 fn test_d6() {
     let command = Command::new(
@@ -336,6 +668,34 @@ fn test_d6() {
     assert!(n >= 1 && n <= 6);
 }
 
+#[test]
+fn test_roll() {
+    let command = Command::new("!roll".to_string(), "$user rolled $roll".to_string());
+    let response = command
+        .respond(&Message::new("!roll 2d6".to_string()))
+        .unwrap()
+        .text;
+    assert!(response.starts_with("foo rolled "));
+    assert!(response.contains("(2d6: ["));
+
+    let response = command
+        .respond(&Message::new("!roll 2x".to_string()))
+        .unwrap()
+        .text;
+    assert_eq!(response, "foo rolled Unexpected 'x' in dice");
+
+    let command = Command::new(
+        "!d6".to_string(),
+        "{{let r = roll(\"3d6+2\"); if r.total >= 5 && r.total <= 20 { r.breakdown } else { \"bad\" }}}"
+            .to_string(),
+    );
+    let response = command
+        .respond(&Message::new("!d6".to_string()))
+        .unwrap()
+        .text;
+    assert!(response.ends_with("] + 2"));
+}
+
 #[test]
 fn test_coinflip() {
     let command = Command::new(
@@ -361,6 +721,7 @@ fn test_counter() -> Result<(), rusqlite::Error> {
             "{{let count = get(\"count\"); count += 1; set(\"count\", count); count}}".to_string(),
         )
         .with_database_path(connection.path)
+        .with_tier(ScriptTier::VariableWrite)
         .build();
         let response = command
             .respond(&Message::new("!count".to_string()))
@@ -376,6 +737,31 @@ fn test_counter() -> Result<(), rusqlite::Error> {
     })
 }
 
+#[test]
+fn test_get_key() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        let mut socials = BTreeMap::new();
+        socials.insert("twitter".to_string(), "@stovoy".to_string());
+        connection.set_variable(&Variable::new(
+            "socials".to_string(),
+            VariableValue::Map(socials),
+        ))?;
+        let command = Command::new(
+            "!socials".to_string(),
+            "{{get_key(\"socials\", \"twitter\") + \"|\" + get_key(\"socials\", \"x\")}}"
+                .to_string(),
+        )
+        .with_database_path(connection.path)
+        .build();
+        let response = command
+            .respond(&Message::new("!socials".to_string()))
+            .unwrap()
+            .text;
+        assert_eq!(response, "@stovoy|");
+        Ok(())
+    })
+}
+
 #[test]
 fn test_quotes() -> Result<(), rusqlite::Error> {
     database::with_test_db(|connection| {
@@ -421,3 +807,423 @@ fn test_quotes() -> Result<(), rusqlite::Error> {
         Ok(())
     })
 }
+
+#[test]
+fn test_quote_search() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        connection.set_variable(&Variable::new(
+            "quotes".to_string(),
+            VariableValue::StringList(vec![
+                StringItem::new("I love dragons"),
+                StringItem::new("Nothing to see here"),
+                StringItem::new("That dragon!"),
+            ]),
+        ))?;
+        let command = Command::default_commands()
+            .into_iter()
+            .find(|command| command.trigger == "!quote search")
+            .unwrap()
+            .with_database_path(connection.path.clone())
+            .build();
+        let response = command
+            .respond(&Message::new("!quote search Dragon".to_string()))
+            .unwrap();
+        assert_eq!(response.text, "Matching quotes: #3 #1");
+        let response = command
+            .respond(&Message::new("!quote search wyvern".to_string()))
+            .unwrap();
+        assert_eq!(response.text, "No quotes match");
+        let response = command
+            .respond(&Message::new(
+                "!quote search x\"); set_list(\"quotes\", []); (\"".to_string(),
+            ))
+            .unwrap();
+        assert_eq!(response.text, "No quotes match");
+        match connection.get_variable("quotes")?.value {
+            VariableValue::StringList(list) => assert_eq!(list.len(), 3),
+            _ => panic!("Expected a StringList"),
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_quote_credit() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        let mut item = StringItem::new("Hello there");
+        item.time_created = time::Timespec::new(1767312000, 0);
+        item.added_by = "foo".to_string();
+        item.added_in = Some(MessageTarget::Twitch("stovoy".to_string()));
+        item.fields
+            .insert("game".to_string(), "Elden Ring".to_string());
+        connection.set_variable(&Variable::new(
+            "quotes".to_string(),
+            VariableValue::StringList(vec![item, StringItem::new("General Kenobi")]),
+        ))?;
+        let command = Command::default_commands()
+            .into_iter()
+            .find(|command| command.trigger == "!quote")
+            .unwrap()
+            .with_database_path(connection.path.clone())
+            .build();
+        let response = command
+            .respond(&Message::new("!quote 1".to_string()))
+            .unwrap();
+        assert_eq!(
+            response.text,
+            "#1: \"Hello there\" — added by foo on 2026-01-02"
+        );
+        let response = command
+            .respond(&Message::new("!quote 2".to_string()))
+            .unwrap();
+        assert_eq!(response.text, "#2: \"General Kenobi\"");
+
+        let info = Command::new(
+            "!info".to_string(),
+            "{{let item = get_item(\"quotes\", 0); \
+             item.platform + \" \" + item.channel + \" \" + field(item, \"game\")}}"
+                .to_string(),
+        )
+        .with_database_path(connection.path.clone())
+        .build();
+        let response = info.respond(&Message::new("!info".to_string())).unwrap();
+        assert_eq!(response.text, "twitch stovoy Elden Ring");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_counter_functions() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        let command = Command::new(
+            "!death".to_string(),
+            "{{increment(\"deaths\"); increment(\"deaths\", 5); decrement(\"deaths\")}}"
+                .to_string(),
+        )
+        .with_database_path(connection.path.clone())
+        .with_tier(ScriptTier::VariableWrite)
+        .build();
+        let response = command
+            .respond(&Message::new("!death".to_string()))
+            .unwrap();
+        assert_eq!(response.text, "5");
+        let reset = Command::new(
+            "!reset".to_string(),
+            "{{reset(\"deaths\"); get(\"deaths\")}}".to_string(),
+        )
+        .with_database_path(connection.path.clone())
+        .with_tier(ScriptTier::VariableWrite)
+        .build();
+        let response = reset.respond(&Message::new("!reset".to_string())).unwrap();
+        assert_eq!(response.text, "0");
+
+        // Like !variable edit, text that isn't a number isn't a counter.
+        connection.set_variable(&Variable::new(
+            "name".to_string(),
+            VariableValue::Text("Bob".to_string()),
+        ))?;
+        let command = Command::new("!name".to_string(), "{{increment(\"name\")}}".to_string())
+            .with_database_path(connection.path.clone())
+            .with_tier(ScriptTier::VariableWrite)
+            .build();
+        let response = command.respond(&Message::new("!name".to_string())).unwrap();
+        assert_eq!(response.script_failures.len(), 1);
+        assert_eq!(
+            connection.get_variable("name")?.value,
+            VariableValue::Text("Bob".to_string())
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_list_mutation() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        let mut items = vec![StringItem::new("a"), StringItem::new("b")];
+        items[0].time_created = time::Timespec::new(1, 0);
+        connection.set_variable(&Variable::new(
+            "list".to_string(),
+            VariableValue::StringList(items),
+        ))?;
+        let command = Command::new(
+            "!list".to_string(),
+            "{{push(\"list\", \"c\"); insert_at(\"list\", 1, 5); \
+             let removed = remove_at(\"list\", 2); remove_value(\"list\", \"c\"); \
+             removed + \" \" + string(list_len(\"list\"))}}"
+                .to_string(),
+        )
+        .with_database_path(connection.path.clone())
+        .with_tier(ScriptTier::VariableWrite)
+        .build();
+        let response = command
+            .respond(&Message::new("!list".to_string()))
+            .unwrap()
+            .text;
+        assert_eq!(response, "b 2");
+        let variable = connection.get_variable("list")?;
+        match variable.value {
+            VariableValue::StringList(list) => {
+                assert_eq!(list.len(), 2);
+                assert_eq!(list[0].value, "a");
+                assert_eq!(list[0].time_created, time::Timespec::new(1, 0));
+                assert_eq!(list[1].value, "5");
+            }
+            _ => panic!("Expected a StringList"),
+        }
+
+        let command = Command::new(
+            "!setlist".to_string(),
+            "{{set_list(\"list\", [\"x\", \"a\"]); list_len(\"list\")}}".to_string(),
+        )
+        .with_database_path(connection.path.clone())
+        .with_tier(ScriptTier::VariableWrite)
+        .build();
+        let response = command
+            .respond(&Message::new("!setlist".to_string()))
+            .unwrap()
+            .text;
+        assert_eq!(response, "2");
+        match connection.get_variable("list")?.value {
+            VariableValue::StringList(list) => {
+                assert_eq!(list[0].value, "x");
+                assert_eq!(list[1].value, "a");
+                assert_eq!(list[1].time_created, time::Timespec::new(1, 0));
+            }
+            _ => panic!("Expected a StringList"),
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+fn run_script_command(script: &str) -> String {
+    let command = Command::new("!script".to_string(), format!("{{{{{}}}}}", script));
+    command
+        .respond(&Message::new("!script".to_string()))
+        .unwrap()
+        .text
+}
+
+#[test]
+fn test_string_functions() {
+    assert_eq!(run_script_command("upper(\"Hi\")"), "HI");
+    assert_eq!(run_script_command("lower(\"Hi\")"), "hi");
+    assert_eq!(run_script_command("trim(\"  hi \")"), "hi");
+    assert_eq!(
+        run_script_command("let parts = split(\"a,b,c\", \",\"); parts[1]"),
+        "b"
+    );
+    assert_eq!(
+        run_script_command("join(split(\"a b c\", \" \"), \"-\")"),
+        "a-b-c"
+    );
+    assert_eq!(run_script_command("replace(\"a-b\", \"-\", \"+\")"), "a+b");
+    assert_eq!(run_script_command("contains(\"hello\", \"ell\")"), "true");
+    assert_eq!(
+        run_script_command("starts_with(\"hello\", \"lo\")"),
+        "false"
+    );
+    assert_eq!(run_script_command("pad(\"ab\", 4) + \"|\""), "ab  |");
+    assert_eq!(run_script_command("pad_left(\"ab\", 4)"), "  ab");
+}
+
+#[test]
+fn test_math_functions() {
+    assert_eq!(run_script_command("min(3, 5)"), "3");
+    assert_eq!(run_script_command("max(3.5, 1.5)"), "3.5");
+    assert_eq!(run_script_command("abs(-4)"), "4");
+    assert_eq!(run_script_command("round(2.5)"), "3");
+    assert_eq!(run_script_command("ceil(2.1)"), "3");
+    assert_eq!(run_script_command("pow(2, 10)"), "1024");
+    assert_eq!(run_script_command("pow(4.0, 0.5)"), "2");
+    assert_eq!(run_script_command("clamp(15, 0, 10)"), "10");
+    assert_eq!(run_script_command("clamp(-1.5, 0.0, 1.0)"), "0");
+    assert!(run_script_command("pow(10, 19)").starts_with("Script Error (Runtime)"));
+    assert!(run_script_command("pow(2, 4294967296)").starts_with("Script Error (Runtime)"));
+}
+
+#[test]
+fn test_random_functions() {
+    for _ in 0..10 {
+        let n: i64 = run_script_command("random_int(3, 5)").parse().unwrap();
+        assert!(n >= 3 && n <= 5);
+    }
+    assert_eq!(
+        run_script_command("random_int(9223372036854775807, 9223372036854775807)"),
+        "9223372036854775807"
+    );
+    let response = run_script_command("choice([\"a\", \"b\"])");
+    assert!(response == "a" || response == "b");
+    let response = run_script_command("join(shuffle([\"a\", \"b\", \"c\"]), \"\")");
+    let mut letters: Vec<char> = response.chars().collect();
+    letters.sort();
+    assert_eq!(letters, vec!['a', 'b', 'c']);
+}
+
+#[test]
+fn test_time_functions() {
+    let now: i64 = run_script_command("now()").parse().unwrap();
+    assert!(now > 1_500_000_000);
+    let year: i64 = run_script_command("date(\"%Y\")").parse().unwrap();
+    assert!(year >= 2020);
+    assert_eq!(
+        run_script_command("format_time(86400, \"%Y-%m-%d\")"),
+        "1970-01-02"
+    );
+}
+
+#[test]
+fn test_dry_run() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        connection.set_variable(&Variable::new(
+            "count".to_string(),
+            VariableValue::Text("1".to_string()),
+        ))?;
+        let command = Command::new(
+            "!count".to_string(),
+            "{{let n = int(get(\"count\")) + 1; set(\"count\", n); set(\"last\", \"$user\"); n}}"
+                .to_string(),
+        )
+        .with_database_path(connection.path.clone())
+        .with_tier(ScriptTier::VariableWrite)
+        .with_dry_run(true)
+        .build();
+        let response = command
+            .respond(&Message::new("!count".to_string()))
+            .unwrap();
+        assert_eq!(response.text, "2");
+        assert_eq!(
+            response
+                .variable_changes
+                .iter()
+                .map(|change| change.to_string())
+                .collect::<Vec<String>>(),
+            vec!["count: 1 -> 2", "last = foo"]
+        );
+        assert_eq!(
+            connection.get_variable("count")?.value,
+            VariableValue::Text("1".to_string())
+        );
+        assert!(connection.get_variable("last").is_err());
+
+        // Nothing listens there, so this would fail if it went to the network.
+        connection.set_http_settings(&HttpSettings {
+            allowed_domains: vec!["127.0.0.1".to_string()],
+            ..HttpSettings::default()
+        })?;
+        let command = Command::new(
+            "!weather".to_string(),
+            "{{http_get(\"http://127.0.0.1:9/weather\")}}".to_string(),
+        )
+        .with_author("admin".to_string())
+        .with_database_path(connection.path)
+        .with_dry_run(true)
+        .build();
+        let response = command
+            .respond(&Message::new("!weather".to_string()))
+            .unwrap();
+        assert_eq!(
+            response.text,
+            "(http_get(http://127.0.0.1:9/weather) is skipped in a dry run)"
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_script_scheduling() {
+    let command = Command::new(
+        "!countdown".to_string(),
+        "{{say_after(30, \"go!\"); schedule(\"every 10m\", \"!quote\"); \"3, 2, 1...\"}}"
+            .to_string(),
+    )
+    .with_author("admin".to_string())
+    .build();
+    let response = command
+        .respond(&Message::new("!countdown".to_string()))
+        .unwrap();
+    assert_eq!(response.text, "3, 2, 1...");
+    assert_eq!(
+        response.actions,
+        vec![
+            ScriptAction::SayAfter(30, "go!".to_string()),
+            ScriptAction::Schedule("every 10m".to_string(), "!quote".to_string()),
+        ]
+    );
+
+    let command = Command::new(
+        "!bad".to_string(),
+        "{{schedule(\"every 5s\", \"!quote\")}}".to_string(),
+    )
+    .with_author("admin".to_string())
+    .build();
+    let response = command.respond(&Message::new("!bad".to_string())).unwrap();
+    assert!(response.text.contains("Can't repeat every 5 seconds"));
+    assert!(response.actions.is_empty());
+}
+
+#[test]
+fn test_user_variables() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        connection.set_user_variable("test:bar", "points", "100")?;
+        let command = Command::new(
+            "!points".to_string(),
+            "{{user_set(\"points\", int(user_get(\"points\")) + 5); \"ok\"}} \
+             $user has ${uservar:points} points"
+                .to_string(),
+        )
+        .with_database_path(connection.path.clone())
+        .build();
+        let response = command
+            .respond(&Message::new("!points".to_string()))
+            .unwrap();
+        assert_eq!(response.text, "ok foo has 5 points");
+        let response = command
+            .respond(&Message::new("!points".to_string()))
+            .unwrap();
+        assert_eq!(response.text, "ok foo has 10 points");
+        assert_eq!(connection.get_user_variable("test:foo", "points")?, "10");
+        assert_eq!(connection.get_user_variable("test:bar", "points")?, "100");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_command_state() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        let command = Command::new(
+            "!count".to_string(),
+            "{{let n = int(state_get(\"n\")) + 1; state_set(\"n\", n); n}}".to_string(),
+        )
+        .with_database_path(connection.path.clone())
+        .build();
+        let message = Message::new("!count".to_string());
+        assert_eq!(command.respond(&message).unwrap().text, "1");
+        assert_eq!(command.respond(&message).unwrap().text, "2");
+        assert_eq!(connection.get_command_state("!count", "n")?, "2");
+        assert!(connection.get_variable("n").is_err());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_script_tiers() -> Result<(), rusqlite::Error> {
+    database::with_test_db(|connection| {
+        let command = Command::new(
+            "!mine".to_string(),
+            "{{state_set(\"n\", 1); set(\"n\", 1); \"ok\"}}".to_string(),
+        )
+        .with_database_path(connection.path.clone())
+        .with_author("twitch:foo".to_string())
+        .build();
+        let message = Message::new("!mine".to_string());
+        let response = command.respond(&message).unwrap();
+        assert_eq!(response.script_failures.len(), 1);
+        assert!(connection.get_variable("n").is_err());
+
+        connection.set_script_tier("twitch:foo", ScriptTier::VariableWrite)?;
+        assert_eq!(command.respond(&message).unwrap().text, "ok");
+        assert!(connection.get_variable("n").is_ok());
+        Ok(())
+    })
+}
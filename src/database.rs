@@ -1,10 +1,17 @@
-use crate::models::{Command, Variable, VariableValue};
-use rusqlite::types::{FromSql, FromSqlError, ToSql, ToSqlOutput, Value, ValueRef};
+use crate::models::{
+    CacheSettings, Command, HttpSettings, MessageTarget, ScheduledTask, ScriptLimits, ScriptModule,
+    ScriptTier, Variable, VariableValue, VariableWatcher,
+};
+use rusqlite::types::{FromSql, FromSqlError, ToSql, ToSqlOutput, Type, Value, ValueRef};
 use rusqlite::{params, Connection, Error, Row};
 use serde_json;
+use std::collections::BTreeMap;
 use std::env;
 use time;
+use time::Timespec;
 
+#[cfg(test)]
+use crate::models::StringItem;
 #[cfg(test)]
 use rand::Rng;
 
@@ -43,12 +50,25 @@ impl Database {
     }
 
     #[cfg(test)]
-    fn new_in_memory() -> Result<Database, Error> {
+    pub fn new_in_memory() -> Result<Database, Error> {
         let database = Database::connect(Some(Database::memory_path()))?;
         database.migrate()?;
         Ok(database)
     }
 
+    // Used by dry runs, so script writes can be looked at and thrown away.
+    pub fn begin(&self) -> Result<(), Error> {
+        self.connection.execute_batch("BEGIN")
+    }
+
+    pub fn commit(&self) -> Result<(), Error> {
+        self.connection.execute_batch("COMMIT")
+    }
+
+    pub fn rollback(&self) -> Result<(), Error> {
+        self.connection.execute_batch("ROLLBACK")
+    }
+
     pub fn memory_path() -> String {
         "MEMORY".to_string()
     }
@@ -64,20 +84,103 @@ impl Database {
               time_created  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
               trigger       TEXT NOT NULL UNIQUE,
               response      TEXT NOT NULL,
-              is_alias      BOOL NOT NULL
+              is_alias      BOOL NOT NULL,
+              author        TEXT NOT NULL DEFAULT '',
+              limits        TEXT NOT NULL DEFAULT '{}',
+              cache         TEXT,
+              time_modified TIMESTAMP,
+              tier          TEXT
             )",
             "CREATE TABLE IF NOT EXISTS variable (
               id            INTEGER PRIMARY KEY AUTOINCREMENT,
               time_created  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
               time_modified TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
               name          TEXT NOT NULL UNIQUE,
+              value         TEXT NOT NULL,
+              time_expires  INTEGER
+            )",
+            "CREATE TABLE IF NOT EXISTS script_module (
+              id            INTEGER PRIMARY KEY AUTOINCREMENT,
+              time_created  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+              time_modified TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+              name          TEXT NOT NULL UNIQUE,
+              source        TEXT NOT NULL,
+              author        TEXT NOT NULL DEFAULT ''
+            )",
+            "CREATE TABLE IF NOT EXISTS setting (
+              name          TEXT PRIMARY KEY,
               value         TEXT NOT NULL
             )",
+            "CREATE TABLE IF NOT EXISTS scheduled_task (
+              id            INTEGER PRIMARY KEY AUTOINCREMENT,
+              time_due      INTEGER NOT NULL,
+              repeat        TEXT NOT NULL,
+              target        TEXT NOT NULL,
+              text          TEXT NOT NULL,
+              is_command    BOOL NOT NULL,
+              username      TEXT NOT NULL,
+              author        TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS user_variable (
+              id            INTEGER PRIMARY KEY AUTOINCREMENT,
+              time_created  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+              time_modified TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+              user          TEXT NOT NULL,
+              name          TEXT NOT NULL,
+              value         TEXT NOT NULL,
+              UNIQUE(user, name)
+            )",
+            "CREATE TABLE IF NOT EXISTS command_state (
+              id            INTEGER PRIMARY KEY AUTOINCREMENT,
+              time_modified TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+              trigger       TEXT NOT NULL,
+              name          TEXT NOT NULL,
+              value         TEXT NOT NULL,
+              UNIQUE(trigger, name)
+            )",
+            "CREATE TABLE IF NOT EXISTS script_tier (
+              user          TEXT PRIMARY KEY,
+              tier          TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS output_cache (
+              trigger       TEXT NOT NULL,
+              key           TEXT NOT NULL,
+              time_expires  INTEGER NOT NULL,
+              text          TEXT NOT NULL,
+              PRIMARY KEY(trigger, key)
+            )",
+            "CREATE TABLE IF NOT EXISTS variable_watcher (
+              id            INTEGER PRIMARY KEY AUTOINCREMENT,
+              variable      TEXT NOT NULL,
+              template      TEXT NOT NULL,
+              target        TEXT NOT NULL,
+              author        TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS http_cache (
+              url           TEXT PRIMARY KEY,
+              time_fetched  INTEGER NOT NULL,
+              body          TEXT NOT NULL
+            )",
         ];
         for table in tables.iter() {
             self.connection.execute(table, params![])?;
         }
 
+        // Columns added after a table was first created.
+        let columns = [
+            ("command", "author", "TEXT NOT NULL DEFAULT ''"),
+            ("command", "limits", "TEXT NOT NULL DEFAULT '{}'"),
+            ("command", "cache", "TEXT"),
+            // Can't default to CURRENT_TIMESTAMP when added later, so older rows fall back to
+            // their time_created.
+            ("command", "time_modified", "TIMESTAMP"),
+            ("command", "tier", "TEXT"),
+            ("variable", "time_expires", "INTEGER"),
+        ];
+        for (table, column, definition) in columns.iter() {
+            self.add_column(table, column, definition)?;
+        }
+
         for command in Command::default_commands() {
             self.upsert_command(&command)?;
         }
@@ -85,39 +188,188 @@ impl Database {
         Ok(())
     }
 
+    fn add_column(&self, table: &str, column: &str, definition: &str) -> Result<(), Error> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("PRAGMA table_info({})", table))?;
+        let mut names = statement.query_map(params![], |row: &Row| row.get::<_, String>(1))?;
+        if !names.any(|name| name.map(|name| name == column).unwrap_or(false)) {
+            self.connection.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                params![],
+            )?;
+        }
+        Ok(())
+    }
+
     pub fn add_command(&self, command: &Command) -> Result<usize, Error> {
         self.connection.execute(
-            "INSERT INTO command (trigger, response, is_alias) VALUES (?1, ?2, ?3)",
-            params![command.trigger, command.response, command.is_alias],
+            "INSERT INTO command (trigger, response, is_alias, author, time_modified, tier)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                command.trigger,
+                command.response,
+                command.is_alias,
+                command.author,
+                time::get_time(),
+                command.tier
+            ],
         )
     }
 
     pub fn update_command(&self, command: &Command) -> Result<usize, Error> {
         self.connection.execute(
-            "UPDATE command SET response = ?2, is_alias = ?3 WHERE trigger = ?1",
-            params![command.trigger, command.response, command.is_alias],
+            "UPDATE command SET response = ?2, is_alias = ?3, author = ?4, time_modified = ?5,
+             tier = ?6 WHERE trigger = ?1",
+            params![
+                command.trigger,
+                command.response,
+                command.is_alias,
+                command.author,
+                time::get_time(),
+                command.tier
+            ],
         )
     }
 
     pub fn upsert_command(&self, command: &Command) -> Result<usize, Error> {
         self.connection.execute(
-            "INSERT INTO command (trigger, response, is_alias) VALUES(?1, ?2, ?3)
-             ON CONFLICT(trigger) DO UPDATE SET response = ?2, is_alias = ?3",
-            params![command.trigger, command.response, command.is_alias],
+            "INSERT INTO command (trigger, response, is_alias, tier) VALUES(?1, ?2, ?3, ?4)
+             ON CONFLICT(trigger) DO UPDATE SET response = ?2, is_alias = ?3, tier = ?4",
+            params![
+                command.trigger,
+                command.response,
+                command.is_alias,
+                command.tier
+            ],
+        )
+    }
+
+    pub fn set_command_limits(&self, trigger: &str, limits: &ScriptLimits) -> Result<usize, Error> {
+        self.connection.execute(
+            "UPDATE command SET limits = ?2 WHERE trigger = ?1",
+            params![trigger, limits],
+        )
+    }
+
+    pub fn set_command_cache(
+        &self,
+        trigger: &str,
+        cache: Option<&CacheSettings>,
+    ) -> Result<usize, Error> {
+        self.connection.execute(
+            "UPDATE command SET cache = ?2 WHERE trigger = ?1",
+            params![trigger, cache],
         )
     }
 
+    pub fn get_cached_output(
+        &self,
+        trigger: &str,
+        key: &str,
+        now: i64,
+    ) -> Result<Option<(i64, String)>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT time_expires, text FROM output_cache
+             WHERE trigger = ?1 AND key = ?2 AND time_expires > ?3",
+        )?;
+        match statement.query_row(params![trigger, key, now], |row: &Row| {
+            Ok((row.get(0)?, row.get(1)?))
+        }) {
+            Ok(output) => Ok(Some(output)),
+            Err(Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_cached_output(
+        &self,
+        trigger: &str,
+        key: &str,
+        time_expires: i64,
+        text: &str,
+    ) -> Result<usize, Error> {
+        self.connection.execute(
+            "INSERT INTO output_cache (trigger, key, time_expires, text) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(trigger, key) DO UPDATE SET time_expires = ?3, text = ?4",
+            params![trigger, key, time_expires, text],
+        )
+    }
+
+    // None clears every command's output, along with anything already expired.
+    pub fn clear_cached_output(&self, trigger: Option<&str>, now: i64) -> Result<usize, Error> {
+        match trigger {
+            Some(trigger) => self.connection.execute(
+                "DELETE FROM output_cache WHERE trigger = ?1 OR time_expires <= ?2",
+                params![trigger, now],
+            ),
+            None => self
+                .connection
+                .execute("DELETE FROM output_cache", params![]),
+        }
+    }
+
+    // The command's state goes with it. Its cached output is cleared by the bot, which also
+    // keeps it in memory.
     pub fn delete_command(&self, command: &Command) -> Result<usize, Error> {
+        self.connection.execute(
+            "DELETE FROM command_state WHERE trigger = ?1",
+            params![command.trigger],
+        )?;
         self.connection.execute(
             "DELETE FROM command WHERE trigger = ?1",
             params![command.trigger],
         )
     }
 
-    pub fn get_commands(&self) -> Result<Vec<Command>, Error> {
+    pub fn get_command_state(&self, trigger: &str, name: &str) -> Result<String, Error> {
+        self.connection.query_row(
+            "SELECT value FROM command_state WHERE trigger = ?1 AND name = ?2",
+            params![trigger, name],
+            |row: &Row| row.get(0),
+        )
+    }
+
+    pub fn get_command_states(&self, trigger: &str) -> Result<Vec<(String, String)>, Error> {
         let mut statement = self
             .connection
-            .prepare("SELECT id, time_created, trigger, response, is_alias FROM command")?;
+            .prepare("SELECT name, value FROM command_state WHERE trigger = ?1 ORDER BY name")?;
+        let states_iter =
+            statement.query_map(params![trigger], |row: &Row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut states = Vec::new();
+        for state in states_iter {
+            states.push(state?);
+        }
+        Ok(states)
+    }
+
+    pub fn set_command_state(
+        &self,
+        trigger: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<usize, Error> {
+        self.connection.execute(
+            "INSERT INTO command_state (trigger, name, value) VALUES(?1, ?2, ?3)
+             ON CONFLICT(trigger, name) DO UPDATE SET value = ?3, time_modified = ?4",
+            params![trigger, name, value, time::get_time()],
+        )
+    }
+
+    pub fn get_command(&self, trigger: &str) -> Result<Command, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, time_created, trigger, response, is_alias, author, limits, cache, \
+             time_modified, tier FROM command WHERE trigger = ?1",
+        )?;
+        statement.query_row(params![trigger], |row: &Row| self.map_command(row))
+    }
+
+    pub fn get_commands(&self) -> Result<Vec<Command>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, time_created, trigger, response, is_alias, author, limits, cache, \
+             time_modified, tier FROM command",
+        )?;
         let commands_iter = statement.query_map(params![], |row: &Row| self.map_command(row))?;
 
         let mut commands = Vec::new();
@@ -127,11 +379,15 @@ impl Database {
         Ok(commands)
     }
 
+    // Expired variables are left out until the bot deletes them, see get_expired_variables.
     pub fn get_variables(&self) -> Result<Vec<Variable>, Error> {
-        let mut statement = self
-            .connection
-            .prepare("SELECT id, time_created, time_modified, name, value FROM variable")?;
-        let variables_iter = statement.query_map(params![], |row: &Row| self.map_variable(row))?;
+        let mut statement = self.connection.prepare(
+            "SELECT id, time_created, time_modified, name, value, time_expires FROM variable \
+             WHERE time_expires IS NULL OR time_expires > ?1",
+        )?;
+        let variables_iter = statement.query_map(params![time::get_time().sec], |row: &Row| {
+            self.map_variable(row)
+        })?;
 
         let mut variables = Vec::new();
         for variable in variables_iter {
@@ -142,20 +398,116 @@ impl Database {
 
     pub fn get_variable(&self, name: &str) -> Result<Variable, Error> {
         let mut statement = self.connection.prepare(
-            "SELECT id, time_created, time_modified, name, value \
-             FROM variable WHERE name = ?1",
+            "SELECT id, time_created, time_modified, name, value, time_expires \
+             FROM variable WHERE name = ?1 AND (time_expires IS NULL OR time_expires > ?2)",
         )?;
-        statement.query_row(params![name], |row: &Row| self.map_variable(row))
+        statement.query_row(params![name, time::get_time().sec], |row: &Row| {
+            self.map_variable(row)
+        })
     }
 
+    // Keeps the expiry of a variable that's still around unless a new one is given.
     pub fn set_variable(&self, variable: &Variable) -> Result<usize, Error> {
+        let now = time::get_time();
+        self.connection.execute(
+            "INSERT INTO variable (name, value, time_expires) VALUES(?1, ?2, ?4)
+             ON CONFLICT(name) DO UPDATE SET value = ?2, time_modified = ?3, time_expires =
+                CASE WHEN time_expires > ?5 THEN COALESCE(?4, time_expires) ELSE ?4 END",
+            params![
+                variable.name,
+                variable.value,
+                now,
+                variable.time_expires,
+                now.sec
+            ],
+        )
+    }
+
+    pub fn get_expired_variables(&self, now: i64) -> Result<Vec<Variable>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, time_created, time_modified, name, value, time_expires FROM variable \
+             WHERE time_expires <= ?1",
+        )?;
+        let variables_iter =
+            statement.query_map(params![now], |row: &Row| self.map_variable(row))?;
+
+        let mut variables = Vec::new();
+        for variable in variables_iter {
+            variables.push(variable?);
+        }
+        Ok(variables)
+    }
+
+    // Only deletes it if it's still expired, so a value set again in the meantime stays.
+    pub fn delete_expired_variable(&self, name: &str, now: i64) -> Result<usize, Error> {
         self.connection.execute(
-            "INSERT INTO variable (name, value) VALUES(?1, ?2)
-             ON CONFLICT(name) DO UPDATE SET value = ?2, time_modified = ?3",
-            params![variable.name, variable.value, time::get_time()],
+            "DELETE FROM variable WHERE name = ?1 AND time_expires <= ?2",
+            params![name, now],
         )
     }
 
+    // Adds to a counter inside one write transaction, so updates made at the same time by other
+    // processes aren't lost. A missing or expired counter starts at 0, and so does empty text.
+    // Lists, maps and text that isn't a number are errors, like for !variable edit.
+    pub fn increment_variable(&self, name: &str, by: i64) -> Result<i64, Error> {
+        // Dry runs are already in a transaction, and are rolled back anyway.
+        let in_transaction = !self.connection.is_autocommit();
+        if !in_transaction {
+            self.connection.execute_batch("BEGIN IMMEDIATE")?;
+        }
+        let result = self.add_to_counter(name, by);
+        if !in_transaction {
+            match result {
+                Ok(_) => self.commit()?,
+                Err(_) => self.rollback()?,
+            }
+        }
+        result
+    }
+
+    fn add_to_counter(&self, name: &str, by: i64) -> Result<i64, Error> {
+        let value = match self.get_variable(name) {
+            Ok(variable) => variable.value,
+            Err(Error::QueryReturnedNoRows) => VariableValue::Text("0".to_string()),
+            Err(e) => return Err(e),
+        };
+        let count = match value {
+            VariableValue::Text(text) if text.trim().is_empty() => Some(0),
+            VariableValue::Text(text) => text.trim().parse::<i64>().ok(),
+            _ => None,
+        };
+        let count = match count {
+            Some(count) => count.saturating_add(by),
+            None => {
+                return Err(Error::FromSqlConversionFailure(
+                    0,
+                    Type::Text,
+                    format!("{} is not a counter", name).into(),
+                ))
+            }
+        };
+        self.connection.execute(
+            "INSERT INTO variable (name, value, time_modified) VALUES(?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET value = ?2, time_modified = ?3, time_expires = NULL",
+            params![
+                name,
+                VariableValue::Text(count.to_string()),
+                time::get_time()
+            ],
+        )?;
+        Ok(count)
+    }
+
+    // Counters go back to 0, and lists and maps are emptied.
+    pub fn reset_variable(&self, name: &str) -> Result<usize, Error> {
+        let value = match self.get_variable(name)?.value {
+            VariableValue::Text(_) => VariableValue::Text("0".to_string()),
+            VariableValue::StringList(_) => VariableValue::StringList(Vec::new()),
+            VariableValue::Map(_) => VariableValue::Map(BTreeMap::new()),
+        };
+        self.set_variable(&Variable::new(name.to_string(), value))
+    }
+
     pub fn delete_variable(&self, variable: &Variable) -> Result<usize, Error> {
         self.connection.execute(
             "DELETE FROM variable WHERE name = ?1",
@@ -163,15 +515,301 @@ impl Database {
         )
     }
 
+    // User variables belong to one user identity, see Message::sender_identity.
+    pub fn get_user_variable(&self, user: &str, name: &str) -> Result<String, Error> {
+        self.connection.query_row(
+            "SELECT value FROM user_variable WHERE user = ?1 AND name = ?2",
+            params![user, name],
+            |row: &Row| row.get(0),
+        )
+    }
+
+    pub fn get_user_variables(&self, user: &str) -> Result<Vec<(String, String)>, Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name, value FROM user_variable WHERE user = ?1 ORDER BY name")?;
+        let variables_iter =
+            statement.query_map(params![user], |row: &Row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut variables = Vec::new();
+        for variable in variables_iter {
+            variables.push(variable?);
+        }
+        Ok(variables)
+    }
+
+    pub fn set_user_variable(&self, user: &str, name: &str, value: &str) -> Result<usize, Error> {
+        self.connection.execute(
+            "INSERT INTO user_variable (user, name, value) VALUES(?1, ?2, ?3)
+             ON CONFLICT(user, name) DO UPDATE SET value = ?3, time_modified = ?4",
+            params![user, name, value, time::get_time()],
+        )
+    }
+
+    pub fn delete_user_variables(&self, user: &str) -> Result<usize, Error> {
+        self.connection
+            .execute("DELETE FROM user_variable WHERE user = ?1", params![user])
+    }
+
+    pub fn get_script_modules(&self) -> Result<Vec<ScriptModule>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, time_created, time_modified, name, source, author FROM script_module",
+        )?;
+        let modules_iter = statement.query_map(params![], |row: &Row| self.map_module(row))?;
+
+        let mut modules = Vec::new();
+        for module in modules_iter {
+            modules.push(module.unwrap());
+        }
+        Ok(modules)
+    }
+
+    pub fn get_script_module(&self, name: &str) -> Result<ScriptModule, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, time_created, time_modified, name, source, author \
+             FROM script_module WHERE name = ?1",
+        )?;
+        statement.query_row(params![name], |row: &Row| self.map_module(row))
+    }
+
+    pub fn set_script_module(&self, module: &ScriptModule) -> Result<usize, Error> {
+        self.connection.execute(
+            "INSERT INTO script_module (name, source, author) VALUES(?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET source = ?2, author = ?3, time_modified = ?4",
+            params![module.name, module.source, module.author, time::get_time()],
+        )
+    }
+
+    pub fn delete_script_module(&self, module: &ScriptModule) -> Result<usize, Error> {
+        self.connection.execute(
+            "DELETE FROM script_module WHERE name = ?1",
+            params![module.name],
+        )
+    }
+
+    pub fn get_script_limits(&self) -> Result<ScriptLimits, Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT value FROM setting WHERE name = 'script_limits'")?;
+        match statement.query_row(params![], |row: &Row| row.get(0)) {
+            Ok(limits) => Ok(limits),
+            Err(Error::QueryReturnedNoRows) => Ok(ScriptLimits::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_script_limits(&self, limits: &ScriptLimits) -> Result<usize, Error> {
+        self.connection.execute(
+            "INSERT INTO setting (name, value) VALUES('script_limits', ?1)
+             ON CONFLICT(name) DO UPDATE SET value = ?1",
+            params![limits],
+        )
+    }
+
+    // None when the user was never given a tier.
+    pub fn get_script_tier(&self, user: &str) -> Result<Option<ScriptTier>, Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT tier FROM script_tier WHERE user = ?1")?;
+        match statement.query_row(params![user], |row: &Row| row.get(0)) {
+            Ok(tier) => Ok(Some(tier)),
+            Err(Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_script_tier(&self, user: &str, tier: ScriptTier) -> Result<usize, Error> {
+        self.connection.execute(
+            "INSERT INTO script_tier (user, tier) VALUES(?1, ?2)
+             ON CONFLICT(user) DO UPDATE SET tier = ?2",
+            params![user, tier],
+        )
+    }
+
+    pub fn get_http_settings(&self) -> Result<HttpSettings, Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT value FROM setting WHERE name = 'http_settings'")?;
+        match statement.query_row(params![], |row: &Row| row.get(0)) {
+            Ok(settings) => Ok(settings),
+            Err(Error::QueryReturnedNoRows) => Ok(HttpSettings::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_http_settings(&self, settings: &HttpSettings) -> Result<usize, Error> {
+        self.connection.execute(
+            "INSERT INTO setting (name, value) VALUES('http_settings', ?1)
+             ON CONFLICT(name) DO UPDATE SET value = ?1",
+            params![settings],
+        )
+    }
+
+    // None if the url was never fetched or was fetched more than max_age_secs ago.
+    pub fn get_cached_response(
+        &self,
+        url: &str,
+        max_age_secs: u64,
+    ) -> Result<Option<String>, Error> {
+        let oldest = time::get_time().sec - max_age_secs as i64;
+        let mut statement = self
+            .connection
+            .prepare("SELECT body FROM http_cache WHERE url = ?1 AND time_fetched >= ?2")?;
+        match statement.query_row(params![url, oldest], |row: &Row| row.get(0)) {
+            Ok(body) => Ok(Some(body)),
+            Err(Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_cached_response(&self, url: &str, body: &str) -> Result<usize, Error> {
+        self.connection.execute(
+            "INSERT INTO http_cache (url, time_fetched, body) VALUES (?1, ?2, ?3)
+             ON CONFLICT(url) DO UPDATE SET time_fetched = ?2, body = ?3",
+            params![url, time::get_time().sec, body],
+        )
+    }
+
+    pub fn add_scheduled_task(&self, task: &ScheduledTask) -> Result<usize, Error> {
+        self.connection.execute(
+            "INSERT INTO scheduled_task (time_due, repeat, target, text, is_command, username, author)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                task.time_due,
+                task.repeat,
+                task.target,
+                task.text,
+                task.is_command,
+                task.username,
+                task.author
+            ],
+        )
+    }
+
+    pub fn get_scheduled_tasks(&self) -> Result<Vec<ScheduledTask>, Error> {
+        self.get_due_tasks(i64::MAX)
+    }
+
+    pub fn get_due_tasks(&self, now: i64) -> Result<Vec<ScheduledTask>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, time_due, repeat, target, text, is_command, username, author \
+             FROM scheduled_task WHERE time_due <= ?1 ORDER BY time_due, id",
+        )?;
+        let tasks_iter =
+            statement.query_map(params![now], |row: &Row| self.map_scheduled_task(row))?;
+
+        let mut tasks = Vec::new();
+        for task in tasks_iter {
+            tasks.push(task?);
+        }
+        Ok(tasks)
+    }
+
+    pub fn count_scheduled_tasks(&self, author: &str) -> Result<i64, Error> {
+        self.connection.query_row(
+            "SELECT COUNT(*) FROM scheduled_task WHERE author = ?1",
+            params![author],
+            |row: &Row| row.get(0),
+        )
+    }
+
+    pub fn reschedule_task(&self, id: i32, time_due: i64) -> Result<usize, Error> {
+        self.connection.execute(
+            "UPDATE scheduled_task SET time_due = ?1 WHERE id = ?2",
+            params![time_due, id],
+        )
+    }
+
+    pub fn delete_scheduled_task(&self, id: i32) -> Result<usize, Error> {
+        self.connection
+            .execute("DELETE FROM scheduled_task WHERE id = ?1", params![id])
+    }
+
+    pub fn add_watcher(&self, watcher: &VariableWatcher) -> Result<usize, Error> {
+        self.connection.execute(
+            "INSERT INTO variable_watcher (variable, template, target, author)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                watcher.variable,
+                watcher.template,
+                watcher.target,
+                watcher.author
+            ],
+        )
+    }
+
+    // None gets every variable's watchers.
+    pub fn get_watchers(&self, variable: Option<&str>) -> Result<Vec<VariableWatcher>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, variable, template, target, author FROM variable_watcher \
+             WHERE ?1 IS NULL OR variable = ?1 ORDER BY id",
+        )?;
+        let watchers_iter =
+            statement.query_map(params![variable], |row: &Row| self.map_watcher(row))?;
+
+        let mut watchers = Vec::new();
+        for watcher in watchers_iter {
+            watchers.push(watcher?);
+        }
+        Ok(watchers)
+    }
+
+    pub fn delete_watcher(&self, id: i32) -> Result<usize, Error> {
+        self.connection
+            .execute("DELETE FROM variable_watcher WHERE id = ?1", params![id])
+    }
+
+    fn map_watcher(&self, row: &Row) -> Result<VariableWatcher, Error> {
+        Ok(VariableWatcher {
+            id: row.get(0)?,
+            variable: row.get(1)?,
+            template: row.get(2)?,
+            target: row.get(3)?,
+            author: row.get(4)?,
+        })
+    }
+
+    fn map_scheduled_task(&self, row: &Row) -> Result<ScheduledTask, Error> {
+        Ok(ScheduledTask {
+            id: row.get(0)?,
+            time_due: row.get(1)?,
+            repeat: row.get(2)?,
+            target: row.get(3)?,
+            text: row.get(4)?,
+            is_command: row.get(5)?,
+            username: row.get(6)?,
+            author: row.get(7)?,
+        })
+    }
+
     fn map_command(&self, row: &Row) -> Result<Command, Error> {
+        let time_created = row.get(1)?;
         Ok(Command {
             id: row.get(0)?,
-            time_created: row.get(1)?,
+            time_created,
+            time_modified: row.get::<_, Option<Timespec>>(8)?.unwrap_or(time_created),
             trigger: row.get(2)?,
             response: row.get(3)?,
             actor: None,
             database_path: self.path.clone(),
             is_alias: row.get(4)?,
+            author: row.get(5)?,
+            limits: row.get(6)?,
+            cache: row.get(7)?,
+            tier: row.get(9)?,
+            dry_run: false,
+            inputs: BTreeMap::new(),
+        })
+    }
+
+    fn map_module(&self, row: &Row) -> Result<ScriptModule, Error> {
+        Ok(ScriptModule {
+            id: row.get(0)?,
+            time_created: row.get(1)?,
+            time_modified: row.get(2)?,
+            name: row.get(3)?,
+            source: row.get(4)?,
+            author: row.get(5)?,
         })
     }
 
@@ -182,6 +820,7 @@ impl Database {
             time_modified: row.get(2)?,
             name: row.get(3)?,
             value: row.get(4)?,
+            time_expires: row.get(5)?,
         })
     }
 }
@@ -203,6 +842,89 @@ impl ToSql for VariableValue {
     }
 }
 
+impl FromSql for ScriptTier {
+    fn column_result(value: ValueRef<'_>) -> Result<Self, FromSqlError> {
+        match ScriptTier::parse(value.as_str()?) {
+            Ok(tier) => Ok(tier),
+            Err(_) => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for ScriptTier {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, Error> {
+        Ok(ToSqlOutput::Owned(Value::Text(self.to_string())))
+    }
+}
+
+impl FromSql for MessageTarget {
+    fn column_result(value: ValueRef<'_>) -> Result<Self, FromSqlError> {
+        match serde_json::from_str(value.as_str()?) {
+            Ok(result) => Ok(result),
+            Err(_) => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for MessageTarget {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, Error> {
+        Ok(ToSqlOutput::Owned(Value::Text(
+            serde_json::to_string(self).unwrap(),
+        )))
+    }
+}
+
+impl FromSql for HttpSettings {
+    fn column_result(value: ValueRef<'_>) -> Result<Self, FromSqlError> {
+        match serde_json::from_str(value.as_str()?) {
+            Ok(result) => Ok(result),
+            Err(_) => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for HttpSettings {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, Error> {
+        Ok(ToSqlOutput::Owned(Value::Text(
+            serde_json::to_string(self).unwrap(),
+        )))
+    }
+}
+
+impl FromSql for CacheSettings {
+    fn column_result(value: ValueRef<'_>) -> Result<Self, FromSqlError> {
+        match serde_json::from_str(value.as_str()?) {
+            Ok(result) => Ok(result),
+            Err(_) => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for CacheSettings {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, Error> {
+        Ok(ToSqlOutput::Owned(Value::Text(
+            serde_json::to_string(self).unwrap(),
+        )))
+    }
+}
+
+impl FromSql for ScriptLimits {
+    fn column_result(value: ValueRef<'_>) -> Result<Self, FromSqlError> {
+        match serde_json::from_str(value.as_str()?) {
+            Ok(result) => Ok(result),
+            Err(_) => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for ScriptLimits {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, Error> {
+        Ok(ToSqlOutput::Owned(Value::Text(
+            serde_json::to_string(self).unwrap(),
+        )))
+    }
+}
+
 #[test]
 fn test_add_command() -> Result<(), Error> {
     let database = Database::new_in_memory()?;
@@ -222,6 +944,34 @@ fn test_add_command() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn test_get_command() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    database.add_command(
+        &Command::new("!test".to_string(), "one".to_string())
+            .with_author("twitch:foo".to_string())
+            .build(),
+    )?;
+    let added = database.get_command("!test")?;
+    assert_eq!(added.response, "one");
+    assert_eq!(added.author, "twitch:foo");
+
+    database.connection.execute(
+        "UPDATE command SET time_modified = NULL WHERE trigger = '!test'",
+        params![],
+    )?;
+    assert_eq!(
+        database.get_command("!test")?.time_modified,
+        added.time_created
+    );
+    database.update_command(&Command::new("!test".to_string(), "two".to_string()))?;
+    let edited = database.get_command("!test")?;
+    assert_eq!(edited.response, "two");
+    assert!(edited.time_modified >= edited.time_created);
+    assert!(database.get_command("!missing").is_err());
+    Ok(())
+}
+
 #[test]
 fn test_set_variable() -> Result<(), Error> {
     let database = Database::new_in_memory()?;
@@ -235,6 +985,364 @@ fn test_set_variable() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn test_variable_expiry() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    let now = time::get_time().sec;
+    let mut keyword = Variable::new(
+        "keyword".to_string(),
+        VariableValue::Text("dragon".to_string()),
+    );
+    keyword.time_expires = Some(now + 60);
+    database.set_variable(&keyword)?;
+
+    // Edits keep the expiry.
+    database.set_variable(&Variable::new(
+        "keyword".to_string(),
+        VariableValue::Text("wyvern".to_string()),
+    ))?;
+    let stored = database.get_variable("keyword")?;
+    assert_eq!(stored.value, VariableValue::Text("wyvern".to_string()));
+    assert_eq!(stored.time_expires, Some(now + 60));
+    assert!(database.get_expired_variables(now)?.is_empty());
+
+    // Expired, it reads as missing until it's deleted.
+    assert_eq!(database.get_expired_variables(now + 60)?.len(), 1);
+    keyword.time_expires = Some(now - 1);
+    database.connection.execute(
+        "UPDATE variable SET time_expires = ?2 WHERE name = ?1",
+        params![keyword.name, keyword.time_expires],
+    )?;
+    assert!(database.get_variable("keyword").is_err());
+    assert!(database.get_variables()?.is_empty());
+    assert_eq!(database.delete_expired_variable("keyword", now)?, 1);
+    assert!(database.get_expired_variables(now)?.is_empty());
+
+    // Setting an expired variable starts it over, without the old expiry.
+    database.connection.execute(
+        "INSERT INTO variable (name, value, time_expires) VALUES(?1, ?2, ?3)",
+        params!["brb", VariableValue::Text("old".to_string()), now - 1],
+    )?;
+    database.set_variable(&Variable::new(
+        "brb".to_string(),
+        VariableValue::Text("new".to_string()),
+    ))?;
+    assert_eq!(database.get_variable("brb")?.time_expires, None);
+    assert_eq!(database.delete_expired_variable("brb", now)?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_variable_watchers() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    let watcher = VariableWatcher {
+        id: 0,
+        variable: "deaths".to_string(),
+        template: "$new deaths!".to_string(),
+        target: MessageTarget::Discord("general".to_string()),
+        author: "twitch:stovoy".to_string(),
+    };
+    database.add_watcher(&watcher)?;
+    database.add_watcher(&VariableWatcher {
+        variable: "goal".to_string(),
+        ..watcher.clone()
+    })?;
+
+    let watchers = database.get_watchers(Some("deaths"))?;
+    assert_eq!(watchers.len(), 1);
+    assert_eq!(
+        watchers[0],
+        VariableWatcher {
+            id: watchers[0].id,
+            ..watcher
+        }
+    );
+    assert_eq!(database.get_watchers(None)?.len(), 2);
+    assert_eq!(database.delete_watcher(watchers[0].id)?, 1);
+    assert!(database.get_watchers(Some("deaths"))?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_counters() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    assert_eq!(database.increment_variable("deaths", 1)?, 1);
+    assert_eq!(database.increment_variable("deaths", 5)?, 6);
+    assert_eq!(database.increment_variable("deaths", -2)?, 4);
+    assert_eq!(
+        database.get_variable("deaths")?.value,
+        VariableValue::Text("4".to_string())
+    );
+    database.reset_variable("deaths")?;
+    assert_eq!(database.increment_variable("deaths", 1)?, 1);
+    database.set_variable(&Variable::new(
+        "deaths".to_string(),
+        VariableValue::Text("many".to_string()),
+    ))?;
+    assert!(database.increment_variable("deaths", 1).is_err());
+    assert_eq!(
+        database.get_variable("deaths")?.value,
+        VariableValue::Text("many".to_string())
+    );
+
+    database.set_variable(&Variable::new(
+        "list".to_string(),
+        VariableValue::StringList(vec![StringItem::new("a")]),
+    ))?;
+    assert!(database.increment_variable("list", 1).is_err());
+    database.reset_variable("list")?;
+    assert_eq!(
+        database.get_variable("list")?.value,
+        VariableValue::StringList(Vec::new())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_counters() -> Result<(), Error> {
+    with_test_db(|connection| {
+        // Two connections to the same file, like two scripts running at once.
+        let path = connection.path.clone();
+        let second = Database::connect(Some(path.clone()))?;
+        let threads: Vec<_> = vec![connection, second]
+            .into_iter()
+            .map(|database| {
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        database.increment_variable("deaths", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let database = Database::connect(Some(path))?;
+        assert_eq!(
+            database.get_variable("deaths")?.value,
+            VariableValue::Text("100".to_string())
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_script_limits() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    assert_eq!(database.get_script_limits()?, ScriptLimits::default());
+    let limits = ScriptLimits::parse("timeout=100 output=20").unwrap();
+    database.set_script_limits(&limits)?;
+    assert_eq!(database.get_script_limits()?, limits);
+
+    database.add_command(&Command::new("!test".to_string(), "test".to_string()))?;
+    database.set_command_limits("!test", &limits)?;
+    let command = database
+        .get_commands()?
+        .into_iter()
+        .find(|command| command.trigger == "!test")
+        .unwrap();
+    assert_eq!(command.limits.timeout_ms, Some(100));
+    assert_eq!(command.limits.max_operations, None);
+    Ok(())
+}
+
+#[test]
+fn test_http_cache() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    assert_eq!(database.get_http_settings()?, HttpSettings::default());
+    let url = "https://example.com/a";
+    assert_eq!(database.get_cached_response(url, 60)?, None);
+    database.set_cached_response(url, "one")?;
+    assert_eq!(
+        database.get_cached_response(url, 60)?,
+        Some("one".to_string())
+    );
+    database.set_cached_response(url, "two")?;
+    assert_eq!(
+        database.get_cached_response(url, 60)?,
+        Some("two".to_string())
+    );
+    database.connection.execute(
+        "UPDATE http_cache SET time_fetched = time_fetched - 120",
+        params![],
+    )?;
+    assert_eq!(database.get_cached_response(url, 60)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_output_cache() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    database.add_command(&Command::new("!test".to_string(), "test".to_string()))?;
+    let cache = CacheSettings::parse("ttl=60 key=user persist").unwrap();
+    database.set_command_cache("!test", Some(&cache))?;
+    let command = database
+        .get_commands()?
+        .into_iter()
+        .find(|command| command.trigger == "!test")
+        .unwrap();
+    assert_eq!(command.cache, Some(cache));
+
+    let now = time::get_time().sec;
+    database.set_cached_output("!test", "a", now + 60, "one")?;
+    database.set_cached_output("!test", "b", now - 1, "two")?;
+    assert_eq!(
+        database.get_cached_output("!test", "a", now)?,
+        Some((now + 60, "one".to_string()))
+    );
+    assert_eq!(database.get_cached_output("!test", "b", now)?, None);
+    database.clear_cached_output(Some("!test"), now)?;
+    assert_eq!(database.get_cached_output("!test", "a", now)?, None);
+
+    database.set_command_cache("!test", None)?;
+    let command = database
+        .get_commands()?
+        .into_iter()
+        .find(|command| command.trigger == "!test")
+        .unwrap();
+    assert_eq!(command.cache, None);
+    Ok(())
+}
+
+#[test]
+fn test_scheduled_tasks() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    let mut task = ScheduledTask {
+        id: 0,
+        time_due: 100,
+        repeat: "".to_string(),
+        target: MessageTarget::Twitch("stovoy".to_string()),
+        text: "the results are in".to_string(),
+        is_command: false,
+        username: "foo".to_string(),
+        author: "twitch:foo".to_string(),
+    };
+    database.add_scheduled_task(&task)?;
+    task.time_due = 200;
+    task.repeat = "every 1m".to_string();
+    database.add_scheduled_task(&task)?;
+    assert_eq!(database.count_scheduled_tasks("twitch:foo")?, 2);
+
+    let due = database.get_due_tasks(150)?;
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].text, "the results are in");
+    assert_eq!(due[0].target, MessageTarget::Twitch("stovoy".to_string()));
+
+    database.delete_scheduled_task(due[0].id)?;
+    let tasks = database.get_scheduled_tasks()?;
+    assert_eq!(tasks.len(), 1);
+    database.reschedule_task(tasks[0].id, 260)?;
+    assert!(database.get_due_tasks(250)?.is_empty());
+    assert_eq!(database.get_due_tasks(260)?[0].repeat, "every 1m");
+    Ok(())
+}
+
+#[test]
+fn test_user_variables() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    database.set_user_variable("twitch:foo", "points", "10")?;
+    database.set_user_variable("twitch:foo", "points", "15")?;
+    database.set_user_variable("twitch:foo", "birthday", "May 4")?;
+    database.set_user_variable("discord:1234", "points", "3")?;
+    assert_eq!(database.get_user_variable("twitch:foo", "points")?, "15");
+    assert_eq!(
+        database.get_user_variables("twitch:foo")?,
+        vec![
+            ("birthday".to_string(), "May 4".to_string()),
+            ("points".to_string(), "15".to_string())
+        ]
+    );
+    assert_eq!(database.delete_user_variables("twitch:foo")?, 2);
+    assert!(database.get_user_variable("twitch:foo", "points").is_err());
+    assert_eq!(database.get_user_variable("discord:1234", "points")?, "3");
+    Ok(())
+}
+
+#[test]
+fn test_command_state() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    let command = Command::new("!count".to_string(), "{{state_get(\"n\")}}".to_string());
+    database.add_command(&command)?;
+    database.set_command_state("!count", "n", "1")?;
+    database.set_command_state("!count", "n", "2")?;
+    database.set_command_state("!other", "n", "7")?;
+    assert_eq!(database.get_command_state("!count", "n")?, "2");
+    assert_eq!(
+        database.get_command_states("!count")?,
+        vec![("n".to_string(), "2".to_string())]
+    );
+    database.delete_command(&command)?;
+    assert!(database.get_command_states("!count")?.is_empty());
+    assert_eq!(database.get_command_state("!other", "n")?, "7");
+    Ok(())
+}
+
+#[test]
+fn test_script_tiers() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    assert_eq!(database.get_script_tier("twitch:foo")?, None);
+    assert_eq!(
+        ScriptTier::for_author("twitch:foo", Some(&database)),
+        ScriptTier::ReadOnly
+    );
+    database.set_script_tier("twitch:foo", ScriptTier::Network)?;
+    assert_eq!(
+        ScriptTier::for_author("twitch:foo", Some(&database)),
+        ScriptTier::Network
+    );
+    assert_eq!(ScriptTier::for_author("", None), ScriptTier::ReadOnly);
+    // The built in tiers are kept in the database, and go with whoever wrote the command.
+    let waifu = database.get_command("!waifu")?;
+    assert_eq!(
+        ScriptTier::for_command(&waifu, Some(&database)),
+        ScriptTier::Network
+    );
+    let quote = database.get_command("!quote")?;
+    assert_eq!(
+        ScriptTier::for_command(&quote, Some(&database)),
+        ScriptTier::ReadOnly
+    );
+    let command = Command::new("!waifu".to_string(), "{{waifu()}}".to_string())
+        .with_author("twitch:foo".to_string())
+        .build();
+    database.update_command(&command)?;
+    let waifu = database.get_command("!waifu")?;
+    assert_eq!(waifu.tier, None);
+    assert_eq!(
+        ScriptTier::for_command(&waifu, Some(&database)),
+        ScriptTier::Network
+    );
+    let command = Command::new("!old".to_string(), "{{waifu()}}".to_string());
+    assert_eq!(
+        ScriptTier::for_command(&command, Some(&database)),
+        ScriptTier::ReadOnly
+    );
+    assert_eq!(
+        ScriptTier::for_author("admin", Some(&database)),
+        ScriptTier::Actions
+    );
+    Ok(())
+}
+
+#[test]
+fn test_script_modules() -> Result<(), Error> {
+    let database = Database::new_in_memory()?;
+    let mut module = ScriptModule::new(
+        "format".to_string(),
+        "fn shout(x) { x + \"!\" }".to_string(),
+        "test:foo".to_string(),
+    );
+    database.set_script_module(&module)?;
+    module.source = "fn shout(x) { x + \"!!\" }".to_string();
+    database.set_script_module(&module)?;
+    assert_eq!(database.get_script_modules()?.len(), 1);
+    assert_eq!(database.get_script_module("format")?.source, module.source);
+    database.delete_script_module(&module)?;
+    assert!(database.get_script_module("format").is_err());
+    Ok(())
+}
+
 #[cfg(test)]
 pub fn with_test_db(block: fn(connection: Database) -> Result<(), Error>) -> Result<(), Error> {
     let mut rng = rand::thread_rng();
@@ -0,0 +1,387 @@
+use rand::Rng;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+const MAX_EXPLOSIONS: u32 = 100;
+
+/*
+Dice notation, a sum of terms separated by + or -:
+    3d6         roll three six-sided dice
+    d20         a single die
+    d%          a hundred-sided die
+    4d6kh3      keep the highest three (k3 is the same)
+    2d20kl1     keep the lowest one
+    3d6!        exploding dice, a max roll rolls again and adds
+    1d8+2d4-1   any mix of dice and flat modifiers
+*/
+
+// Seam for the randomness so tests can choose the rolls.
+pub trait DiceRng {
+    // Returns a value in 1..=sides.
+    fn roll_die(&mut self, sides: u32) -> u32;
+}
+
+impl<R: Rng> DiceRng for R {
+    fn roll_die(&mut self, sides: u32) -> u32 {
+        self.gen_range(1, sides + 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiceError {
+    Empty,
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    TooManyDice(u32),
+    TooManySides(u32),
+    NoSides,
+    BadKeep(u32),
+}
+
+impl Display for DiceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            DiceError::Empty => write!(f, "No dice to roll"),
+            DiceError::UnexpectedCharacter(c) => write!(f, "Unexpected '{}' in dice", c),
+            DiceError::UnexpectedEnd => write!(f, "Dice notation ended early"),
+            DiceError::TooManyDice(n) => write!(f, "Can't roll {} dice (max {})", n, MAX_DICE),
+            DiceError::TooManySides(n) => {
+                write!(f, "Can't roll a d{} (max d{})", n, MAX_SIDES)
+            }
+            DiceError::NoSides => write!(f, "Dice need at least one side"),
+            DiceError::BadKeep(n) => write!(f, "Can't keep {} dice", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Keep {
+    All,
+    Highest(u32),
+    Lowest(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DiceSpec {
+    count: u32,
+    sides: u32,
+    explode: bool,
+    keep: Keep,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Die {
+    pub value: u32,
+    pub kept: bool,
+    pub exploded: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RollTerm {
+    Dice(Vec<Die>),
+    Modifier(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Roll {
+    pub notation: String,
+    pub total: i64,
+    // Each term paired with whether it is subtracted.
+    pub terms: Vec<(bool, RollTerm)>,
+}
+
+impl Roll {
+    pub fn breakdown(&self) -> String {
+        let mut breakdown = String::new();
+        for (i, (negative, term)) in self.terms.iter().enumerate() {
+            if i > 0 {
+                breakdown += if *negative { " - " } else { " + " };
+            } else if *negative {
+                breakdown += "-";
+            }
+            match term {
+                RollTerm::Dice(dice) => {
+                    let dice: Vec<String> = dice
+                        .iter()
+                        .map(|die| {
+                            format!(
+                                "{}{}{}",
+                                if die.kept { "" } else { "~" },
+                                die.value,
+                                if die.exploded { "!" } else { "" }
+                            )
+                        })
+                        .collect();
+                    breakdown += &format!("[{}]", dice.join(", "));
+                }
+                RollTerm::Modifier(n) => breakdown += &n.to_string(),
+            }
+        }
+        breakdown
+    }
+}
+
+impl Display for Roll {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "{} ({}: {})",
+            self.total,
+            self.notation,
+            self.breakdown()
+        )
+    }
+}
+
+pub fn roll(notation: &str) -> Result<Roll, DiceError> {
+    roll_with(notation, &mut rand::thread_rng())
+}
+
+pub fn roll_with<R: DiceRng>(notation: &str, rng: &mut R) -> Result<Roll, DiceError> {
+    let notation: String = notation
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    let parsed = parse(&notation)?;
+
+    let mut total = 0;
+    let mut terms = Vec::with_capacity(parsed.len());
+    for (negative, term) in parsed {
+        let (value, term) = match term {
+            ParsedTerm::Number(n) => (n, RollTerm::Modifier(n)),
+            ParsedTerm::Dice(spec) => {
+                let dice = roll_dice(&spec, rng);
+                let value = dice
+                    .iter()
+                    .filter(|die| die.kept)
+                    .map(|die| die.value as i64)
+                    .sum();
+                (value, RollTerm::Dice(dice))
+            }
+        };
+        total += if negative { -value } else { value };
+        terms.push((negative, term));
+    }
+    Ok(Roll {
+        notation,
+        total,
+        terms,
+    })
+}
+
+fn roll_dice<R: DiceRng>(spec: &DiceSpec, rng: &mut R) -> Vec<Die> {
+    let mut dice = Vec::with_capacity(spec.count as usize);
+    for _ in 0..spec.count {
+        let mut value = rng.roll_die(spec.sides);
+        let mut exploded = false;
+        // A d1 would explode forever.
+        if spec.explode && spec.sides > 1 {
+            let mut explosions = 0;
+            let mut last = value;
+            while last == spec.sides && explosions < MAX_EXPLOSIONS {
+                last = rng.roll_die(spec.sides);
+                value += last;
+                explosions += 1;
+                exploded = true;
+            }
+        }
+        dice.push(Die {
+            value,
+            kept: true,
+            exploded,
+        });
+    }
+
+    let (keep, highest) = match spec.keep {
+        Keep::All => return dice,
+        Keep::Highest(n) => (n as usize, true),
+        Keep::Lowest(n) => (n as usize, false),
+    };
+    let mut order: Vec<usize> = (0..dice.len()).collect();
+    // Stable sort, so ties drop the later dice first.
+    order.sort_by(|a, b| {
+        if highest {
+            dice[*b].value.cmp(&dice[*a].value)
+        } else {
+            dice[*a].value.cmp(&dice[*b].value)
+        }
+    });
+    for index in order.into_iter().skip(keep) {
+        dice[index].kept = false;
+    }
+    dice
+}
+
+enum ParsedTerm {
+    Number(i64),
+    Dice(DiceSpec),
+}
+
+fn parse(notation: &str) -> Result<Vec<(bool, ParsedTerm)>, DiceError> {
+    let chars: Vec<char> = notation.chars().collect();
+    if chars.is_empty() {
+        return Err(DiceError::Empty);
+    }
+    let mut i = 0;
+    let mut terms = Vec::new();
+    loop {
+        let mut negative = false;
+        if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+            negative = chars[i] == '-';
+            i += 1;
+        }
+        let count = parse_number(&chars, &mut i);
+        if i < chars.len() && chars[i] == 'd' {
+            i += 1;
+            let count = count.unwrap_or(1);
+            let sides = if i < chars.len() && chars[i] == '%' {
+                i += 1;
+                100
+            } else {
+                match parse_number(&chars, &mut i) {
+                    Some(sides) => sides,
+                    None => return Err(next_error(&chars, i)),
+                }
+            };
+            let explode = i < chars.len() && chars[i] == '!';
+            if explode {
+                i += 1;
+            }
+            let keep = if i < chars.len() && chars[i] == 'k' {
+                i += 1;
+                let highest = match chars.get(i) {
+                    Some('h') => {
+                        i += 1;
+                        true
+                    }
+                    Some('l') => {
+                        i += 1;
+                        false
+                    }
+                    _ => true,
+                };
+                let n = match parse_number(&chars, &mut i) {
+                    Some(n) => n,
+                    None => return Err(next_error(&chars, i)),
+                };
+                if n == 0 || n > count {
+                    return Err(DiceError::BadKeep(n));
+                }
+                if highest {
+                    Keep::Highest(n)
+                } else {
+                    Keep::Lowest(n)
+                }
+            } else {
+                Keep::All
+            };
+            if count > MAX_DICE {
+                return Err(DiceError::TooManyDice(count));
+            }
+            if sides == 0 {
+                return Err(DiceError::NoSides);
+            }
+            if sides > MAX_SIDES {
+                return Err(DiceError::TooManySides(sides));
+            }
+            terms.push((
+                negative,
+                ParsedTerm::Dice(DiceSpec {
+                    count,
+                    sides,
+                    explode,
+                    keep,
+                }),
+            ));
+        } else {
+            match count {
+                Some(n) => terms.push((negative, ParsedTerm::Number(n as i64))),
+                None => return Err(next_error(&chars, i)),
+            }
+        }
+
+        if i >= chars.len() {
+            return Ok(terms);
+        }
+        if chars[i] != '+' && chars[i] != '-' {
+            return Err(DiceError::UnexpectedCharacter(chars[i]));
+        }
+    }
+}
+
+fn parse_number(chars: &[char], i: &mut usize) -> Option<u32> {
+    let start = *i;
+    let mut n: u32 = 0;
+    while *i < chars.len() && chars[*i].is_ascii_digit() {
+        n = n
+            .saturating_mul(10)
+            .saturating_add(chars[*i].to_digit(10).unwrap());
+        *i += 1;
+    }
+    if *i == start {
+        None
+    } else {
+        Some(n)
+    }
+}
+
+fn next_error(chars: &[char], i: usize) -> DiceError {
+    match chars.get(i) {
+        Some(c) => DiceError::UnexpectedCharacter(*c),
+        None => DiceError::UnexpectedEnd,
+    }
+}
+
+#[cfg(test)]
+struct FixedRolls(Vec<u32>);
+
+#[cfg(test)]
+impl DiceRng for FixedRolls {
+    fn roll_die(&mut self, sides: u32) -> u32 {
+        let value = self.0.remove(0);
+        assert!(value >= 1 && value <= sides);
+        value
+    }
+}
+
+#[test]
+fn test_roll_sum_and_modifier() {
+    let roll = roll_with("3d6+2", &mut FixedRolls(vec![4, 5, 3])).unwrap();
+    assert_eq!(roll.total, 14);
+    assert_eq!(roll.breakdown(), "[4, 5, 3] + 2");
+    assert_eq!(roll.to_string(), "14 (3d6+2: [4, 5, 3] + 2)");
+
+    let roll = roll_with("d20 - 1", &mut FixedRolls(vec![1])).unwrap();
+    assert_eq!(roll.total, 0);
+    assert_eq!(roll.to_string(), "0 (d20-1: [1] - 1)");
+}
+
+#[test]
+fn test_roll_keep() {
+    let roll = roll_with("4d6kh3", &mut FixedRolls(vec![2, 6, 2, 5])).unwrap();
+    assert_eq!(roll.total, 13);
+    assert_eq!(roll.breakdown(), "[2, 6, ~2, 5]");
+
+    let roll = roll_with("2d20kl1", &mut FixedRolls(vec![17, 3])).unwrap();
+    assert_eq!(roll.total, 3);
+    assert_eq!(roll.breakdown(), "[~17, 3]");
+}
+
+#[test]
+fn test_roll_exploding() {
+    let roll = roll_with("2d6!", &mut FixedRolls(vec![6, 6, 2, 3])).unwrap();
+    assert_eq!(roll.total, 17);
+    assert_eq!(roll.breakdown(), "[14!, 3]");
+}
+
+#[test]
+fn test_roll_errors() {
+    assert_eq!(roll(""), Err(DiceError::Empty));
+    assert_eq!(roll("3d"), Err(DiceError::UnexpectedEnd));
+    assert_eq!(roll("3x6"), Err(DiceError::UnexpectedCharacter('x')));
+    assert_eq!(roll("1000d6"), Err(DiceError::TooManyDice(1000)));
+    assert_eq!(roll("d0"), Err(DiceError::NoSides));
+    assert_eq!(roll("2d6k3"), Err(DiceError::BadKeep(3)));
+}
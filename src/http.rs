@@ -0,0 +1,176 @@
+use crate::database::Database;
+use crate::models::HttpSettings;
+use reqwest::blocking::Client;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::Read;
+use std::time::Duration;
+
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpError {
+    BadUrl(String),
+    NotAllowed(String),
+    Request(String),
+    Status(u16),
+    TooLarge(usize),
+    NotText,
+    Database(String),
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            HttpError::BadUrl(url) => write!(f, "Bad url {}", url),
+            HttpError::NotAllowed(host) => write!(f, "{} is not on the http allowlist", host),
+            HttpError::Request(e) => write!(f, "Request failed: {}", e),
+            HttpError::Status(status) => write!(f, "Server responded with {}", status),
+            HttpError::TooLarge(max) => write!(f, "Response over {} bytes", max),
+            HttpError::NotText => write!(f, "Response is not text"),
+            HttpError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+// Served from the cache when the same url was fetched within the TTL.
+pub fn get(url: &str, settings: &HttpSettings, database: &Database) -> Result<String, HttpError> {
+    if let Some(body) = get_cached(url, settings, database)? {
+        return Ok(body);
+    }
+    let url = Url::parse(url).map_err(|_| HttpError::BadUrl(url.to_string()))?;
+    let body = fetch(&url, settings)?;
+    database
+        .set_cached_response(url.as_str(), &body)
+        .map_err(|e| HttpError::Database(e.to_string()))?;
+    Ok(body)
+}
+
+// Checks the url like get does, but never goes to the network.
+pub fn get_cached(
+    url: &str,
+    settings: &HttpSettings,
+    database: &Database,
+) -> Result<Option<String>, HttpError> {
+    let url = Url::parse(url).map_err(|_| HttpError::BadUrl(url.to_string()))?;
+    check_url(&url, settings)?;
+    database
+        .get_cached_response(url.as_str(), settings.cache_ttl_secs())
+        .map_err(|e| HttpError::Database(e.to_string()))
+}
+
+fn check_url(url: &Url, settings: &HttpSettings) -> Result<(), HttpError> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(HttpError::BadUrl(url.to_string()));
+    }
+    match url.host_str() {
+        Some(host) if settings.allows(host) => Ok(()),
+        Some(host) => Err(HttpError::NotAllowed(host.to_string())),
+        None => Err(HttpError::BadUrl(url.to_string())),
+    }
+}
+
+fn fetch(url: &Url, settings: &HttpSettings) -> Result<String, HttpError> {
+    // Redirects must stay on the allowlist too.
+    let redirect_settings = settings.clone();
+    let client = Client::builder()
+        .timeout(Duration::from_millis(settings.timeout_ms()))
+        .redirect(Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS
+                || check_url(attempt.url(), &redirect_settings).is_err()
+            {
+                attempt.stop()
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()
+        .map_err(|e| HttpError::Request(e.to_string()))?;
+    let response = client
+        .get(url.clone())
+        .send()
+        .map_err(|e| HttpError::Request(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(HttpError::Status(response.status().as_u16()));
+    }
+
+    let max = settings.max_response_bytes();
+    if response.content_length().unwrap_or(0) > max as u64 {
+        return Err(HttpError::TooLarge(max));
+    }
+    // The length header can be missing or wrong, so read one byte past the limit to be sure.
+    let mut body = Vec::new();
+    response
+        .take(max as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| HttpError::Request(e.to_string()))?;
+    if body.len() > max {
+        return Err(HttpError::TooLarge(max));
+    }
+    String::from_utf8(body).map_err(|_| HttpError::NotText)
+}
+
+// A local stand-in that answers each connection with the next body, then stops.
+#[cfg(test)]
+pub fn serve(bodies: Vec<&'static str>) -> String {
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for (body, stream) in bodies.into_iter().zip(listener.incoming()) {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    });
+    address
+}
+
+#[cfg(test)]
+fn local_settings() -> HttpSettings {
+    HttpSettings {
+        allowed_domains: vec!["127.0.0.1".to_string()],
+        ..HttpSettings::default()
+    }
+}
+
+#[test]
+fn test_get_and_cache() -> Result<(), rusqlite::Error> {
+    let database = Database::new_in_memory()?;
+    // Only one response is served, so the second get must come from the cache.
+    let url = format!("{}/weather", serve(vec!["sunny"]));
+    let settings = local_settings();
+    assert_eq!(get(&url, &settings, &database), Ok("sunny".to_string()));
+    assert_eq!(get(&url, &settings, &database), Ok("sunny".to_string()));
+    Ok(())
+}
+
+#[test]
+fn test_get_limits() -> Result<(), rusqlite::Error> {
+    let database = Database::new_in_memory()?;
+    assert_eq!(
+        get("https://example.com/", &local_settings(), &database),
+        Err(HttpError::NotAllowed("example.com".to_string()))
+    );
+    assert_eq!(
+        get("file:///etc/passwd", &local_settings(), &database),
+        Err(HttpError::BadUrl("file:///etc/passwd".to_string()))
+    );
+
+    let url = serve(vec!["far too long"]);
+    let settings = HttpSettings {
+        max_response_bytes: Some(4),
+        ..local_settings()
+    };
+    assert_eq!(get(&url, &settings, &database), Err(HttpError::TooLarge(4)));
+    Ok(())
+}
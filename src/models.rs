@@ -1,9 +1,12 @@
 use crate::database::Database;
+use chrono::{TimeZone, Utc};
+use regex::{Captures, Regex};
 use serde::export::fmt::Error;
 use serde::export::Formatter;
 use serde::{Deserialize, Serialize};
 use serenity::model::channel::Message as DiscordMessage;
 use serenity::prelude::Context as DiscordContext;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::sync::{Arc, Mutex};
@@ -29,6 +32,41 @@ pub enum Action {
     EditVariable(Variable, EditType),
     DeleteVariable(Variable),
     SendLiveNotification,
+    SendMessage(MessageTarget, String),
+    AddModule(ScriptModule),
+    EditModule(ScriptModule),
+    DeleteModule(ScriptModule),
+    // None sets the global limits.
+    SetScriptLimits(Option<String>, ScriptLimits),
+    // Message text to run as a dry run, optionally against an unsaved response.
+    TestCommand(String, Option<String>),
+    // Runs a script straight away, for debugging.
+    EvalScript(String),
+    AllowHttpDomain(String),
+    DisallowHttpDomain(String),
+    // Only the limits are set, the allowed domains are kept.
+    SetHttpLimits(HttpSettings),
+    ScheduleTask(ScheduledTask),
+    DeleteScheduledTask(i32),
+    ListScheduledTasks,
+    // A user identity, like "twitch:name" or "discord:id".
+    ShowUserVariables(String),
+    ClearUserVariables(String),
+    // Pages start at 1.
+    ShowCommand(String, usize),
+    // A variable, or one item of a list, and the page.
+    ShowVariable(String, Option<usize>, usize),
+    // A list, the words to search it for, and the page.
+    FindInVariable(String, String, usize),
+    SetScriptTier(String, ScriptTier),
+    // None turns the cache off.
+    SetOutputCache(String, Option<CacheSettings>),
+    // None clears every command's cached output.
+    ClearOutputCache(Option<String>),
+    AddWatcher(VariableWatcher),
+    DeleteWatcher(i32),
+    // A variable's watchers, or None for all of them.
+    ListWatchers(Option<String>),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -38,6 +76,14 @@ pub enum EditType {
     Remove(),
     InsertAt(usize),
     RemoveAt(usize),
+    // Counters, applied atomically by the database.
+    Increment(i64),
+    Decrement(i64),
+    // Counters go back to 0, and lists and maps are emptied.
+    Reset(),
+    // Keys of a map.
+    SetKey(String),
+    RemoveKey(String),
 }
 
 #[derive(Debug)]
@@ -59,6 +105,35 @@ pub enum ActionError {
     VariableBadEditIndexValue,
     PermissionDenied,
     NotificationChannelNotFound,
+    BadScriptLimits(String),
+    MessageTargetNotFound,
+    BadModule(String),
+    BadModuleSource(String),
+    ModuleAlreadyExists,
+    ModuleDoesNotExist,
+    BadHttpSettings(String),
+    HttpDomainNotAllowed,
+    BadSchedule(String),
+    TooManyScheduledTasks,
+    ScheduledTaskDoesNotExist,
+    BadUserIdentity(String),
+    BadScriptTier(String),
+    BadCacheSettings(String),
+    PageDoesNotExist,
+    BadVariableTtl(String),
+    BadMessageTarget(String),
+    WatcherDoesNotExist,
+    VariableKeyDoesNotExist,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageTarget {
+    // A channel name.
+    Twitch(String),
+    // A channel id or name.
+    Discord(String),
+    // The admin console.
+    Admin,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -66,6 +141,8 @@ pub struct Command {
     pub id: i32,
     #[serde(with = "TimespecDef")]
     pub time_created: Timespec,
+    #[serde(with = "TimespecDef")]
+    pub time_modified: Timespec,
     pub trigger: String,
     pub response: String,
     #[serde(skip)]
@@ -73,6 +150,22 @@ pub struct Command {
     #[serde(skip)]
     pub database_path: String,
     pub is_alias: bool,
+    // Identity of whoever last added or edited the command, see Message::sender_identity.
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub limits: ScriptLimits,
+    #[serde(default)]
+    pub cache: Option<CacheSettings>,
+    // What its scripts run with instead of the author's tier, for built in and imported commands.
+    #[serde(default)]
+    pub tier: Option<ScriptTier>,
+    // Runs scripts without keeping their variable writes or performing their actions.
+    #[serde(skip)]
+    pub dry_run: bool,
+    // Values for $old and $new in a watcher's template, given to its scripts as input(name).
+    #[serde(skip)]
+    pub inputs: BTreeMap<String, String>,
 }
 
 pub struct Message {
@@ -86,12 +179,15 @@ pub struct User {
     pub username: String,
 }
 
+#[derive(Clone)]
 pub enum Source {
     #[cfg(test)]
     None,
     Admin,
     Twitch(TwitchWriter, String),
     Discord(Box<Arc<Mutex<DiscordContext>>>, Box<DiscordMessage>),
+    // A scheduled task, with the identity of whoever scheduled it. Never admin.
+    Scheduled(MessageTarget, String),
 }
 
 impl Command {
@@ -99,11 +195,18 @@ impl Command {
         Command {
             id: 0,
             time_created: time::empty_tm().to_timespec(),
+            time_modified: time::empty_tm().to_timespec(),
             trigger,
             response,
             actor: None,
             database_path: Database::default_path(),
             is_alias: false,
+            author: "".to_string(),
+            limits: ScriptLimits::default(),
+            cache: None,
+            tier: None,
+            dry_run: false,
+            inputs: BTreeMap::new(),
         }
     }
 
@@ -111,11 +214,18 @@ impl Command {
         Command {
             id: 0,
             time_created: time::empty_tm().to_timespec(),
+            time_modified: time::empty_tm().to_timespec(),
             trigger,
             response: alias,
             actor: None,
             database_path: Database::default_path(),
             is_alias: true,
+            author: "".to_string(),
+            limits: ScriptLimits::default(),
+            cache: None,
+            tier: None,
+            dry_run: false,
+            inputs: BTreeMap::new(),
         }
     }
 
@@ -130,6 +240,26 @@ impl Command {
         self
     }
 
+    pub fn with_author(&mut self, author: String) -> &mut Command {
+        self.author = author;
+        self
+    }
+
+    pub fn with_dry_run(&mut self, dry_run: bool) -> &mut Command {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn with_tier(&mut self, tier: ScriptTier) -> &mut Command {
+        self.tier = Some(tier);
+        self
+    }
+
+    pub fn with_input(&mut self, name: &str, value: String) -> &mut Command {
+        self.inputs.insert(name.to_string(), value);
+        self
+    }
+
     pub fn build(&self) -> Command {
         self.clone()
     }
@@ -148,31 +278,538 @@ impl Command {
                  \"Don't count on it.\"]; \
                  responses[floor(random() * len(responses))]\
                  }}".to_string(),
-            ),
+            )
+            .with_tier(ScriptTier::ReadOnly)
+            .build(),
             Command::new(
                 "!quote".to_string(),
                 "{{\
                 let quotes = get_list(\"quotes\"); \
                 let i = int(\"$1\"); if i == 0 { i = random_index(quotes) } else { i -= 1 } \
-                \"#\" + string(i + 1) + \": \" + quotes[i]\
+                let item = get_item(\"quotes\", i); \
+                \"#\" + string(i + 1) + \": \\\"\" + item.value + \"\\\"\" + item.credit\
                 }}".to_string(),
-            ),
+            )
+            .with_tier(ScriptTier::ReadOnly)
+            .build(),
             Command::new_alias(
                 "!quote add".to_string(),
-                "!variable edit quotes+ [$text]".to_string(),
-            ),
+                "!variable edit quotes+ --item $text".to_string(),
+            )
+            .with_tier(ScriptTier::ReadOnly)
+            .build(),
+            Command::new(
+                "!quote search".to_string(),
+                "{{\
+                let found = search(\"quotes\", \"$text\"); \
+                if len(found) == 0 { \"No quotes match\" } else { \
+                let text = \"Matching quotes:\"; \
+                for i in found { text += \" #\" + string(i + 1) } \
+                text }\
+                }}".to_string(),
+            )
+            .with_tier(ScriptTier::ReadOnly)
+            .build(),
             Command::new_alias(
                 "!quote remove".to_string(),
                 "!variable edit quotes-# {{int(\"$text\") - 1}}".to_string(),
-            ),
+            )
+            .with_tier(ScriptTier::ReadOnly)
+            .build(),
             Command::new(
                 "!waifu".to_string(),
                 "{{\"@$user \" + upload_image(waifu())}}".to_string(),
-            ),
+            )
+            .with_tier(ScriptTier::Network)
+            .build(),
         ]
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScriptErrorKind {
+    Parse,
+    Runtime,
+    OutputType,
+    Timeout,
+    TooManyOperations,
+    DataTooLarge,
+    OutputTooLarge,
+    Crash,
+    IO,
+}
+
+// Unset fields fall back to the global limits, then to the defaults below.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptLimits {
+    pub timeout_ms: Option<u64>,
+    pub max_operations: Option<u64>,
+    pub max_string_length: Option<usize>,
+    pub max_array_length: Option<usize>,
+    // Counted without any uploaded images.
+    pub max_output_length: Option<usize>,
+}
+
+impl ScriptLimits {
+    pub fn merge(&self, fallback: &ScriptLimits) -> ScriptLimits {
+        ScriptLimits {
+            timeout_ms: self.timeout_ms.or(fallback.timeout_ms),
+            max_operations: self.max_operations.or(fallback.max_operations),
+            max_string_length: self.max_string_length.or(fallback.max_string_length),
+            max_array_length: self.max_array_length.or(fallback.max_array_length),
+            max_output_length: self.max_output_length.or(fallback.max_output_length),
+        }
+    }
+
+    pub fn timeout_ms(&self) -> u64 {
+        self.timeout_ms.unwrap_or(1500)
+    }
+
+    pub fn max_operations(&self) -> u64 {
+        self.max_operations.unwrap_or(5_000_000)
+    }
+
+    // Large enough for an uploaded image.
+    pub fn max_string_length(&self) -> usize {
+        self.max_string_length.unwrap_or(4 * 1024 * 1024)
+    }
+
+    pub fn max_array_length(&self) -> usize {
+        self.max_array_length.unwrap_or(100_000)
+    }
+
+    pub fn max_output_length(&self) -> usize {
+        self.max_output_length.unwrap_or(2000)
+    }
+
+    // Parses "timeout=3000 operations=100000 strings=1000 arrays=100 output=500".
+    pub fn parse(text: &str) -> Result<ScriptLimits, ActionError> {
+        let mut limits = ScriptLimits::default();
+        for part in text.split_whitespace() {
+            let mut pair = part.splitn(2, '=');
+            let key = pair.next().unwrap_or("");
+            let value = match pair.next().map(|value| value.parse::<u64>()) {
+                Some(Ok(value)) => value,
+                _ => return Err(ActionError::BadScriptLimits(part.to_string())),
+            };
+            match key {
+                "timeout" => limits.timeout_ms = Some(value),
+                "operations" => limits.max_operations = Some(value),
+                "strings" => limits.max_string_length = Some(value as usize),
+                "arrays" => limits.max_array_length = Some(value as usize),
+                "output" => limits.max_output_length = Some(value as usize),
+                _ => return Err(ActionError::BadScriptLimits(part.to_string())),
+            }
+        }
+        Ok(limits)
+    }
+}
+
+impl Display for ScriptLimits {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.write_str(&format!(
+            "timeout={} operations={} strings={} arrays={} output={}",
+            self.timeout_ms(),
+            self.max_operations(),
+            self.max_string_length(),
+            self.max_array_length(),
+            self.max_output_length()
+        ))
+    }
+}
+
+// Reuses a command's output until the TTL runs out, instead of rendering it again.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheSettings {
+    pub ttl_secs: u64,
+    pub key: CacheKey,
+    // Also kept in the database, so the output survives a restart.
+    pub persist: bool,
+}
+
+// What has to match for a cached output to be reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CacheKey {
+    // The text after the trigger.
+    Arguments,
+    // Whoever triggered the command.
+    User,
+    // Everyone gets the same output.
+    None,
+}
+
+impl CacheSettings {
+    // Parses "ttl=300 key=args|user|none persist". The key defaults to args.
+    pub fn parse(text: &str) -> Result<CacheSettings, ActionError> {
+        let mut ttl_secs = None;
+        let mut settings = CacheSettings {
+            ttl_secs: 0,
+            key: CacheKey::Arguments,
+            persist: false,
+        };
+        for part in text.split_whitespace() {
+            let mut pair = part.splitn(2, '=');
+            match (pair.next().unwrap_or(""), pair.next()) {
+                ("ttl", Some(value)) => match value.parse::<u64>() {
+                    Ok(value) if value > 0 => ttl_secs = Some(value),
+                    _ => return Err(ActionError::BadCacheSettings(part.to_string())),
+                },
+                ("key", Some("args")) => settings.key = CacheKey::Arguments,
+                ("key", Some("user")) => settings.key = CacheKey::User,
+                ("key", Some("none")) => settings.key = CacheKey::None,
+                ("persist", None) => settings.persist = true,
+                _ => return Err(ActionError::BadCacheSettings(part.to_string())),
+            }
+        }
+        match ttl_secs {
+            Some(ttl_secs) => Ok(CacheSettings {
+                ttl_secs,
+                ..settings
+            }),
+            None => Err(ActionError::BadCacheSettings("No ttl".to_string())),
+        }
+    }
+
+    pub fn key(&self, arguments: &str, user: &str) -> String {
+        match self.key {
+            CacheKey::Arguments => arguments.trim().to_string(),
+            CacheKey::User => user.to_string(),
+            CacheKey::None => "".to_string(),
+        }
+    }
+}
+
+impl Display for CacheSettings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.write_str(&format!(
+            "ttl={} key={}{}",
+            self.ttl_secs,
+            match self.key {
+                CacheKey::Arguments => "args",
+                CacheKey::User => "user",
+                CacheKey::None => "none",
+            },
+            if self.persist { " persist" } else { "" }
+        ))
+    }
+}
+
+// How far the scripts in a command can reach, from the trust in its author. Each tier
+// includes the ones before it. Scripts can always read variables and keep their own
+// command state and user variables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ScriptTier {
+    ReadOnly,
+    // set, push and the other global variable writes.
+    VariableWrite,
+    // http_get, waifu and upload_image.
+    Network,
+    // send_message, add_command, schedule and the other requested actions.
+    Actions,
+}
+
+impl Default for ScriptTier {
+    fn default() -> Self {
+        ScriptTier::ReadOnly
+    }
+}
+
+impl ScriptTier {
+    pub fn parse(text: &str) -> Result<ScriptTier, ActionError> {
+        match text.trim().to_lowercase().as_ref() {
+            "read" => Ok(ScriptTier::ReadOnly),
+            "write" => Ok(ScriptTier::VariableWrite),
+            "network" => Ok(ScriptTier::Network),
+            "actions" => Ok(ScriptTier::Actions),
+            _ => Err(ActionError::BadScriptTier(text.to_string())),
+        }
+    }
+
+    // Commands from before authors were kept have neither, so they can only read.
+    pub fn for_command(command: &Command, database: Option<&Database>) -> ScriptTier {
+        match command.tier {
+            Some(tier) => tier,
+            None => ScriptTier::for_author(&command.author, database),
+        }
+    }
+
+    // Admins are fully trusted. Everyone else has the tier they were given, or can only read.
+    pub fn for_author(author: &str, database: Option<&Database>) -> ScriptTier {
+        if is_admin_identity(author) {
+            return ScriptTier::Actions;
+        }
+        database
+            .and_then(|database| database.get_script_tier(author).ok().flatten())
+            .unwrap_or_default()
+    }
+}
+
+impl Display for ScriptTier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.write_str(match self {
+            ScriptTier::ReadOnly => "read",
+            ScriptTier::VariableWrite => "write",
+            ScriptTier::Network => "network",
+            ScriptTier::Actions => "actions",
+        })
+    }
+}
+
+fn location_regex() -> Regex {
+    Regex::new(r"line:? (\d+),? pos(?:ition)?:? (\d+)").unwrap()
+}
+
+// Matches Message::is_admin, for identities from Message::sender_identity.
+pub fn is_admin_identity(identity: &str) -> bool {
+    identity == "admin" || identity == "twitch:stovoy"
+}
+
+// Where http_get may reach, and how much it may fetch.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpSettings {
+    // A domain also allows its subdomains.
+    pub allowed_domains: Vec<String>,
+    pub timeout_ms: Option<u64>,
+    pub max_response_bytes: Option<usize>,
+    pub cache_ttl_secs: Option<u64>,
+}
+
+impl HttpSettings {
+    pub fn timeout_ms(&self) -> u64 {
+        self.timeout_ms.unwrap_or(1000)
+    }
+
+    pub fn max_response_bytes(&self) -> usize {
+        self.max_response_bytes.unwrap_or(64 * 1024)
+    }
+
+    pub fn cache_ttl_secs(&self) -> u64 {
+        self.cache_ttl_secs.unwrap_or(60)
+    }
+
+    pub fn allows(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        self.allowed_domains
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+    }
+
+    // Parses "timeout=500 size=10000 ttl=300", without any domains.
+    pub fn parse_limits(text: &str) -> Result<HttpSettings, ActionError> {
+        let mut settings = HttpSettings::default();
+        for part in text.split_whitespace() {
+            let mut pair = part.splitn(2, '=');
+            let key = pair.next().unwrap_or("");
+            let value = match pair.next().map(|value| value.parse::<u64>()) {
+                Some(Ok(value)) => value,
+                _ => return Err(ActionError::BadHttpSettings(part.to_string())),
+            };
+            match key {
+                "timeout" => settings.timeout_ms = Some(value),
+                "size" => settings.max_response_bytes = Some(value as usize),
+                "ttl" => settings.cache_ttl_secs = Some(value),
+                _ => return Err(ActionError::BadHttpSettings(part.to_string())),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+impl Display for HttpSettings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.write_str(&format!(
+            "domains=[{}] timeout={} size={} ttl={}",
+            self.allowed_domains.join(", "),
+            self.timeout_ms(),
+            self.max_response_bytes(),
+            self.cache_ttl_secs()
+        ))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptError {
+    pub kind: ScriptErrorKind,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    // The source line the error points at.
+    pub snippet: Option<String>,
+    // Set when the line is in an imported module rather than the script itself.
+    #[serde(default)]
+    pub module: Option<String>,
+}
+
+// Requested by a script, then checked and performed by the bot like a chat command would be.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScriptAction {
+    SendMessage(MessageTarget, String),
+    AddCommand(String, String),
+    EditCommand(String, String),
+    DeleteCommand(String),
+    DeleteVariable(String),
+    SendLiveNotification,
+    // Seconds to wait, then the text to say where the command was triggered.
+    SayAfter(u64, String),
+    // A schedule (see schedule.rs), then the command to run there.
+    Schedule(String, String),
+}
+
+// What the script_engine process writes to stdout, as JSON.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScriptOutput {
+    pub result: Result<String, ScriptError>,
+    #[serde(default)]
+    pub actions: Vec<ScriptAction>,
+    // What the script wrote. A dry run's writes are rolled back, and its user variables and
+    // command state are included.
+    #[serde(default)]
+    pub variable_changes: Vec<VariableChange>,
+}
+
+impl ScriptOutput {
+    pub fn error(error: ScriptError) -> ScriptOutput {
+        ScriptOutput {
+            result: Err(error),
+            actions: Vec::new(),
+            variable_changes: Vec::new(),
+        }
+    }
+}
+
+// A variable a script added (no old), changed, or deleted (no new).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VariableChange {
+    pub name: String,
+    pub old: Option<VariableValue>,
+    pub new: Option<VariableValue>,
+}
+
+impl VariableChange {
+    pub fn between(before: &[Variable], after: &[Variable]) -> Vec<VariableChange> {
+        let mut changes = Vec::new();
+        for variable in after {
+            let old = before.iter().find(|old| old.name == variable.name);
+            if old.map(|old| &old.value) != Some(&variable.value) {
+                changes.push(VariableChange {
+                    name: variable.name.clone(),
+                    old: old.map(|old| old.value.clone()),
+                    new: Some(variable.value.clone()),
+                });
+            }
+        }
+        for variable in before {
+            if !after.iter().any(|new| new.name == variable.name) {
+                changes.push(VariableChange {
+                    name: variable.name.clone(),
+                    old: Some(variable.value.clone()),
+                    new: None,
+                });
+            }
+        }
+        changes
+    }
+}
+
+impl Display for VariableChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match (&self.old, &self.new) {
+            (None, Some(new)) => f.write_str(&format!("{} = {}", self.name, new)),
+            (Some(old), Some(new)) => f.write_str(&format!("{}: {} -> {}", self.name, old, new)),
+            (Some(_), None) => f.write_str(&format!("{} deleted", self.name)),
+            (None, None) => f.write_str(&self.name),
+        }
+    }
+}
+
+impl ScriptError {
+    pub fn new(kind: ScriptErrorKind, message: String) -> ScriptError {
+        ScriptError {
+            kind,
+            message,
+            line: None,
+            column: None,
+            snippet: None,
+            module: None,
+        }
+    }
+
+    // Fills in the location and snippet from a message like "... (line 2, position 5)".
+    pub fn with_location(mut self, script: &str) -> ScriptError {
+        if let Some(captures) = location_regex().captures(&self.message) {
+            let line: usize = captures[1].parse().unwrap_or(0);
+            let column: usize = captures[2].parse().unwrap_or(0);
+            self.line = Some(line);
+            self.column = Some(column);
+            self.snippet = script
+                .lines()
+                .nth(line.saturating_sub(1))
+                .map(|snippet| snippet.to_string());
+        }
+        self
+    }
+
+    // For a location in a module that was appended to the script, starting at first_line.
+    pub fn in_module(mut self, name: &str, first_line: usize) -> ScriptError {
+        if let Some(line) = self.line {
+            let line = line + 1 - first_line;
+            self.message = location_regex()
+                .replace(&self.message, |captures: &Captures| {
+                    format!(
+                        "{} in module {}",
+                        captures[0].replacen(&captures[1], &line.to_string(), 1),
+                        name
+                    )
+                })
+                .to_string();
+            self.line = Some(line);
+            self.module = Some(name.to_string());
+        }
+        self
+    }
+
+    // For chat, where anyone can see it.
+    pub fn short(&self) -> String {
+        let mut message: String = self.message.lines().next().unwrap_or("").to_string();
+        if message.chars().count() > 80 {
+            message = message.chars().take(77).collect::<String>() + "...";
+        }
+        match (self.line, &self.module) {
+            (Some(line), Some(module)) => format!(
+                "Script Error ({:?}, module {} line {}): {}",
+                self.kind, module, line, message
+            ),
+            (Some(line), None) => {
+                format!("Script Error ({:?}, line {}): {}", self.kind, line, message)
+            }
+            (None, _) => format!("Script Error ({:?}): {}", self.kind, message),
+        }
+    }
+
+    // For the command author, with the offending line pointed out.
+    pub fn diagnostic(&self) -> String {
+        let mut diagnostic = format!("{:?} error: {}", self.kind, self.message);
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            match &self.module {
+                Some(module) => {
+                    diagnostic += &format!(
+                        "\n  --> module {}, line {}, column {}",
+                        module, line, column
+                    )
+                }
+                None => diagnostic += &format!("\n  --> line {}, column {}", line, column),
+            }
+        }
+        if let Some(snippet) = &self.snippet {
+            diagnostic += &format!("\n   | {}", snippet);
+            if let Some(column) = self.column {
+                diagnostic += &format!("\n   | {}^", " ".repeat(column.saturating_sub(1)));
+            }
+        }
+        diagnostic
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(remote = "Timespec")]
 pub struct TimespecDef {
@@ -189,6 +826,9 @@ pub struct Variable {
     pub time_modified: Timespec,
     pub name: String,
     pub value: VariableValue,
+    // Unix time when it's deleted, for temporary values. Edits keep it.
+    #[serde(default)]
+    pub time_expires: Option<i64>,
 }
 
 impl Variable {
@@ -199,6 +839,60 @@ impl Variable {
             time_modified: time::empty_tm().to_timespec(),
             name,
             value,
+            time_expires: None,
+        }
+    }
+}
+
+// Said or run by the bot once time_due (unix seconds) passes, then rescheduled if it repeats.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledTask {
+    pub id: i32,
+    pub time_due: i64,
+    // The schedule it repeats on, or empty to run once.
+    pub repeat: String,
+    pub target: MessageTarget,
+    pub text: String,
+    // Runs the text as a command instead of saying it.
+    pub is_command: bool,
+    pub username: String,
+    pub author: String,
+}
+
+// Runs its template whenever the variable is added or edited, from chat or by a script, and
+// says the output to the target unless it's empty. $old and $new in the template are the value before and
+// after, and scripts in it run with the author's tier.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VariableWatcher {
+    pub id: i32,
+    pub variable: String,
+    pub template: String,
+    pub target: MessageTarget,
+    pub author: String,
+}
+
+// Shared rhai source that scripts pull in with `import "name";`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptModule {
+    pub id: i32,
+    #[serde(with = "TimespecDef")]
+    pub time_created: Timespec,
+    #[serde(with = "TimespecDef")]
+    pub time_modified: Timespec,
+    pub name: String,
+    pub source: String,
+    pub author: String,
+}
+
+impl ScriptModule {
+    pub fn new(name: String, source: String, author: String) -> ScriptModule {
+        ScriptModule {
+            id: 0,
+            time_created: time::empty_tm().to_timespec(),
+            time_modified: time::empty_tm().to_timespec(),
+            name,
+            source,
+            author,
         }
     }
 }
@@ -207,6 +901,8 @@ impl Variable {
 pub enum VariableValue {
     Text(String),
     StringList(Vec<StringItem>),
+    // Text by key, edited from chat with !variable edit <name>.<key>.
+    Map(BTreeMap<String, String>),
 }
 
 impl Display for VariableValue {
@@ -218,6 +914,9 @@ impl Display for VariableValue {
             VariableValue::StringList(value) => {
                 f.write_str(format!("{:?}", value).as_ref())?;
             }
+            VariableValue::Map(value) => {
+                f.write_str(format!("{:?}", value).as_ref())?;
+            }
         }
         Ok(())
     }
@@ -228,6 +927,15 @@ pub struct StringItem {
     #[serde(with = "TimespecDef")]
     pub time_created: Timespec,
     pub value: String,
+    // Who added the item from chat, and where. Empty for items added by scripts, or before
+    // these were kept.
+    #[serde(default)]
+    pub added_by: String,
+    #[serde(default)]
+    pub added_in: Option<MessageTarget>,
+    // Free-form, like the game being played.
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
 }
 
 impl StringItem {
@@ -235,6 +943,24 @@ impl StringItem {
         StringItem {
             time_created: time::get_time(),
             value: item.to_string(),
+            added_by: "".to_string(),
+            added_in: None,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    pub fn date(&self) -> String {
+        Utc.timestamp(self.time_created.sec, 0)
+            .format("%Y-%m-%d")
+            .to_string()
+    }
+
+    // " — added by X on 2026-01-02", or nothing when it isn't known who added the item.
+    pub fn credit(&self) -> String {
+        if self.added_by.is_empty() {
+            "".to_string()
+        } else {
+            format!(" — added by {} on {}", self.added_by, self.date())
         }
     }
 }
@@ -0,0 +1,133 @@
+use crate::database::Database;
+use crate::models::CacheSettings;
+use std::collections::HashMap;
+
+// Past this many entries, new output is only cached once older entries expire.
+const MAX_ENTRIES: usize = 1000;
+
+// Rendered command output by trigger and cache key. Persisted entries are also kept in the
+// database and loaded from there after a restart.
+pub struct OutputCache {
+    entries: HashMap<(String, String), (i64, String)>,
+}
+
+impl OutputCache {
+    pub fn new() -> OutputCache {
+        OutputCache {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(
+        &mut self,
+        trigger: &str,
+        key: &str,
+        settings: &CacheSettings,
+        database: &Database,
+        now: i64,
+    ) -> Option<String> {
+        let entry_key = (trigger.to_string(), key.to_string());
+        match self.entries.get(&entry_key) {
+            Some((time_expires, text)) if *time_expires > now => return Some(text.clone()),
+            Some(_) => {
+                self.entries.remove(&entry_key);
+            }
+            None => {}
+        }
+        if !settings.persist {
+            return None;
+        }
+        match database.get_cached_output(trigger, key, now) {
+            Ok(Some((time_expires, text))) => {
+                self.entries.insert(entry_key, (time_expires, text.clone()));
+                Some(text)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                println!("Error reading cached output for {}: {}", trigger, e);
+                None
+            }
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        trigger: &str,
+        key: &str,
+        text: &str,
+        settings: &CacheSettings,
+        database: &Database,
+        now: i64,
+    ) {
+        let time_expires = now + settings.ttl_secs as i64;
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries
+                .retain(|_, (time_expires, _)| *time_expires > now);
+        }
+        if self.entries.len() < MAX_ENTRIES {
+            self.entries.insert(
+                (trigger.to_string(), key.to_string()),
+                (time_expires, text.to_string()),
+            );
+        }
+        if settings.persist {
+            if let Err(e) = database.set_cached_output(trigger, key, time_expires, text) {
+                println!("Error caching output for {}: {}", trigger, e)
+            }
+        }
+    }
+
+    // None clears every command's output.
+    pub fn clear(&mut self, trigger: Option<&str>, database: &Database, now: i64) {
+        match trigger {
+            Some(trigger) => self.entries.retain(|(cached, _), _| cached != trigger),
+            None => self.entries.clear(),
+        }
+        if let Err(e) = database.clear_cached_output(trigger, now) {
+            println!("Error clearing cached output: {}", e)
+        }
+    }
+}
+
+#[test]
+fn test_output_cache() -> Result<(), rusqlite::Error> {
+    let database = Database::new_in_memory()?;
+    let mut cache = OutputCache::new();
+    let settings = CacheSettings::parse("ttl=60").unwrap();
+    cache.insert("!waifu", "", "image", &settings, &database, 1000);
+    assert_eq!(
+        cache.get("!waifu", "", &settings, &database, 1059),
+        Some("image".to_string())
+    );
+    assert_eq!(
+        cache.get("!waifu", "other", &settings, &database, 1000),
+        None
+    );
+    assert_eq!(cache.get("!waifu", "", &settings, &database, 1060), None);
+
+    cache.insert("!waifu", "", "image", &settings, &database, 1000);
+    cache.clear(Some("!waifu"), &database, 1000);
+    assert_eq!(cache.get("!waifu", "", &settings, &database, 1000), None);
+    Ok(())
+}
+
+#[test]
+fn test_persisted_output_cache() -> Result<(), rusqlite::Error> {
+    let database = Database::new_in_memory()?;
+    let settings = CacheSettings::parse("ttl=60 key=user persist").unwrap();
+    let now = time::get_time().sec;
+    OutputCache::new().insert("!weather", "twitch:foo", "sunny", &settings, &database, now);
+
+    // As if after a restart.
+    let mut cache = OutputCache::new();
+    assert_eq!(
+        cache.get("!weather", "twitch:foo", &settings, &database, now),
+        Some("sunny".to_string())
+    );
+    cache.clear(None, &database, now);
+    assert_eq!(
+        OutputCache::new().get("!weather", "twitch:foo", &settings, &database, now),
+        None
+    );
+    Ok(())
+}
@@ -0,0 +1,260 @@
+use chrono::{DateTime, Datelike, TimeZone, Timelike, Utc};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+const MAX_DELAY_SECS: u64 = 7 * 24 * 60 * 60;
+const MIN_INTERVAL_SECS: u64 = 60;
+// Far enough ahead for any cron that can match at all, like "0 0 29 2 *".
+const MAX_CRON_SEARCH_SECS: i64 = 8 * 366 * 24 * 60 * 60;
+
+// When a scheduled task runs:
+//     30s, 5m, 2h, 1d     once, after the delay (a plain number is seconds)
+//     every 10m           repeatedly, at least a minute apart
+//     */5 * * * *         repeatedly, by cron: minute hour day-of-month month day-of-week (UTC)
+// Cron fields take *, numbers, ranges (1-5), steps (*/15, 0-30/10) and lists (1,15,30).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Once(u64),
+    Every(u64),
+    Cron(Cron),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    // Like cron, when both days are restricted either one matching is enough.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    BadDelay(String),
+    TooLong(u64),
+    TooFrequent(u64),
+    BadCron(String),
+    NeverRuns,
+}
+
+impl Display for ScheduleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            ScheduleError::BadDelay(text) => write!(f, "Bad delay {}", text),
+            ScheduleError::TooLong(secs) => {
+                write!(f, "Can't wait {} seconds (max {})", secs, MAX_DELAY_SECS)
+            }
+            ScheduleError::TooFrequent(secs) => write!(
+                f,
+                "Can't repeat every {} seconds (min {})",
+                secs, MIN_INTERVAL_SECS
+            ),
+            ScheduleError::BadCron(field) => write!(f, "Bad cron field {}", field),
+            ScheduleError::NeverRuns => write!(f, "That schedule never runs"),
+        }
+    }
+}
+
+impl Schedule {
+    pub fn parse(text: &str) -> Result<Schedule, ScheduleError> {
+        let text = text.trim().to_lowercase();
+        if text.starts_with("every ") {
+            let secs = parse_delay(&text["every ".len()..])?;
+            if secs < MIN_INTERVAL_SECS {
+                return Err(ScheduleError::TooFrequent(secs));
+            }
+            return Ok(Schedule::Every(secs));
+        }
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        if fields.len() == 5 {
+            let cron = Cron::parse(&fields)?;
+            return match Schedule::Cron(cron.clone()).next_due(Utc::now().timestamp()) {
+                Some(_) => Ok(Schedule::Cron(cron)),
+                None => Err(ScheduleError::NeverRuns),
+            };
+        }
+        Ok(Schedule::Once(parse_delay(&text)?))
+    }
+
+    pub fn repeats(&self) -> bool {
+        match self {
+            Schedule::Once(_) => false,
+            _ => true,
+        }
+    }
+
+    // Unix time of the next run after now.
+    pub fn next_due(&self, now: i64) -> Option<i64> {
+        match self {
+            Schedule::Once(secs) | Schedule::Every(secs) => Some(now + *secs as i64),
+            Schedule::Cron(cron) => cron.next_due(now),
+        }
+    }
+}
+
+// A delay like 30s, 5m, 2h or 1d in seconds, up to a week.
+pub fn parse_delay(text: &str) -> Result<u64, ScheduleError> {
+    let secs = match parse_duration(text) {
+        Some(secs) => secs,
+        None => return Err(ScheduleError::BadDelay(text.trim().to_string())),
+    };
+    if secs > MAX_DELAY_SECS {
+        return Err(ScheduleError::TooLong(secs));
+    }
+    Ok(secs)
+}
+
+// 30s, 5m, 2h or 1d in seconds, with no limit. A plain number is seconds.
+pub fn parse_duration(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => text.split_at(i),
+        None => (text, "s"),
+    };
+    let multiplier = match unit.trim() {
+        "s" | "sec" | "secs" | "seconds" => 1,
+        "m" | "min" | "mins" | "minutes" => 60,
+        "h" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .map(|number| number.saturating_mul(multiplier))
+}
+
+impl Cron {
+    fn parse(fields: &[&str]) -> Result<Cron, ScheduleError> {
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // 7 is Sunday too.
+        for weekday in weekdays.iter_mut() {
+            *weekday %= 7;
+        }
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    // Skips whole days and hours that can't match, so even a far off date is quick to find.
+    fn next_due(&self, now: i64) -> Option<i64> {
+        let mut time = now - now.rem_euclid(60) + 60;
+        let end = time + MAX_CRON_SEARCH_SECS;
+        while time < end {
+            let date = Utc.timestamp(time, 0);
+            if !self.date_matches(&date) {
+                time += 24 * 60 * 60 - date.num_seconds_from_midnight() as i64;
+            } else if !self.hours.contains(&date.hour()) {
+                time += 60 * 60 - date.minute() as i64 * 60;
+            } else if !self.minutes.contains(&date.minute()) {
+                time += 60;
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    fn date_matches(&self, date: &DateTime<Utc>) -> bool {
+        let day = self.days.contains(&date.day());
+        let weekday = self
+            .weekdays
+            .contains(&date.weekday().num_days_from_sunday());
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        };
+        day_matches && self.months.contains(&date.month())
+    }
+}
+
+fn parse_field(field: &str, low: u32, high: u32) -> Result<Vec<u32>, ScheduleError> {
+    let bad = || ScheduleError::BadCron(field.to_string());
+    let mut values = Vec::new();
+    for part in field.split(',') {
+        let mut step_parts = part.splitn(2, '/');
+        let range = step_parts.next().unwrap_or("");
+        let step = match step_parts.next() {
+            Some(step) => step.parse::<u32>().map_err(|_| bad())?,
+            None => 1,
+        };
+        let (start, end) = if range == "*" {
+            (low, high)
+        } else {
+            let mut bounds = range.splitn(2, '-');
+            let start = bounds
+                .next()
+                .unwrap_or("")
+                .parse::<u32>()
+                .map_err(|_| bad())?;
+            let end = match bounds.next() {
+                Some(end) => end.parse::<u32>().map_err(|_| bad())?,
+                None => start,
+            };
+            (start, end)
+        };
+        if step == 0 || start < low || end > high || start > end {
+            return Err(bad());
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    Ok(values)
+}
+
+#[test]
+fn test_parse_delays() {
+    assert_eq!(Schedule::parse("30"), Ok(Schedule::Once(30)));
+    assert_eq!(Schedule::parse("5m"), Ok(Schedule::Once(300)));
+    assert_eq!(Schedule::parse("every 2 hours"), Ok(Schedule::Every(7200)));
+    assert_eq!(
+        Schedule::parse("every 5s"),
+        Err(ScheduleError::TooFrequent(5))
+    );
+    assert_eq!(
+        Schedule::parse("30 weeks"),
+        Err(ScheduleError::BadDelay("30 weeks".to_string()))
+    );
+    assert_eq!(
+        Schedule::parse("30d"),
+        Err(ScheduleError::TooLong(30 * 24 * 60 * 60))
+    );
+    assert!(!Schedule::parse("5m").unwrap().repeats());
+    assert_eq!(Schedule::parse("5m").unwrap().next_due(1000), Some(1300));
+}
+
+#[test]
+fn test_cron() {
+    // 2020-01-01 00:00:30 UTC, a Wednesday.
+    let now = 1577836830;
+    let every_fifteen = Schedule::parse("*/15 * * * *").unwrap();
+    assert_eq!(every_fifteen.next_due(now), Some(now - 30 + 15 * 60));
+
+    let monday_noon = Schedule::parse("0 12 * * 1").unwrap();
+    assert_eq!(
+        monday_noon.next_due(now),
+        Some(Utc.ymd(2020, 1, 6).and_hms(12, 0, 0).timestamp())
+    );
+
+    // The 15th or any Sunday, whichever comes first.
+    let either = Schedule::parse("0 0 15 * 0").unwrap();
+    assert_eq!(
+        either.next_due(now),
+        Some(Utc.ymd(2020, 1, 5).and_hms(0, 0, 0).timestamp())
+    );
+
+    assert_eq!(
+        Schedule::parse("60 * * * *"),
+        Err(ScheduleError::BadCron("60".to_string()))
+    );
+    assert_eq!(Schedule::parse("0 0 31 2 *"), Err(ScheduleError::NeverRuns));
+}
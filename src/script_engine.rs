@@ -1,71 +1,161 @@
 #![allow(dead_code)]
 
 use crate::database::Database;
-use crate::models::{Variable, VariableValue};
+use crate::dice::Roll;
+use crate::models::{
+    MessageTarget, ScriptAction, ScriptError, ScriptErrorKind, ScriptLimits, ScriptOutput,
+    ScriptTier, StringItem, Variable, VariableChange, VariableValue,
+};
+use crate::schedule::Schedule;
+use chrono::{TimeZone, Utc};
 use crossbeam::channel::{bounded, RecvTimeoutError};
+use rand::distributions::Uniform;
+use rand::seq::SliceRandom;
 use rand::Rng;
+use regex::Regex;
 use rhai::{Any, AnyExt, Engine, EvalAltResult, RegisterFn};
 use rusqlite::Error;
+use std::cell::RefCell;
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::fmt::Display;
-use std::ops::{Add, Mul};
+use std::ops::{Add, Div, Mul, Rem, Sub};
 use std::process::exit;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::thread;
 use std::time::Duration;
 use std::{env, panic};
 
 mod database;
+mod dice;
+mod http;
 mod models;
+mod schedule;
+mod script_source;
+mod search;
 mod waifu;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let script = args.get(1).unwrap().clone();
 
-    let timeout = Duration::from_millis(1500);
+    let limits: ScriptLimits = match env::var("SCRIPT_LIMITS") {
+        Ok(limits) => serde_json::from_str(&limits).unwrap_or_default(),
+        Err(_) => ScriptLimits::default(),
+    };
+    MAX_OPERATIONS.store(limits.max_operations(), Ordering::SeqCst);
+    MAX_STRING_LENGTH.store(limits.max_string_length(), Ordering::SeqCst);
+    MAX_ARRAY_LENGTH.store(limits.max_array_length(), Ordering::SeqCst);
+
+    let timeout = Duration::from_millis(limits.timeout_ms());
     let (sender, receiver) = bounded(0);
 
+    let tier = match env::var("SCRIPT_TIER") {
+        Ok(tier) => ScriptTier::parse(&tier).unwrap_or_default(),
+        Err(_) => ScriptTier::default(),
+    };
+
+    // Variable writes are kept in a transaction that is rolled back and reported instead.
+    let dry_run = dry_run();
+
     thread::spawn(move || {
         panic::set_hook(Box::new(|_| {}));
 
+        let before = if dry_run {
+            with_database(|database| {
+                database.begin()?;
+                snapshot_variables(database)
+            })
+            .unwrap()
+        } else {
+            Vec::new()
+        };
+
         let result = panic::catch_unwind(|| {
-            let mut script_engine = ScriptEngine::new();
-            match script_engine.0.eval::<String>(script.as_str()) {
-                Ok(result) => result,
+            let mut script_engine = ScriptEngine::new(tier);
+            let mut resolver = ModuleResolver::new();
+            let resolved = resolver.resolve(&script);
+            match script_engine.0.eval::<String>(resolved.as_str()) {
+                Ok(result) => Ok(result),
                 Err(e) => match &e {
                     EvalAltResult::ErrorMismatchOutputType(t, output) => match t.as_ref() {
-                        "i64" => format!("{}", output.clone().downcast::<i64>().unwrap()),
-                        "f64" => format!("{}", output.clone().downcast::<f64>().unwrap()),
-                        "bool" => format!("{}", output.clone().downcast::<bool>().unwrap()),
-                        _ => format!("Script Error: Unknown type {}", e),
+                        "i64" => Ok(format!("{}", output.clone().downcast::<i64>().unwrap())),
+                        "f64" => Ok(format!("{}", output.clone().downcast::<f64>().unwrap())),
+                        "bool" => Ok(format!("{}", output.clone().downcast::<bool>().unwrap())),
+                        _ => output_to_string(output.clone()).ok_or_else(|| {
+                            ScriptError::new(
+                                ScriptErrorKind::OutputType,
+                                format!("Script returned a {}, which can't be shown as text", t),
+                            )
+                        }),
                     },
-                    _ => format!("Script Error: {}", e),
+                    EvalAltResult::ErrorParseError(_) => Err(resolver.locate(
+                        ScriptError::new(ScriptErrorKind::Parse, format!("{}", e))
+                            .with_location(&resolved),
+                    )),
+                    _ => Err(resolver.locate(
+                        ScriptError::new(ScriptErrorKind::Runtime, format!("{}", e))
+                            .with_location(&resolved),
+                    )),
                 },
             }
         });
-        match sender.send(match result {
-            Ok(result) => result,
-            Err(err) => format!(
-                "Script Error: {}",
-                match err.downcast_ref::<&'static str>() {
-                    Some(s) => *s,
-                    None => match err.downcast_ref::<String>() {
-                        Some(s) => &s[..],
-                        None => "Box<Any>",
-                    },
-                }
-            ),
-        }) {
+        let result = match result {
+            Ok(result) => Ok(result),
+            Err(err) => match err.downcast_ref::<LimitExceeded>() {
+                Some(limit) => Err(limit.clone()),
+                None => Ok(Err(ScriptError::new(
+                    ScriptErrorKind::Runtime,
+                    match err.downcast_ref::<&'static str>() {
+                        Some(s) => *s,
+                        None => match err.downcast_ref::<String>() {
+                            Some(s) => &s[..],
+                            None => "Box<Any>",
+                        },
+                    }
+                    .to_string(),
+                ))),
+            },
+        };
+        let actions = ACTIONS.with(|actions| actions.replace(Vec::new()));
+        let variable_changes = if dry_run {
+            with_database(|database| -> Result<_, Error> {
+                let after = snapshot_variables(database)?;
+                database.rollback()?;
+                Ok(VariableChange::between(&before, &after))
+            })
+            .unwrap()
+        } else {
+            written_changes()
+        };
+        let output = result.map(|result| ScriptOutput {
+            result,
+            actions,
+            variable_changes,
+        });
+        match sender.send(output) {
             Ok(_) => {}
             Err(e) => println!("{:?}", e),
         }
     });
     match receiver.recv_timeout(timeout) {
-        Ok(result) => {
-            print!("{}", result);
+        Ok(Ok(output)) => {
+            if let Ok(text) = &output.result {
+                let image_regex = Regex::new(r"\{\{IMAGE\|(.*?)}}").unwrap();
+                let visible = image_regex.replace_all(text, "");
+                if visible.chars().count() > limits.max_output_length() {
+                    exit(111);
+                }
+            }
+            print!("{}", serde_json::to_string(&output).unwrap());
             exit(0);
         }
+        Ok(Err(limit)) => match limit {
+            LimitExceeded::Operations => exit(110),
+            LimitExceeded::DataSize => exit(112),
+        },
         Err(e) => match e {
             RecvTimeoutError::Timeout => exit(100),
             RecvTimeoutError::Disconnected => exit(128),
@@ -73,10 +163,300 @@ fn main() {
     }
 }
 
+thread_local! {
+    // Requested by the script, performed by the bot once the script is done.
+    static ACTIONS: RefCell<Vec<ScriptAction>> = RefCell::new(Vec::new());
+    // One connection for the whole script, so a dry run can roll everything back.
+    static DATABASE: RefCell<Option<Database>> = RefCell::new(None);
+    // Variables the script wrote, with their values from before its first write to them.
+    static WRITTEN: RefCell<BTreeMap<String, Option<VariableValue>>> = RefCell::new(BTreeMap::new());
+}
+
+fn with_database<T, F: FnOnce(&Database) -> T>(f: F) -> T {
+    DATABASE.with(|database| {
+        let mut database = database.borrow_mut();
+        if database.is_none() {
+            *database = Some(Database::connect(None).unwrap());
+        }
+        f(database.as_ref().unwrap())
+    })
+}
+
+fn before_write(name: &str) {
+    WRITTEN.with(|written| {
+        let mut written = written.borrow_mut();
+        if !written.contains_key(name) {
+            let old = with_database(|database| database.get_variable(name))
+                .ok()
+                .map(|variable| variable.value);
+            written.insert(name.to_string(), old);
+        }
+    })
+}
+
+// So the bot can run the watchers of what the script changed.
+fn written_changes() -> Vec<VariableChange> {
+    let written = WRITTEN.with(|written| written.replace(BTreeMap::new()));
+    written
+        .into_iter()
+        .filter_map(|(name, old)| {
+            let new = with_database(|database| database.get_variable(&name))
+                .ok()
+                .map(|variable| variable.value);
+            if new == old {
+                None
+            } else {
+                Some(VariableChange { name, old, new })
+            }
+        })
+        .collect()
+}
+
+// Global variables, plus the current user's variables as "user/name"
+// and the command's state as "trigger/name".
+fn snapshot_variables(database: &Database) -> Result<Vec<Variable>, Error> {
+    let mut variables = database.get_variables()?;
+    let user = script_user();
+    if !user.is_empty() {
+        for (name, value) in database.get_user_variables(&user)? {
+            variables.push(Variable::new(
+                format!("{}/{}", user, name),
+                VariableValue::Text(value),
+            ));
+        }
+    }
+    let command = script_command();
+    if !command.is_empty() {
+        for (name, value) in database.get_command_states(&command)? {
+            variables.push(Variable::new(
+                format!("{}/{}", command, name),
+                VariableValue::Text(value),
+            ));
+        }
+    }
+    Ok(variables)
+}
+
+// Identity of whoever triggered the script, empty when there is nobody.
+fn script_user() -> String {
+    env::var("SCRIPT_USER").unwrap_or_default()
+}
+
+// Trigger of the command the script is in, empty for !eval.
+fn script_command() -> String {
+    env::var("SCRIPT_COMMAND").unwrap_or_default()
+}
+
+// Dry runs roll back variable writes, and don't use the network either.
+fn dry_run() -> bool {
+    env::var("DRY_RUN").is_ok()
+}
+
+fn skipped_in_dry_run(call: String) -> String {
+    format!("({} is skipped in a dry run)", call)
+}
+
+static OPERATIONS: AtomicU64 = AtomicU64::new(0);
+static MAX_OPERATIONS: AtomicU64 = AtomicU64::new(0);
+static MAX_STRING_LENGTH: AtomicUsize = AtomicUsize::new(0);
+static MAX_ARRAY_LENGTH: AtomicUsize = AtomicUsize::new(0);
+
+// Unwound with in place of a panic message, so limits are reported apart from script errors.
+#[derive(Clone, Debug)]
+enum LimitExceeded {
+    Operations,
+    DataSize,
+}
+
+// Counts one operation. The arithmetic and comparison operators call this, so loops are counted.
+fn tick() {
+    if OPERATIONS.fetch_add(1, Ordering::SeqCst) >= MAX_OPERATIONS.load(Ordering::SeqCst) {
+        panic::resume_unwind(Box::new(LimitExceeded::Operations));
+    }
+}
+
+fn check_string(x: String) -> String {
+    check_string_length(x.len());
+    x
+}
+
+fn check_string_length(len: usize) {
+    if len > MAX_STRING_LENGTH.load(Ordering::SeqCst) {
+        panic::resume_unwind(Box::new(LimitExceeded::DataSize));
+    }
+}
+
+fn check_array<T>(x: Vec<T>) -> Vec<T> {
+    if x.len() > MAX_ARRAY_LENGTH.load(Ordering::SeqCst) {
+        panic::resume_unwind(Box::new(LimitExceeded::DataSize));
+    }
+    x
+}
+
+// Lists, rolls and list items are shown as text, anything else is an error.
+fn output_to_string(output: Box<dyn Any>) -> Option<String> {
+    let output = match output.downcast::<Vec<Box<dyn Any>>>() {
+        Ok(list) => {
+            return Some(
+                list.into_iter()
+                    .map(ScriptFunction::dynamic_to_string)
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            )
+        }
+        Err(output) => output,
+    };
+    let output = match output.downcast::<Roll>() {
+        Ok(roll) => return Some(roll.to_string()),
+        Err(output) => output,
+    };
+    match output.downcast::<StringItem>() {
+        Ok(item) => Some(item.value),
+        Err(_) => None,
+    }
+}
+
+// Resolves `import "name";` from the script_module table. The import is blanked out so line
+// numbers still match, and the module source is appended after the script, which works because
+// rhai hoists function definitions. `!module add` makes sure modules only hold `fn` definitions.
+struct ModuleResolver {
+    imported: Vec<String>,
+    // The first line of each module in the resolved script, for pointing errors at the module.
+    sections: Vec<(usize, String)>,
+}
+
+impl ModuleResolver {
+    fn new() -> ModuleResolver {
+        ModuleResolver {
+            imported: Vec::new(),
+            sections: Vec::new(),
+        }
+    }
+
+    fn resolve(&mut self, script: &str) -> String {
+        let mut pending = Vec::new();
+        let mut resolved = self.blank_imports(script, &mut pending);
+        while let Some(name) = pending.pop() {
+            let source = self.load(&name);
+            resolved += "\n";
+            self.sections
+                .push((resolved.matches('\n').count() + 1, name));
+            resolved += &self.blank_imports(&source, &mut pending);
+        }
+        resolved
+    }
+
+    // Each module is only pulled in once, which also stops import cycles.
+    fn blank_imports(&mut self, script: &str, pending: &mut Vec<String>) -> String {
+        let mut blanked = script.to_string();
+        for import in script_source::imports(script) {
+            if !self.imported.contains(&import.name) {
+                self.imported.push(import.name.clone());
+                pending.push(import.name);
+            }
+            // Same length in bytes, and newlines kept, so nothing else moves.
+            let blank: String = script[import.span.clone()]
+                .chars()
+                .map(|c| match c {
+                    '\n' => "\n".to_string(),
+                    c => " ".repeat(c.len_utf8()),
+                })
+                .collect();
+            blanked.replace_range(import.span, &blank);
+        }
+        blanked
+    }
+
+    fn load(&mut self, name: &str) -> String {
+        match with_database(|database| database.get_script_module(name)) {
+            Ok(module) => module.source,
+            Err(e) => match e {
+                Error::QueryReturnedNoRows => panic!(format!("Module {} does not exist!", name)),
+                _ => panic!(e),
+            },
+        }
+    }
+
+    // Errors past the end of the script are in one of the modules.
+    fn locate(&self, error: ScriptError) -> ScriptError {
+        let line = match error.line {
+            Some(line) => line,
+            None => return error,
+        };
+        match self
+            .sections
+            .iter()
+            .filter(|(first_line, _)| *first_line <= line)
+            .last()
+        {
+            Some((first_line, name)) => error.in_module(name, *first_line),
+            None => error,
+        }
+    }
+}
+
 pub struct ScriptEngine(Engine);
 
 impl ScriptEngine {
-    fn new() -> ScriptEngine {
+    /// Builds the engine with the script standard library registered. Functions that write
+    /// global variables, use the network or request actions are only there for trusted
+    /// authors, see `ScriptTier`.
+    ///
+    /// Strings: `upper(s)`, `lower(s)`, `trim(s)`, `split(s, sep)`, `join(list, sep)`,
+    /// `replace(s, from, to)`, `contains(s, sub)`, `starts_with(s, prefix)`,
+    /// `pad(s, width)` (pads on the right) and `pad_left(s, width)`.
+    ///
+    /// Math: `min(a, b)`, `max(a, b)`, `abs(x)`, `round(x)`, `ceil(x)`, `floor(x)`,
+    /// `pow(x, y)` and `clamp(x, low, high)`, for both integers and floats.
+    ///
+    /// Random: `random()` in [0, 1), `random_int(a, b)` inclusive, `random_index(list)`,
+    /// `choice(list)` and `shuffle(list)`.
+    ///
+    /// Dice: `roll(notation)` returns a roll with `total` and `breakdown`, e.g.
+    /// `roll("4d6kh3+1").total`. See `dice.rs` for the notation.
+    ///
+    /// Actions, performed by the bot after the script finishes and only if the user who
+    /// triggered the command could do them from chat: `send_message(platform, channel, text)`
+    /// with platform "twitch" or "discord", `add_command(trigger, response)`,
+    /// `edit_command(trigger, response)`, `delete_command(trigger)`, `delete_variable(name)`
+    /// and `notify_live()`.
+    ///
+    /// Later: `say_after(seconds, text)` says text where the command was triggered, and
+    /// `schedule(when, command)` runs a command there once or repeatedly, with `when` a delay
+    /// like "30s", "every 10m" or a cron line. See `schedule.rs`. Both survive restarts.
+    ///
+    /// Counters: `increment(name)`, `decrement(name)` and `increment(name, n)` or
+    /// `decrement(name, n)` return the new count, without losing updates from other commands
+    /// running at the same time. A missing counter starts at 0. `reset(name)` sets it back to 0,
+    /// or empties a list.
+    ///
+    /// User variables: `user_get(name)` and `user_set(name, value)` keep a value for whoever
+    /// triggered the command, separately per platform. Unset values are "". Templates can use
+    /// `${uservar:name}`.
+    ///
+    /// Inputs: `input(name)` reads a value the script was given, like `old` and `new` in a
+    /// variable watcher. Missing inputs are "".
+    ///
+    /// Command state: `state_get(name)` and `state_set(name, value)` keep values private to
+    /// the command, deleted along with it and shown by `!command show`. Unset values are "".
+    ///
+    /// List items: `get_item(list_name, index)` returns an item with `value`, `added_by`,
+    /// `platform`, `channel`, `time`, `date` (like "2026-01-02") and `credit`, which is
+    /// " — added by X on 2026-01-02" or "" when that isn't known. `field(item, name)` reads a
+    /// free-form field, or "".
+    ///
+    /// Search: `search(list_name, query)` returns the indices of the list items containing
+    /// every word of the query, best matches first. See `search.rs`.
+    ///
+    /// Modules: `import "name";` pulls in the functions of a module saved with `!module add`.
+    ///
+    /// HTTP: `http_get(url)` returns the body as text, `http_get_json(url)` checks it is JSON
+    /// and `http_get_json(url, pointer)` picks one value out, e.g. `"/current/temp"`. Only
+    /// domains allowed with `!http allow` can be fetched, and responses are cached for a while.
+    ///
+    /// Time (UTC): `now()` as a unix timestamp, `date(format)` for the current time and
+    /// `format_time(timestamp, format)`, using strftime-style formats.
+    fn new(tier: ScriptTier) -> ScriptEngine {
         let mut engine = Engine::new();
         engine.register_fn("string", ScriptFunction::string as fn(x: i64) -> String);
         engine.register_fn("string", ScriptFunction::string as fn(x: f64) -> String);
@@ -86,6 +466,67 @@ impl ScriptEngine {
         engine.register_fn("len", ScriptFunction::len);
         engine.register_fn("floor", ScriptFunction::floor);
         engine.register_fn("int", ScriptFunction::int);
+        engine.register_fn("upper", ScriptFunction::upper);
+        engine.register_fn("lower", ScriptFunction::lower);
+        engine.register_fn("trim", ScriptFunction::trim);
+        engine.register_fn("split", ScriptFunction::split);
+        engine.register_fn("join", ScriptFunction::join);
+        engine.register_fn("replace", ScriptFunction::replace);
+        engine.register_fn("contains", ScriptFunction::contains);
+        engine.register_fn("starts_with", ScriptFunction::starts_with);
+        engine.register_fn("pad", ScriptFunction::pad);
+        engine.register_fn("pad_left", ScriptFunction::pad_left);
+        engine.register_fn("min", ScriptFunction::min as fn(x: i64, y: i64) -> i64);
+        engine.register_fn("min", ScriptFunction::min as fn(x: f64, y: f64) -> f64);
+        engine.register_fn("max", ScriptFunction::max as fn(x: i64, y: i64) -> i64);
+        engine.register_fn("max", ScriptFunction::max as fn(x: f64, y: f64) -> f64);
+        engine.register_fn("abs", ScriptFunction::abs_int);
+        engine.register_fn("abs", ScriptFunction::abs_float);
+        engine.register_fn("round", ScriptFunction::round);
+        engine.register_fn("ceil", ScriptFunction::ceil);
+        engine.register_fn("pow", ScriptFunction::pow_int);
+        engine.register_fn("pow", ScriptFunction::pow_float);
+        engine.register_fn(
+            "clamp",
+            ScriptFunction::clamp as fn(x: i64, low: i64, high: i64) -> i64,
+        );
+        engine.register_fn(
+            "clamp",
+            ScriptFunction::clamp as fn(x: f64, low: f64, high: f64) -> f64,
+        );
+        engine.register_fn("random_int", ScriptFunction::random_int);
+        engine.register_fn("choice", ScriptFunction::choice);
+        engine.register_fn("shuffle", ScriptFunction::shuffle);
+        engine.register_type::<Roll>();
+        engine.register_get("total", |roll: &mut Roll| roll.total);
+        engine.register_get("breakdown", |roll: &mut Roll| roll.breakdown());
+        engine.register_fn("roll", ScriptFunction::roll);
+        engine.register_fn("string", ScriptFunction::string as fn(x: Roll) -> String);
+        engine.register_fn("now", ScriptFunction::now);
+        engine.register_fn("date", ScriptFunction::date);
+        engine.register_fn("format_time", ScriptFunction::format_time);
+        // Replaces the built in operators so that they count towards the operation limit.
+        engine.register_fn("+", ScriptFunction::add as fn(x: i64, y: i64) -> i64);
+        engine.register_fn("+", ScriptFunction::add as fn(x: f64, y: f64) -> f64);
+        engine.register_fn("+", ScriptFunction::concat);
+        engine.register_fn("-", ScriptFunction::sub as fn(x: i64, y: i64) -> i64);
+        engine.register_fn("-", ScriptFunction::sub as fn(x: f64, y: f64) -> f64);
+        engine.register_fn("/", ScriptFunction::div as fn(x: i64, y: i64) -> i64);
+        engine.register_fn("/", ScriptFunction::div as fn(x: f64, y: f64) -> f64);
+        engine.register_fn("%", ScriptFunction::rem as fn(x: i64, y: i64) -> i64);
+        engine.register_fn("%", ScriptFunction::rem as fn(x: f64, y: f64) -> f64);
+        engine.register_fn("<", ScriptFunction::lt as fn(x: i64, y: i64) -> bool);
+        engine.register_fn("<", ScriptFunction::lt as fn(x: f64, y: f64) -> bool);
+        engine.register_fn("<=", ScriptFunction::le as fn(x: i64, y: i64) -> bool);
+        engine.register_fn("<=", ScriptFunction::le as fn(x: f64, y: f64) -> bool);
+        engine.register_fn(">", ScriptFunction::gt as fn(x: i64, y: i64) -> bool);
+        engine.register_fn(">", ScriptFunction::gt as fn(x: f64, y: f64) -> bool);
+        engine.register_fn(">=", ScriptFunction::ge as fn(x: i64, y: i64) -> bool);
+        engine.register_fn(">=", ScriptFunction::ge as fn(x: f64, y: f64) -> bool);
+        engine.register_fn("==", ScriptFunction::eq as fn(x: i64, y: i64) -> bool);
+        engine.register_fn("==", ScriptFunction::eq as fn(x: f64, y: f64) -> bool);
+        engine.register_fn("!=", ScriptFunction::ne as fn(x: i64, y: i64) -> bool);
+        engine.register_fn("!=", ScriptFunction::ne as fn(x: f64, y: f64) -> bool);
         engine.register_fn("*", ScriptFunction::mul as fn(x: i64, y: i64) -> i64);
         engine.register_fn("*", ScriptFunction::mul as fn(x: f64, y: f64) -> f64);
         engine.register_fn("*", ScriptFunction::mul as fn(x: i64, y: f64) -> i64);
@@ -99,13 +540,130 @@ impl ScriptEngine {
             ScriptFunction::add_string_number as fn(x: String, y: f64) -> f64,
         );
         engine.register_fn("get", ScriptFunction::get);
-        engine.register_fn("set", ScriptFunction::set as fn(x: String, y: String));
-        engine.register_fn("set", ScriptFunction::set as fn(x: String, y: i64));
-        engine.register_fn("set", ScriptFunction::set as fn(x: String, y: f64));
-        engine.register_fn("set", ScriptFunction::set as fn(x: String, y: bool));
         engine.register_fn("get_list", ScriptFunction::get_list);
-        engine.register_fn("waifu", ScriptFunction::waifu);
-        engine.register_fn("upload_image", ScriptFunction::upload_image);
+        engine.register_fn("get_key", ScriptFunction::get_key);
+        engine.register_fn("search", ScriptFunction::search);
+        engine.register_type::<StringItem>();
+        engine.register_fn("get_item", ScriptFunction::get_item);
+        engine.register_get("value", |item: &mut StringItem| item.value.clone());
+        engine.register_get("added_by", |item: &mut StringItem| item.added_by.clone());
+        engine.register_get("platform", |item: &mut StringItem| {
+            match &item.added_in {
+                Some(MessageTarget::Twitch(_)) => "twitch",
+                Some(MessageTarget::Discord(_)) => "discord",
+                Some(MessageTarget::Admin) => "admin",
+                None => "",
+            }
+            .to_string()
+        });
+        engine.register_get("channel", |item: &mut StringItem| match &item.added_in {
+            Some(MessageTarget::Twitch(channel)) | Some(MessageTarget::Discord(channel)) => {
+                channel.clone()
+            }
+            _ => "".to_string(),
+        });
+        engine.register_get("time", |item: &mut StringItem| item.time_created.sec);
+        engine.register_get("date", |item: &mut StringItem| item.date());
+        engine.register_get("credit", |item: &mut StringItem| item.credit());
+        engine.register_fn("field", ScriptFunction::field);
+        engine.register_fn("state_get", ScriptFunction::state_get);
+        engine.register_fn(
+            "state_set",
+            ScriptFunction::state_set as fn(x: String, y: String),
+        );
+        engine.register_fn(
+            "state_set",
+            ScriptFunction::state_set as fn(x: String, y: i64),
+        );
+        engine.register_fn(
+            "state_set",
+            ScriptFunction::state_set as fn(x: String, y: f64),
+        );
+        engine.register_fn(
+            "state_set",
+            ScriptFunction::state_set as fn(x: String, y: bool),
+        );
+        engine.register_fn("input", ScriptFunction::input);
+        engine.register_fn("user_get", ScriptFunction::user_get);
+        engine.register_fn(
+            "user_set",
+            ScriptFunction::user_set as fn(x: String, y: String),
+        );
+        engine.register_fn(
+            "user_set",
+            ScriptFunction::user_set as fn(x: String, y: i64),
+        );
+        engine.register_fn(
+            "user_set",
+            ScriptFunction::user_set as fn(x: String, y: f64),
+        );
+        engine.register_fn(
+            "user_set",
+            ScriptFunction::user_set as fn(x: String, y: bool),
+        );
+        engine.register_fn("list_len", ScriptFunction::list_len);
+        // Everything else depends on how much the command's author is trusted.
+        if tier >= ScriptTier::VariableWrite {
+            engine.register_fn("set", ScriptFunction::set as fn(x: String, y: String));
+            engine.register_fn("set", ScriptFunction::set as fn(x: String, y: i64));
+            engine.register_fn("set", ScriptFunction::set as fn(x: String, y: f64));
+            engine.register_fn("set", ScriptFunction::set as fn(x: String, y: bool));
+            engine.register_fn("increment", |name: String| {
+                ScriptFunction::increment(name, 1)
+            });
+            engine.register_fn("increment", ScriptFunction::increment);
+            engine.register_fn("decrement", |name: String| {
+                ScriptFunction::increment(name, -1)
+            });
+            engine.register_fn("decrement", |name: String, by: i64| {
+                match by.checked_neg() {
+                    Some(by) => ScriptFunction::increment(name, by),
+                    None => panic!(format!("Can't decrement {} by {}", name, by)),
+                }
+            });
+            engine.register_fn("reset", ScriptFunction::reset);
+            engine.register_fn("push", ScriptFunction::push as fn(x: String, y: String));
+            engine.register_fn("push", ScriptFunction::push as fn(x: String, y: i64));
+            engine.register_fn("push", ScriptFunction::push as fn(x: String, y: f64));
+            engine.register_fn(
+                "insert_at",
+                ScriptFunction::insert_at as fn(x: String, i: i64, y: String),
+            );
+            engine.register_fn(
+                "insert_at",
+                ScriptFunction::insert_at as fn(x: String, i: i64, y: i64),
+            );
+            engine.register_fn(
+                "insert_at",
+                ScriptFunction::insert_at as fn(x: String, i: i64, y: f64),
+            );
+            engine.register_fn("remove_at", ScriptFunction::remove_at);
+            engine.register_fn("remove_value", ScriptFunction::remove_value);
+            engine.register_fn("set_list", ScriptFunction::set_list);
+        }
+        if tier >= ScriptTier::Network {
+            engine.register_fn("waifu", ScriptFunction::waifu);
+            engine.register_fn("upload_image", ScriptFunction::upload_image);
+            engine.register_fn("http_get", ScriptFunction::http_get);
+            engine.register_fn(
+                "http_get_json",
+                ScriptFunction::http_get_json as fn(url: String) -> String,
+            );
+            engine.register_fn(
+                "http_get_json",
+                ScriptFunction::http_get_json_at as fn(url: String, pointer: String) -> String,
+            );
+        }
+        if tier >= ScriptTier::Actions {
+            engine.register_fn("send_message", ScriptFunction::send_message);
+            engine.register_fn("add_command", ScriptFunction::add_command);
+            engine.register_fn("edit_command", ScriptFunction::edit_command);
+            engine.register_fn("delete_command", ScriptFunction::delete_command);
+            engine.register_fn("delete_variable", ScriptFunction::delete_variable);
+            engine.register_fn("notify_live", ScriptFunction::notify_live);
+            engine.register_fn("say_after", ScriptFunction::say_after);
+            engine.register_fn("schedule", ScriptFunction::schedule);
+        }
         ScriptEngine(engine)
     }
 }
@@ -144,7 +702,213 @@ impl ScriptFunction {
         }
     }
 
+    fn upper(x: String) -> String {
+        x.to_uppercase()
+    }
+
+    fn lower(x: String) -> String {
+        x.to_lowercase()
+    }
+
+    fn trim(x: String) -> String {
+        x.trim().to_string()
+    }
+
+    fn split(x: String, separator: String) -> Vec<Box<dyn Any>> {
+        let mut results: Vec<Box<dyn Any>> = Vec::new();
+        for part in x.split(separator.as_str()) {
+            results.push(Box::new(part.to_string()));
+        }
+        check_array(results)
+    }
+
+    fn join(x: Vec<Box<dyn Any>>, separator: String) -> String {
+        check_string(
+            x.into_iter()
+                .map(ScriptFunction::dynamic_to_string)
+                .collect::<Vec<String>>()
+                .join(&separator),
+        )
+    }
+
+    fn replace(x: String, from: String, to: String) -> String {
+        check_string(x.replace(&from, &to))
+    }
+
+    fn contains(x: String, needle: String) -> bool {
+        x.contains(&needle)
+    }
+
+    fn starts_with(x: String, prefix: String) -> bool {
+        x.starts_with(&prefix)
+    }
+
+    fn pad(x: String, width: i64) -> String {
+        check_string_length(max(width, 0) as usize);
+        format!("{:<width$}", x, width = max(width, 0) as usize)
+    }
+
+    fn pad_left(x: String, width: i64) -> String {
+        check_string_length(max(width, 0) as usize);
+        format!("{:>width$}", x, width = max(width, 0) as usize)
+    }
+
+    fn min<T: PartialOrd>(x: T, y: T) -> T {
+        if y < x {
+            y
+        } else {
+            x
+        }
+    }
+
+    fn max<T: PartialOrd>(x: T, y: T) -> T {
+        if y > x {
+            y
+        } else {
+            x
+        }
+    }
+
+    fn abs_int(x: i64) -> i64 {
+        x.abs()
+    }
+
+    fn abs_float(x: f64) -> f64 {
+        x.abs()
+    }
+
+    fn round(x: f64) -> i64 {
+        x.round() as i64
+    }
+
+    fn ceil(x: f64) -> i64 {
+        x.ceil() as i64
+    }
+
+    fn pow_int(x: i64, y: i64) -> i64 {
+        if y < 0 {
+            panic!("pow() exponent must not be negative for integers")
+        }
+        match y.try_into().ok().and_then(|y| x.checked_pow(y)) {
+            Some(result) => result,
+            None => panic!(format!("pow({}, {}) is too big for an integer", x, y)),
+        }
+    }
+
+    fn pow_float(x: f64, y: f64) -> f64 {
+        x.powf(y)
+    }
+
+    fn clamp<T: PartialOrd>(x: T, low: T, high: T) -> T {
+        if x < low {
+            low
+        } else if x > high {
+            high
+        } else {
+            x
+        }
+    }
+
+    fn random_int(low: i64, high: i64) -> i64 {
+        if low > high {
+            panic!(format!("random_int({}, {}) has an empty range", low, high))
+        }
+        // Inclusive without computing high + 1, which overflows at the largest integer.
+        let mut rng = rand::thread_rng();
+        rng.sample(Uniform::new_inclusive(low, high))
+    }
+
+    fn choice(x: Vec<Box<dyn Any>>) -> String {
+        if x.is_empty() {
+            panic!("choice() needs a non-empty list")
+        }
+        let mut rng = rand::thread_rng();
+        let index = rng.gen_range(0, x.len());
+        ScriptFunction::dynamic_to_string(x[index].clone())
+    }
+
+    fn shuffle(mut x: Vec<Box<dyn Any>>) -> Vec<Box<dyn Any>> {
+        let mut rng = rand::thread_rng();
+        x.shuffle(&mut rng);
+        x
+    }
+
+    fn roll(notation: String) -> Roll {
+        match dice::roll(&notation) {
+            Ok(roll) => roll,
+            Err(e) => panic!(format!("Bad dice {}: {}", notation, e)),
+        }
+    }
+
+    fn now() -> i64 {
+        Utc::now().timestamp()
+    }
+
+    fn date(format: String) -> String {
+        Utc::now().format(&format).to_string()
+    }
+
+    fn format_time(timestamp: i64, format: String) -> String {
+        Utc.timestamp(timestamp, 0).format(&format).to_string()
+    }
+
+    fn add<T: Add>(x: T, y: T) -> <T as Add>::Output {
+        tick();
+        x + y
+    }
+
+    fn concat(x: String, y: String) -> String {
+        tick();
+        check_string(x + &y)
+    }
+
+    fn sub<T: Sub>(x: T, y: T) -> <T as Sub>::Output {
+        tick();
+        x - y
+    }
+
+    fn div<T: Div>(x: T, y: T) -> <T as Div>::Output {
+        tick();
+        x / y
+    }
+
+    fn rem<T: Rem>(x: T, y: T) -> <T as Rem>::Output {
+        tick();
+        x % y
+    }
+
+    fn lt<T: PartialOrd>(x: T, y: T) -> bool {
+        tick();
+        x < y
+    }
+
+    fn le<T: PartialOrd>(x: T, y: T) -> bool {
+        tick();
+        x <= y
+    }
+
+    fn gt<T: PartialOrd>(x: T, y: T) -> bool {
+        tick();
+        x > y
+    }
+
+    fn ge<T: PartialOrd>(x: T, y: T) -> bool {
+        tick();
+        x >= y
+    }
+
+    fn eq<T: PartialEq>(x: T, y: T) -> bool {
+        tick();
+        x == y
+    }
+
+    fn ne<T: PartialEq>(x: T, y: T) -> bool {
+        tick();
+        x != y
+    }
+
     fn mul<T: Mul + From<U>, U: Mul>(x: T, y: U) -> <T as Mul>::Output {
+        tick();
         x * (Into::into(y))
     }
 
@@ -159,14 +923,17 @@ impl ScriptFunction {
     // Want to serialize all these events and send them on stdout along with script output,
     // which means we'll need to store them somehow.
     fn get(name: String) -> String {
-        let database = Database::connect(None).unwrap();
-        match database.get_variable(&name) {
+        match with_database(|database| database.get_variable(&name)) {
             Ok(variable) => match variable.value {
                 VariableValue::Text(text) => text,
                 VariableValue::StringList(_) => panic!(format!(
                     "Variable {} is StringList, not Text. Use get_list()!",
                     name
                 )),
+                VariableValue::Map(_) => panic!(format!(
+                    "Variable {} is Map, not Text. Use get_key()!",
+                    name
+                )),
             },
             Err(e) => match e {
                 Error::QueryReturnedNoRows => panic!(format!("Variable {} does not exist!", name)),
@@ -176,30 +943,201 @@ impl ScriptFunction {
     }
 
     fn set<T: Display>(name: String, value: T) {
-        let database = Database::connect(None).unwrap();
-        database
-            .set_variable(&Variable::new(
+        before_write(&name);
+        with_database(|database| {
+            database.set_variable(&Variable::new(
                 name,
                 VariableValue::Text(format!("{}", value)),
             ))
-            .unwrap();
+        })
+        .unwrap();
+    }
+
+    // Empty when the script wasn't given that input.
+    fn input(name: String) -> String {
+        env::var("SCRIPT_INPUTS")
+            .ok()
+            .and_then(|inputs| serde_json::from_str::<BTreeMap<String, String>>(&inputs).ok())
+            .and_then(|mut inputs| inputs.remove(&name))
+            .unwrap_or_default()
+    }
+
+    fn user_get(name: String) -> String {
+        let user = ScriptFunction::require_user();
+        match with_database(|database| database.get_user_variable(&user, &name)) {
+            Ok(value) => value,
+            Err(Error::QueryReturnedNoRows) => "".to_string(),
+            Err(e) => panic!(e),
+        }
+    }
+
+    fn user_set<T: Display>(name: String, value: T) {
+        let user = ScriptFunction::require_user();
+        let value = check_string(format!("{}", value));
+        with_database(|database| database.set_user_variable(&user, &name, &value)).unwrap();
+    }
+
+    fn state_get(name: String) -> String {
+        let command = ScriptFunction::require_command();
+        match with_database(|database| database.get_command_state(&command, &name)) {
+            Ok(value) => value,
+            Err(Error::QueryReturnedNoRows) => "".to_string(),
+            Err(e) => panic!(e),
+        }
+    }
+
+    fn state_set<T: Display>(name: String, value: T) {
+        let command = ScriptFunction::require_command();
+        let value = check_string(format!("{}", value));
+        with_database(|database| database.set_command_state(&command, &name, &value)).unwrap();
+    }
+
+    fn require_command() -> String {
+        let command = script_command();
+        if command.is_empty() {
+            panic!("State only works inside a command");
+        }
+        command
+    }
+
+    fn require_user() -> String {
+        let user = script_user();
+        if user.is_empty() {
+            panic!("There is no user to keep variables for");
+        }
+        user
+    }
+
+    fn get_item(name: String, index: i64) -> StringItem {
+        let list = ScriptFunction::get_string_list(&name);
+        let item = if index < 0 {
+            None
+        } else {
+            list.into_iter().nth(index as usize)
+        };
+        match item {
+            Some(item) => item,
+            None => panic!(format!("{} has no item {}", name, index)),
+        }
+    }
+
+    fn field(item: StringItem, name: String) -> String {
+        item.fields.get(&name).cloned().unwrap_or_default()
     }
 
     fn get_list(name: String) -> Vec<Box<dyn Any>> {
-        let database = Database::connect(None).unwrap();
-        match database.get_variable(&name) {
+        let mut results: Vec<Box<dyn Any>> = Vec::new();
+        for item in ScriptFunction::get_string_list(&name).iter() {
+            results.push(Box::new(item.value.clone()));
+        }
+        check_array(results)
+    }
+
+    // Empty for a key that isn't set.
+    fn get_key(name: String, key: String) -> String {
+        match with_database(|database| database.get_variable(&name)) {
+            Ok(variable) => match variable.value {
+                VariableValue::Map(mut map) => map.remove(&key).unwrap_or_default(),
+                _ => panic!(format!("Variable {} is not a Map. Use get()!", name)),
+            },
+            Err(e) => match e {
+                Error::QueryReturnedNoRows => panic!(format!("Variable {} does not exist!", name)),
+                _ => panic!(e),
+            },
+        }
+    }
+
+    fn search(name: String, query: String) -> Vec<Box<dyn Any>> {
+        let list = ScriptFunction::get_string_list(&name);
+        let mut results: Vec<Box<dyn Any>> = Vec::new();
+        for index in search::search(&list, &query) {
+            results.push(Box::new(index as i64));
+        }
+        check_array(results)
+    }
+
+    fn push<T: Display>(name: String, value: T) {
+        let mut list = ScriptFunction::get_string_list(&name);
+        list.push(StringItem::new(&format!("{}", value)));
+        ScriptFunction::set_string_list(name, list);
+    }
+
+    fn insert_at<T: Display>(name: String, index: i64, value: T) {
+        let mut list = ScriptFunction::get_string_list(&name);
+        let index = min(max(index, 0) as usize, list.len());
+        list.insert(index, StringItem::new(&format!("{}", value)));
+        ScriptFunction::set_string_list(name, list);
+    }
+
+    fn remove_at(name: String, index: i64) -> String {
+        let mut list = ScriptFunction::get_string_list(&name);
+        if index < 0 || index as usize >= list.len() {
+            panic!(format!(
+                "Index {} is out of bounds for list {} of length {}",
+                index,
+                name,
+                list.len()
+            ))
+        }
+        let item = list.remove(index as usize);
+        ScriptFunction::set_string_list(name, list);
+        item.value
+    }
+
+    fn remove_value(name: String, value: String) -> i64 {
+        let mut list = ScriptFunction::get_string_list(&name);
+        let len = list.len();
+        list.retain(|item| item.value != value);
+        let removed = len - list.len();
+        if removed > 0 {
+            ScriptFunction::set_string_list(name, list);
+        }
+        removed as i64
+    }
+
+    fn increment(name: String, by: i64) -> i64 {
+        before_write(&name);
+        match with_database(|database| database.increment_variable(&name, by)) {
+            Ok(count) => count,
+            Err(e) => panic!(format!("Can't increment {}: {}", name, e)),
+        }
+    }
+
+    fn reset(name: String) {
+        before_write(&name);
+        with_database(|database| database.reset_variable(&name)).unwrap();
+    }
+
+    // Items that keep their value keep their original time_created.
+    fn set_list(name: String, values: Vec<Box<dyn Any>>) {
+        let mut old_list = ScriptFunction::get_string_list(&name);
+        let mut list = Vec::with_capacity(values.len());
+        for value in values {
+            let value = ScriptFunction::dynamic_to_string(value);
+            match old_list.iter().position(|item| item.value == value) {
+                Some(index) => list.push(old_list.remove(index)),
+                None => list.push(StringItem::new(&value)),
+            }
+        }
+        ScriptFunction::set_string_list(name, list);
+    }
+
+    fn list_len(name: String) -> i64 {
+        ScriptFunction::get_string_list(&name).len() as i64
+    }
+
+    fn get_string_list(name: &str) -> Vec<StringItem> {
+        match with_database(|database| database.get_variable(name)) {
             Ok(variable) => match variable.value {
                 VariableValue::Text(_) => panic!(format!(
                     "Variable {} is Text, not StringList. Use get()!",
                     name
                 )),
-                VariableValue::StringList(list) => {
-                    let mut results: Vec<Box<dyn Any>> = Vec::new();
-                    for item in list.iter() {
-                        results.push(Box::new(item.value.clone()));
-                    }
-                    results
-                }
+                VariableValue::StringList(list) => list,
+                VariableValue::Map(_) => panic!(format!(
+                    "Variable {} is Map, not StringList. Use get_key()!",
+                    name
+                )),
             },
             Err(e) => match e {
                 Error::QueryReturnedNoRows => panic!(format!("Variable {} does not exist!", name)),
@@ -208,13 +1146,157 @@ impl ScriptFunction {
         }
     }
 
+    fn set_string_list(name: String, list: Vec<StringItem>) {
+        let list = check_array(list);
+        before_write(&name);
+        with_database(|database| {
+            database.set_variable(&Variable::new(name, VariableValue::StringList(list)))
+        })
+        .unwrap();
+    }
+
+    fn dynamic_to_string(value: Box<dyn Any>) -> String {
+        let value = match value.downcast::<String>() {
+            Ok(value) => return *value,
+            Err(value) => value,
+        };
+        let value = match value.downcast::<i64>() {
+            Ok(value) => return format!("{}", value),
+            Err(value) => value,
+        };
+        let value = match value.downcast::<f64>() {
+            Ok(value) => return format!("{}", value),
+            Err(value) => value,
+        };
+        match value.downcast::<bool>() {
+            Ok(value) => format!("{}", value),
+            Err(_) => panic!("List items must be strings, numbers or booleans"),
+        }
+    }
+
+    fn send_message(platform: String, channel: String, text: String) {
+        let target = match platform.to_lowercase().as_ref() {
+            "twitch" => MessageTarget::Twitch(channel),
+            "discord" => MessageTarget::Discord(channel),
+            _ => panic!(format!("Unknown platform {}", platform)),
+        };
+        ScriptFunction::request(ScriptAction::SendMessage(target, text));
+    }
+
+    fn add_command(trigger: String, response: String) {
+        ScriptFunction::request(ScriptAction::AddCommand(trigger, response));
+    }
+
+    fn edit_command(trigger: String, response: String) {
+        ScriptFunction::request(ScriptAction::EditCommand(trigger, response));
+    }
+
+    fn delete_command(trigger: String) {
+        ScriptFunction::request(ScriptAction::DeleteCommand(trigger));
+    }
+
+    fn delete_variable(name: String) {
+        ScriptFunction::request(ScriptAction::DeleteVariable(name));
+    }
+
+    fn notify_live() {
+        ScriptFunction::request(ScriptAction::SendLiveNotification);
+    }
+
+    fn say_after(seconds: i64, text: String) {
+        if seconds < 0 {
+            panic!("say_after needs a delay of at least 0 seconds");
+        }
+        ScriptFunction::check_schedule(&format!("{}s", seconds));
+        ScriptFunction::request(ScriptAction::SayAfter(seconds as u64, text));
+    }
+
+    fn schedule(when: String, command: String) {
+        ScriptFunction::check_schedule(&when);
+        if !command.starts_with('!') {
+            panic!(format!(
+                "schedule needs a command starting with !, not {}",
+                command
+            ));
+        }
+        ScriptFunction::request(ScriptAction::Schedule(when, command));
+    }
+
+    fn check_schedule(when: &str) {
+        if let Err(e) = Schedule::parse(when) {
+            panic!(e.to_string());
+        }
+    }
+
+    fn request(action: ScriptAction) {
+        tick();
+        ACTIONS.with(|actions| actions.borrow_mut().push(action));
+    }
+
     fn waifu() -> String {
+        if dry_run() {
+            return "".to_string();
+        }
         waifu::generate_waifu_image()
     }
 
     fn upload_image(png_base64: String) -> String {
+        if dry_run() {
+            return skipped_in_dry_run("upload_image()".to_string());
+        }
         format!("{{{{IMAGE|{}}}}}", png_base64)
     }
+
+    fn http_get(url: String) -> String {
+        ScriptFunction::fetch(&url)
+            .unwrap_or_else(|| skipped_in_dry_run(format!("http_get({})", url)))
+    }
+
+    // None when a dry run would have had to go to the network. Cached responses are still used.
+    fn fetch(url: &str) -> Option<String> {
+        tick();
+        let result = with_database(|database| {
+            let settings = database
+                .get_http_settings()
+                .map_err(|e| http::HttpError::Database(e.to_string()))?;
+            if dry_run() {
+                http::get_cached(url, &settings, database)
+            } else {
+                http::get(url, &settings, database).map(Some)
+            }
+        });
+        match result {
+            Ok(body) => body.map(check_string),
+            Err(e) => panic!(format!("http_get({}): {}", url, e)),
+        }
+    }
+
+    fn parse_json(url: &str, body: &str) -> serde_json::Value {
+        match serde_json::from_str(body) {
+            Ok(json) => json,
+            Err(e) => panic!(format!("http_get_json({}): {}", url, e)),
+        }
+    }
+
+    fn http_get_json(url: String) -> String {
+        match ScriptFunction::fetch(&url) {
+            Some(body) => ScriptFunction::parse_json(&url, &body).to_string(),
+            None => skipped_in_dry_run(format!("http_get_json({})", url)),
+        }
+    }
+
+    // Strings come back without their quotes, anything else as JSON.
+    fn http_get_json_at(url: String, pointer: String) -> String {
+        let body = match ScriptFunction::fetch(&url) {
+            Some(body) => body,
+            None => return skipped_in_dry_run(format!("http_get_json({})", url)),
+        };
+        match ScriptFunction::parse_json(&url, &body).pointer(&pointer) {
+            Some(serde_json::Value::String(text)) => text.clone(),
+            Some(value) => value.to_string(),
+            None => panic!(format!("http_get_json({}): nothing at {}", url, pointer)),
+        }
+    }
 }
 
 trait From<T>: Sized {
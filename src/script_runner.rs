@@ -1,9 +1,16 @@
+use crate::database::Database;
+use crate::models::{ScriptError, ScriptErrorKind, ScriptLimits, ScriptOutput, ScriptTier};
+use std::collections::BTreeMap;
 use std::env;
 use std::io::Error;
+use std::path::Path;
 use std::process::Command;
 
 enum ScriptRunnerError {
     Timeout,
+    TooManyOperations,
+    DataTooLarge,
+    OutputTooLarge,
     Crash,
     IO(Error),
 }
@@ -14,32 +21,102 @@ impl From<Error> for ScriptRunnerError {
     }
 }
 
-pub fn run(script: &str, database_path: &str) -> String {
-    match eval(script, database_path) {
-        Ok(result) => result,
-        Err(e) => match e {
-            ScriptRunnerError::Timeout => "Script Error: Timeout".to_string(),
-            ScriptRunnerError::Crash => "Script Error: Crash".to_string(),
-            ScriptRunnerError::IO(_) => "Script Error: IO".to_string(),
-        },
+// What a script runs with, besides its source.
+pub struct ScriptContext<'a> {
+    pub database_path: &'a str,
+    pub limits: &'a ScriptLimits,
+    // Variable writes are rolled back and reported instead.
+    pub dry_run: bool,
+    // Identity of whoever triggered the script, for user variables.
+    pub user: &'a str,
+    // Trigger of the command the script is in, for its state. Empty outside a command.
+    pub command: &'a str,
+    // Which functions the script gets, from the command's author.
+    pub tier: ScriptTier,
+    // Values the script reads with input(name).
+    pub inputs: &'a BTreeMap<String, String>,
+}
+
+pub fn run(script: &str, context: &ScriptContext) -> ScriptOutput {
+    let limits = context.limits.merge(&global_limits(context.database_path));
+    match eval(script, context, &limits) {
+        Ok(output) => output,
+        Err(e) => ScriptOutput::error(match e {
+            ScriptRunnerError::Timeout => ScriptError::new(
+                ScriptErrorKind::Timeout,
+                format!("Timeout after {}ms", limits.timeout_ms()),
+            ),
+            ScriptRunnerError::TooManyOperations => ScriptError::new(
+                ScriptErrorKind::TooManyOperations,
+                format!("More than {} operations", limits.max_operations()),
+            ),
+            ScriptRunnerError::DataTooLarge => ScriptError::new(
+                ScriptErrorKind::DataTooLarge,
+                format!(
+                    "A string over {} characters or an array over {} items",
+                    limits.max_string_length(),
+                    limits.max_array_length()
+                ),
+            ),
+            ScriptRunnerError::OutputTooLarge => ScriptError::new(
+                ScriptErrorKind::OutputTooLarge,
+                format!("Output over {} characters", limits.max_output_length()),
+            ),
+            ScriptRunnerError::Crash => {
+                ScriptError::new(ScriptErrorKind::Crash, "Crash".to_string())
+            }
+            ScriptRunnerError::IO(e) => ScriptError::new(ScriptErrorKind::IO, e.to_string()),
+        }),
     }
 }
 
-fn eval(script: &str, database_path: &str) -> Result<String, ScriptRunnerError> {
+// Don't create a database just to find out it has no limits in it.
+fn global_limits(database_path: &str) -> ScriptLimits {
+    if database_path == Database::memory_path() || !Path::new(database_path).exists() {
+        return ScriptLimits::default();
+    }
+    match Database::connect(Some(database_path.to_string())) {
+        Ok(database) => database.get_script_limits().unwrap_or_default(),
+        Err(_) => ScriptLimits::default(),
+    }
+}
+
+fn eval(
+    script: &str,
+    context: &ScriptContext,
+    limits: &ScriptLimits,
+) -> Result<ScriptOutput, ScriptRunnerError> {
     let mut path = env::current_exe()?;
     path.pop();
     if path.ends_with("deps") {
         path.pop();
     }
     path.push("script_engine");
-    let output = Command::new(path)
+    let mut command = Command::new(path);
+    command
         .args(&[script])
-        .env("WITH_DATABASE", database_path)
-        .output()
-        .unwrap();
-    match output.status.code().unwrap() {
-        100 => Err(ScriptRunnerError::Timeout),
-        128 => Err(ScriptRunnerError::Crash),
-        _ => Ok(output.stdout.iter().map(|c| *c as char).collect::<String>()),
+        .env("WITH_DATABASE", context.database_path)
+        .env("SCRIPT_LIMITS", serde_json::to_string(limits).unwrap())
+        .env("SCRIPT_USER", context.user)
+        .env("SCRIPT_COMMAND", context.command)
+        .env("SCRIPT_TIER", context.tier.to_string())
+        .env(
+            "SCRIPT_INPUTS",
+            serde_json::to_string(context.inputs).unwrap(),
+        );
+    if context.dry_run {
+        command.env("DRY_RUN", "1");
+    }
+    let output = command.output()?;
+    match output.status.code() {
+        Some(100) => Err(ScriptRunnerError::Timeout),
+        Some(110) => Err(ScriptRunnerError::TooManyOperations),
+        Some(111) => Err(ScriptRunnerError::OutputTooLarge),
+        Some(112) => Err(ScriptRunnerError::DataTooLarge),
+        Some(0) => match serde_json::from_slice(&output.stdout) {
+            Ok(output) => Ok(output),
+            Err(_) => Err(ScriptRunnerError::Crash),
+        },
+        _ => Err(ScriptRunnerError::Crash),
     }
 }
@@ -0,0 +1,205 @@
+use std::ops::Range;
+
+// Reads scripts just far enough to find their imports and to check modules, without running
+// them. Strings and comments are skipped, so an `import "x";` inside a string is left alone.
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    // The inside of a "string", escapes left as they are.
+    Text(&'a str),
+    Symbol(char),
+}
+
+// An `import "name";` statement and where it is in the script, the `;` included.
+#[derive(Debug, PartialEq)]
+pub struct Import {
+    pub name: String,
+    pub span: Range<usize>,
+}
+
+// Imports that can't be read, like one after an unclosed string, are left for rhai to report.
+pub fn imports(script: &str) -> Vec<Import> {
+    let tokens = match tokenize(script) {
+        Ok(tokens) => tokens,
+        Err(_) => return Vec::new(),
+    };
+    let mut imports = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        if let Some((name, end, next)) = import_at(&tokens, i) {
+            if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                imports.push(Import {
+                    name: name.to_string(),
+                    span: tokens[i].1.start..end,
+                });
+            }
+            i = next;
+        } else {
+            i += 1;
+        }
+    }
+    imports
+}
+
+// Modules are pulled into every script that imports them, so they can only hold `fn`
+// definitions and imports. Anything else would run, and change the result, of every importer.
+pub fn check_module(source: &str) -> Result<(), String> {
+    let tokens = tokenize(source)?;
+    let mut i = 0;
+    while i < tokens.len() {
+        let (token, span) = &tokens[i];
+        if let Some((_, _, next)) = import_at(&tokens, i) {
+            i = next;
+            continue;
+        }
+        match token {
+            Token::Word("fn") => {
+                let mut depth = 0;
+                let mut opened = false;
+                let close = tokens[i..].iter().position(|(token, _)| {
+                    match token {
+                        Token::Symbol('{') => {
+                            depth += 1;
+                            opened = true;
+                        }
+                        Token::Symbol('}') => depth -= 1,
+                        _ => {}
+                    }
+                    opened && depth == 0
+                });
+                match close {
+                    Some(close) => i += close + 1,
+                    None => {
+                        return Err(format!(
+                            "fn on line {} is never closed",
+                            line_of(source, span.start)
+                        ))
+                    }
+                }
+            }
+            Token::Symbol(';') => i += 1,
+            _ => {
+                return Err(format!(
+                    "Line {}: modules can only hold fn definitions and imports",
+                    line_of(source, span.start)
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+// The name, where the statement ends and the next token, for an import starting at token i.
+fn import_at<'a>(
+    tokens: &[(Token<'a>, Range<usize>)],
+    i: usize,
+) -> Option<(&'a str, usize, usize)> {
+    match (&tokens[i].0, tokens.get(i + 1)) {
+        (Token::Word("import"), Some((Token::Text(name), span))) => match tokens.get(i + 2) {
+            Some((Token::Symbol(';'), semicolon)) => Some((*name, semicolon.end, i + 3)),
+            _ => Some((*name, span.end, i + 2)),
+        },
+        _ => None,
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token<'_>, Range<usize>)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '/' if chars.peek().map(|&(_, c)| c) == Some('/') => {
+                while let Some(&(_, c)) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            '/' if chars.peek().map(|&(_, c)| c) == Some('*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some((_, '/')) if last == '*' => break,
+                        Some((_, c)) => last = c,
+                        None => {
+                            return Err(format!(
+                                "Comment on line {} is never closed",
+                                line_of(source, start)
+                            ))
+                        }
+                    }
+                }
+            }
+            '"' | '\'' => {
+                let end = loop {
+                    match chars.next() {
+                        Some((_, '\\')) => {
+                            chars.next();
+                        }
+                        Some((end, quote)) if quote == c => break end,
+                        Some(_) => {}
+                        None => {
+                            return Err(format!(
+                                "Quote on line {} is never closed",
+                                line_of(source, start)
+                            ))
+                        }
+                    }
+                };
+                let token = if c == '"' {
+                    Token::Text(&source[start + 1..end])
+                } else {
+                    // Characters only matter for being skipped.
+                    Token::Symbol('\'')
+                };
+                tokens.push((token, start..end + 1));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push((Token::Word(&source[start..end]), start..end));
+            }
+            c => tokens.push((Token::Symbol(c), start..start + c.len_utf8())),
+        }
+    }
+    Ok(tokens)
+}
+
+fn line_of(source: &str, index: usize) -> usize {
+    source[..index].matches('\n').count() + 1
+}
+
+#[test]
+fn test_imports() {
+    let script = "import \"a\";\nlet x = \"import \\\"b\\\";\"; // import \"c\";\nimport \"d\" x";
+    let found = imports(script);
+    assert_eq!(
+        found.iter().map(|i| i.name.as_str()).collect::<Vec<_>>(),
+        vec!["a", "d"]
+    );
+    assert_eq!(&script[found[0].span.clone()], "import \"a\";");
+    assert_eq!(&script[found[1].span.clone()], "import \"d\"");
+    assert_eq!(imports("import \"a b\";"), Vec::new());
+}
+
+#[test]
+fn test_check_module() {
+    assert_eq!(
+        check_module("import \"x\";\nfn a(x) { if x { \"}\" } else { '{' } }\n/* } */ fn b() {}"),
+        Ok(())
+    );
+    assert!(check_module("fn a() { 1 }\nset(\"x\", 1);").is_err());
+    assert!(check_module("\"text\"").is_err());
+    assert!(check_module("fn a() { 1").is_err());
+    assert!(check_module("fn a() { \"}").is_err());
+}
@@ -0,0 +1,58 @@
+use crate::models::StringItem;
+use std::cmp::Reverse;
+
+// Indices of the items that contain every word of the query, best first. Words match
+// case-insensitively from their start, so "drag" finds "Dragons". Items where more of the
+// query matches whole words rank higher, then earlier items.
+pub fn search(items: &[StringItem], query: &str) -> Vec<usize> {
+    let query = words(query);
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let mut matches = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        let item_words = words(&item.value);
+        let mut whole_words = 0;
+        let all_match = query.iter().all(|query_word| {
+            if item_words.iter().any(|word| word == query_word) {
+                whole_words += 1;
+                true
+            } else {
+                item_words.iter().any(|word| word.starts_with(query_word))
+            }
+        });
+        if all_match {
+            matches.push((Reverse(whole_words), index));
+        }
+    }
+    matches.sort();
+    matches.into_iter().map(|(_, index)| index).collect()
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+fn items(values: &[&str]) -> Vec<StringItem> {
+    values.iter().map(|value| StringItem::new(value)).collect()
+}
+
+#[test]
+fn test_search() {
+    let quotes = items(&[
+        "I love dragons",
+        "That dragon was a DRAGON",
+        "Nothing to see here",
+        "Dragon, love!",
+    ]);
+    assert_eq!(search(&quotes, "dragon"), vec![1, 3, 0]);
+    assert_eq!(search(&quotes, "LOVE drag"), vec![0, 3]);
+    assert_eq!(search(&quotes, "dragon love"), vec![3, 0]);
+    assert_eq!(search(&quotes, "see nothing"), vec![2]);
+    assert_eq!(search(&quotes, "wyvern"), Vec::<usize>::new());
+    assert_eq!(search(&quotes, " , "), Vec::<usize>::new());
+}
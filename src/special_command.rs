@@ -1,22 +1,44 @@
 use crate::models::{
-    Action, ActionError, Actor, Command, EditType, Message, Source, StringItem, Variable,
-    VariableValue,
+    Action, ActionError, Actor, CacheSettings, Command, EditType, HttpSettings, Message,
+    MessageTarget, ScriptLimits, ScriptModule, ScriptTier, Source, StringItem, Variable,
+    VariableValue, VariableWatcher,
 };
+use crate::schedule;
+use crate::script_source;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+#[cfg(test)]
+use crate::models::User;
+
+// Variables can outlive anything the scheduler would wait for, up to a year.
+const MAX_VARIABLE_TTL_SECS: u64 = 365 * 24 * 60 * 60;
 
 /*
 Variables
     Text
     StringList
+    Map
 
 Commands
     done !variable add <var name> <value>
-    done !variable add <var name> [<value?>] (array)
+    done !variable add <var name> [<value?>, <value?>] (array, see parse_variable_value)
+    !variable add --ttl <delay> <var name> <value> (deleted after the delay, like 30m or 2h, up to 365d)
     done !variable edit <var name> <new value> (should never change form from text -> stringlist or vice versa)
     !variable edit <var name>+ <value to append> (append to array or string)
     !variable edit <var name>- <value to remove> (remove from array or string)
     !variable edit <var name>-# <index to remove> (remove from array or string)
     !variable edit <var name>+# <index to insert at> (insert into array or string)
+    !variable edit <var name>++ [n] / <var name>-- [n] (counters, 1 by default)
+    !variable edit <var name>=0 (reset a counter to 0, or empty a list or map)
+    !variable edit <var name>.<key> <value> (set a key of a map)
+    !variable edit <var name>.<key>- (remove a key from a map)
     !variable delete <var name>
+    !variable show <var name> [index] [page N]
+    !variable find <var name> <words> [page N]
+    !variable watch <var name> [--to <target>] <template> (see add_watcher)
+    !variable unwatch <id>
+    !variable watchers [var name]
 */
 pub fn commands() -> Vec<Command> {
     vec![
@@ -38,6 +60,36 @@ pub fn commands() -> Vec<Command> {
         )
         .with_actor(Actor(delete_command))
         .build(),
+        Command::new(
+            "!command limits".to_string(),
+            "Script limits have been updated".to_string(),
+        )
+        .with_actor(Actor(set_script_limits))
+        .build(),
+        Command::new(
+            "!command cache".to_string(),
+            "Output caching has been updated".to_string(),
+        )
+        .with_actor(Actor(set_output_cache))
+        .build(),
+        Command::new(
+            "!command cache clear".to_string(),
+            "The cached output has been cleared".to_string(),
+        )
+        .with_actor(Actor(clear_output_cache))
+        .build(),
+        Command::new("!command show".to_string(), "".to_string())
+            .with_actor(Actor(show_command))
+            .build(),
+        Command::new(
+            "!command tier".to_string(),
+            "Their commands' scripts have a new tier".to_string(),
+        )
+        .with_actor(Actor(set_script_tier))
+        .build(),
+        Command::new("!command test".to_string(), "".to_string())
+            .with_actor(Actor(test_command))
+            .build(),
         Command::new(
             "!variable add".to_string(),
             "Your variable has been added".to_string(),
@@ -56,6 +108,84 @@ pub fn commands() -> Vec<Command> {
         )
         .with_actor(Actor(delete_variable))
         .build(),
+        Command::new("!variable show".to_string(), "".to_string())
+            .with_actor(Actor(show_variable))
+            .build(),
+        Command::new("!variable find".to_string(), "".to_string())
+            .with_actor(Actor(find_in_variable))
+            .build(),
+        Command::new(
+            "!variable watch".to_string(),
+            "The watcher has been added".to_string(),
+        )
+        .with_actor(Actor(add_watcher))
+        .build(),
+        Command::new(
+            "!variable unwatch".to_string(),
+            "The watcher has been removed".to_string(),
+        )
+        .with_actor(Actor(delete_watcher))
+        .build(),
+        Command::new("!variable watchers".to_string(), "".to_string())
+            .with_actor(Actor(list_watchers))
+            .build(),
+        Command::new(
+            "!module add".to_string(),
+            "Your module has been added".to_string(),
+        )
+        .with_actor(Actor(add_module))
+        .build(),
+        Command::new(
+            "!module edit".to_string(),
+            "Your module has been edited".to_string(),
+        )
+        .with_actor(Actor(edit_module))
+        .build(),
+        Command::new(
+            "!module delete".to_string(),
+            "Your module has been deleted".to_string(),
+        )
+        .with_actor(Actor(delete_module))
+        .build(),
+        Command::new("!eval".to_string(), "".to_string())
+            .with_actor(Actor(eval_script))
+            .build(),
+        Command::new(
+            "!http allow".to_string(),
+            "Scripts can now fetch from that domain".to_string(),
+        )
+        .with_actor(Actor(allow_http_domain))
+        .build(),
+        Command::new(
+            "!http disallow".to_string(),
+            "Scripts can no longer fetch from that domain".to_string(),
+        )
+        .with_actor(Actor(disallow_http_domain))
+        .build(),
+        Command::new(
+            "!http limits".to_string(),
+            "HTTP limits have been updated".to_string(),
+        )
+        .with_actor(Actor(set_http_limits))
+        .build(),
+        Command::new("!schedule list".to_string(), "".to_string())
+            .with_actor(Actor(list_scheduled_tasks))
+            .build(),
+        Command::new(
+            "!schedule delete".to_string(),
+            "The scheduled task has been deleted".to_string(),
+        )
+        .with_actor(Actor(delete_scheduled_task))
+        .build(),
+        Command::new("!uservar show".to_string(), "".to_string())
+            .with_actor(Actor(show_user_variables))
+            .build(),
+        Command::new(
+            "!uservar clear".to_string(),
+            "Their variables have been cleared".to_string(),
+        )
+        .with_actor(Actor(clear_user_variables))
+        .build(),
         Command::new("!notify".to_string(), "Notification sent!".to_string())
             .with_actor(Actor(send_live_notification))
             .build(),
@@ -64,12 +194,20 @@ pub fn commands() -> Vec<Command> {
 
 fn add_command(command: &Command, message: &Message) -> Result<Action, ActionError> {
     let (trigger, response) = parse_command_message(command, message)?;
-    Ok(Action::AddCommand(Command::new(trigger, response)))
+    Ok(Action::AddCommand(
+        Command::new(trigger, response)
+            .with_author(message.sender_identity())
+            .build(),
+    ))
 }
 
 fn edit_command(command: &Command, message: &Message) -> Result<Action, ActionError> {
     let (trigger, response) = parse_command_message(command, message)?;
-    Ok(Action::EditCommand(Command::new(trigger, response)))
+    Ok(Action::EditCommand(
+        Command::new(trigger, response)
+            .with_author(message.sender_identity())
+            .build(),
+    ))
 }
 
 fn delete_command(command: &Command, message: &Message) -> Result<Action, ActionError> {
@@ -85,6 +223,211 @@ fn delete_command(command: &Command, message: &Message) -> Result<Action, Action
     }
 }
 
+// !command show <trigger> [page N]
+fn show_command(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    let (trigger, page) = parse_page(message.after_trigger(&command.trigger));
+    if !trigger.starts_with('!') {
+        return Err(ActionError::BadCommandTriggerPrefix);
+    }
+    Ok(Action::ShowCommand(trigger.to_string(), page))
+}
+
+// "<text> page N", where the page is 1 when not given.
+fn parse_page(text: &str) -> (&str, usize) {
+    let text = text.trim();
+    if let Some(i) = text.rfind(" page ") {
+        if let Ok(page) = text[i + " page ".len()..].trim().parse::<usize>() {
+            return (text[..i].trim(), page);
+        }
+    }
+    (text, 1)
+}
+
+// !command tier <twitch:name|discord:id> <read|write|network|actions>
+// Applies to every command that user has written, see ScriptTier.
+fn set_script_tier(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    let text = message.after_trigger(&command.trigger);
+    let mut parts = text.split_whitespace();
+    let identity = parse_identity(parts.next().unwrap_or(""))?;
+    let tier = ScriptTier::parse(parts.next().unwrap_or(""))?;
+    Ok(Action::SetScriptTier(identity, tier))
+}
+
+// !command test <trigger> [args] [=> <unsaved response>]
+fn test_command(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    let text = message.after_trigger(&command.trigger);
+    let mut parts = text.splitn(2, "=>");
+    let text = parts.next().unwrap_or("").trim();
+    let response = parts.next().map(|response| response.trim().to_string());
+    if !text.starts_with('!') {
+        return Err(ActionError::BadCommandTriggerPrefix);
+    }
+    Ok(Action::TestCommand(text.to_string(), response))
+}
+
+// !eval <script>
+fn eval_script(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    let script = message.after_trigger(&command.trigger).trim();
+    if script.is_empty() {
+        return Err(ActionError::BadCommand("No script to run".to_string()));
+    }
+    Ok(Action::EvalScript(script.to_string()))
+}
+
+// !command limits <trigger|global> [timeout=ms] [operations=n] [strings=n] [arrays=n] [output=n]
+// Limits that aren't given fall back to the global limits, so no limits resets them.
+fn set_script_limits(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    let text = message.after_trigger(&command.trigger);
+    let mut parts = text.splitn(2, ' ');
+    let target = parts.next().unwrap_or("");
+    let limits = ScriptLimits::parse(parts.next().unwrap_or(""))?;
+    if target == "global" {
+        Ok(Action::SetScriptLimits(None, limits))
+    } else if !target.starts_with('!') {
+        Err(ActionError::BadCommandTriggerPrefix)
+    } else {
+        Ok(Action::SetScriptLimits(Some(target.to_string()), limits))
+    }
+}
+
+// !command cache <trigger> ttl=seconds [key=args|user|none] [persist]
+// !command cache <trigger> off
+fn set_output_cache(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    let text = message.after_trigger(&command.trigger);
+    let mut parts = text.splitn(2, ' ');
+    let target = parts.next().unwrap_or("");
+    if !target.starts_with('!') {
+        return Err(ActionError::BadCommandTriggerPrefix);
+    }
+    let settings = match parts.next().unwrap_or("").trim() {
+        "off" => None,
+        settings => Some(CacheSettings::parse(settings)?),
+    };
+    Ok(Action::SetOutputCache(target.to_string(), settings))
+}
+
+// !command cache clear [trigger], which clears every command's output without a trigger.
+fn clear_output_cache(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    let target = message.after_trigger(&command.trigger).trim();
+    if target.is_empty() {
+        Ok(Action::ClearOutputCache(None))
+    } else if !target.starts_with('!') {
+        Err(ActionError::BadCommandTriggerPrefix)
+    } else {
+        Ok(Action::ClearOutputCache(Some(target.to_string())))
+    }
+}
+
+// !http allow <domain>, which also allows its subdomains.
+fn allow_http_domain(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    Ok(Action::AllowHttpDomain(parse_http_domain(
+        command, message,
+    )?))
+}
+
+// !http disallow <domain>
+fn disallow_http_domain(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    Ok(Action::DisallowHttpDomain(parse_http_domain(
+        command, message,
+    )?))
+}
+
+// !http limits [timeout=ms] [size=bytes] [ttl=seconds]
+fn set_http_limits(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    let text = message.after_trigger(&command.trigger);
+    Ok(Action::SetHttpLimits(HttpSettings::parse_limits(text)?))
+}
+
+fn parse_http_domain(command: &Command, message: &Message) -> Result<String, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    let domain = message
+        .after_trigger(&command.trigger)
+        .trim()
+        .trim_end_matches('.')
+        .to_lowercase();
+    let valid = Regex::new(r"^[a-z0-9-]+(\.[a-z0-9-]+)*$").unwrap();
+    if valid.is_match(&domain) {
+        Ok(domain)
+    } else {
+        Err(ActionError::BadHttpSettings(domain))
+    }
+}
+
+fn list_scheduled_tasks(_: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    Ok(Action::ListScheduledTasks)
+}
+
+// !schedule delete <id>, with the id from !schedule list.
+fn delete_scheduled_task(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    match message
+        .after_trigger(&command.trigger)
+        .trim()
+        .trim_start_matches('#')
+        .parse::<i32>()
+    {
+        Ok(id) => Ok(Action::DeleteScheduledTask(id)),
+        Err(_) => Err(ActionError::ScheduledTaskDoesNotExist),
+    }
+}
+
+// !uservar show <twitch:name|discord:id>
+fn show_user_variables(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    Ok(Action::ShowUserVariables(parse_user_identity(
+        command, message,
+    )?))
+}
+
+// !uservar clear <twitch:name|discord:id>
+fn clear_user_variables(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    Ok(Action::ClearUserVariables(parse_user_identity(
+        command, message,
+    )?))
+}
+
+fn parse_user_identity(command: &Command, message: &Message) -> Result<String, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    parse_identity(message.after_trigger(&command.trigger).trim())
+}
+
+fn parse_identity(identity: &str) -> Result<String, ActionError> {
+    let mut parts = identity.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("twitch"), Some(name)) if !name.is_empty() => {
+            Ok(format!("twitch:{}", name.to_lowercase()))
+        }
+        (Some("discord"), Some(id)) if id.parse::<u64>().is_ok() => Ok(identity.to_string()),
+        _ => Err(ActionError::BadUserIdentity(identity.to_string())),
+    }
+}
+
 fn parse_command_message(
     command: &Command,
     message: &Message,
@@ -104,20 +447,150 @@ fn parse_command_message(
     }
 }
 
+// !variable add [--ttl <delay>] <name> <value>, where the delay is like 30m, 2h or 90d.
 fn add_variable(command: &Command, message: &Message) -> Result<Action, ActionError> {
-    let (name, value, edit_type) = parse_variable_message(command, message)?;
+    let text = message.after_trigger(&command.trigger).trim();
+    let (time_expires, text) = if text.starts_with("--ttl ") {
+        let (ttl, rest) = split_first_word(text["--ttl ".len()..].trim_start());
+        match schedule::parse_duration(ttl) {
+            Some(secs) if secs > 0 && secs <= MAX_VARIABLE_TTL_SECS => {
+                (Some(time::get_time().sec + secs as i64), rest)
+            }
+            _ => return Err(ActionError::BadVariableTtl(ttl.to_string())),
+        }
+    } else {
+        (None, text)
+    };
+    let (name, value, edit_type) = parse_variable_text(text, message)?;
     if edit_type != EditType::Overwrite() {
         Err(ActionError::VariableEditTypeNotSupported)
     } else {
-        Ok(Action::AddVariable(Variable::new(name, value)))
+        let mut variable = Variable::new(name, value);
+        variable.time_expires = time_expires;
+        Ok(Action::AddVariable(variable))
     }
 }
 
+// name.key edits a key of a map. The bot only knows whether name is a map, so it goes back to
+// edit_variable_by_name when it isn't.
 fn edit_variable(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    let (name, rest) = split_first_word(message.after_trigger(&command.trigger).trim());
+    if let Some(dot) = name.find('.') {
+        if let Ok((name, value, edit_type)) = parse_key_edit(&name[..dot], &name[dot + 1..], rest) {
+            return Ok(Action::EditVariable(Variable::new(name, value), edit_type));
+        }
+    }
+    edit_variable_by_name(command, message)
+}
+
+// Treats a dot as part of the name, like any other character.
+pub fn edit_variable_by_name(command: &Command, message: &Message) -> Result<Action, ActionError> {
     let (name, value, edit_type) = parse_variable_message(command, message)?;
     Ok(Action::EditVariable(Variable::new(name, value), edit_type))
 }
 
+// !variable show <name> [index] [page N]
+fn show_variable(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    let (text, page) = parse_page(message.after_trigger(&command.trigger));
+    let (name, index) = split_first_word(text);
+    if name.is_empty() {
+        return Err(ActionError::BadVariable(text.to_string()));
+    }
+    let index = match index {
+        "" => None,
+        index => match index.parse::<usize>() {
+            Ok(index) => Some(index),
+            Err(_) => return Err(ActionError::VariableBadEditIndex),
+        },
+    };
+    Ok(Action::ShowVariable(name.to_string(), index, page))
+}
+
+// !variable find <name> <words> [page N], for lists, see search.rs.
+fn find_in_variable(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    let (text, page) = parse_page(message.after_trigger(&command.trigger));
+    let (name, query) = split_first_word(text);
+    if name.is_empty() || query.is_empty() {
+        return Err(ActionError::BadVariable(text.to_string()));
+    }
+    Ok(Action::FindInVariable(
+        name.to_string(),
+        query.to_string(),
+        page,
+    ))
+}
+
+// !variable watch <name> [--to twitch:<channel>|discord:<channel>|admin] <template>
+// The output goes to where the watcher was added, unless another target is given.
+// $old and $new in the template are the values as text. Inside {{ }} they are already strings,
+// like {{int($new) - int($old)}}.
+fn add_watcher(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    let text = message.after_trigger(&command.trigger).trim();
+    let (variable, rest) = split_first_word(text);
+    let (target, template) = if rest.starts_with("--to ") {
+        let (target, template) = split_first_word(rest["--to ".len()..].trim_start());
+        (parse_target(target)?, template)
+    } else {
+        match message.reply_target() {
+            Some(target) => (target, rest),
+            None => return Err(ActionError::MessageTargetNotFound),
+        }
+    };
+    if variable.is_empty() || template.is_empty() {
+        return Err(ActionError::BadVariable(text.to_string()));
+    }
+    Ok(Action::AddWatcher(VariableWatcher {
+        id: 0,
+        variable: variable.to_string(),
+        template: template.to_string(),
+        target,
+        author: message.sender_identity(),
+    }))
+}
+
+fn parse_target(target: &str) -> Result<MessageTarget, ActionError> {
+    let mut parts = target.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("admin"), None) => Ok(MessageTarget::Admin),
+        (Some("twitch"), Some(channel)) if !channel.is_empty() => {
+            Ok(MessageTarget::Twitch(channel.to_string()))
+        }
+        (Some("discord"), Some(channel)) if !channel.is_empty() => {
+            Ok(MessageTarget::Discord(channel.to_string()))
+        }
+        _ => Err(ActionError::BadMessageTarget(target.to_string())),
+    }
+}
+
+// !variable unwatch <id>, with the id from !variable watchers.
+fn delete_watcher(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    match message
+        .after_trigger(&command.trigger)
+        .trim()
+        .trim_start_matches('#')
+        .parse::<i32>()
+    {
+        Ok(id) => Ok(Action::DeleteWatcher(id)),
+        Err(_) => Err(ActionError::WatcherDoesNotExist),
+    }
+}
+
+fn list_watchers(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    if !message.is_admin() {
+        return Err(ActionError::PermissionDenied);
+    }
+    match message.after_trigger(&command.trigger).trim() {
+        "" => Ok(Action::ListWatchers(None)),
+        name => Ok(Action::ListWatchers(Some(name.to_string()))),
+    }
+}
+
 fn delete_variable(command: &Command, message: &Message) -> Result<Action, ActionError> {
     let (name, value, edit_type) = parse_variable_message(command, message)?;
     if edit_type != EditType::Overwrite() {
@@ -131,75 +604,306 @@ fn parse_variable_message(
     command: &Command,
     message: &Message,
 ) -> Result<(String, VariableValue, EditType), ActionError> {
-    let variable = message.after_trigger(&command.trigger);
-    let parts: Vec<&str> = variable.split(' ').collect();
-    if parts.is_empty() {
-        Err(ActionError::BadVariable(variable.to_string()))
-    } else if parts.len() == 1 {
-        let name = parts[0];
-        Ok((
-            name.to_string(),
-            VariableValue::Text("".to_string()),
-            EditType::Overwrite(),
-        ))
+    parse_variable_text(message.after_trigger(&command.trigger).trim(), message)
+}
+
+fn parse_variable_text(
+    variable: &str,
+    message: &Message,
+) -> Result<(String, VariableValue, EditType), ActionError> {
+    let (name, rest) = split_first_word(variable);
+    if name.is_empty() {
+        return Err(ActionError::BadVariable(variable.to_string()));
+    }
+    let (name, edit_type, value) = if name.ends_with("+#") || name.ends_with("-#") {
+        let (index, value) = split_first_word(rest);
+        let index = match index.parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => return Err(ActionError::VariableBadEditIndex),
+        };
+        if name.ends_with("-#") {
+            (&name[..name.len() - 2], EditType::RemoveAt(index), "")
+        } else if value.is_empty() {
+            return Err(ActionError::VariableBadEditIndexValue);
+        } else {
+            (&name[..name.len() - 2], EditType::InsertAt(index), value)
+        }
+    } else if name.ends_with("++") || name.ends_with("--") {
+        let by = match rest {
+            "" => 1,
+            by => match by.parse::<i64>() {
+                Ok(by) => by,
+                Err(_) => return Err(ActionError::BadVariable(by.to_string())),
+            },
+        };
+        let edit_type = if name.ends_with("++") {
+            EditType::Increment(by)
+        } else {
+            EditType::Decrement(by)
+        };
+        (&name[..name.len() - 2], edit_type, "")
+    } else if name.ends_with("=0") {
+        (&name[..name.len() - 2], EditType::Reset(), "")
+    } else if name.ends_with('+') {
+        (&name[..name.len() - 1], EditType::Append(), rest)
+    } else if name.ends_with('-') {
+        (&name[..name.len() - 1], EditType::Remove(), rest)
     } else {
-        let mut name = parts[0];
-        let mut edit_type = EditType::Overwrite();
-        let mut value = parts[1..].join(" ");
-
-        if name.ends_with("+") {
-            edit_type = EditType::Append();
-            name = &name[0..name.len() - 1];
-        } else if name.ends_with("-") {
-            edit_type = EditType::Remove();
-            name = &name[0..name.len() - 1];
-        } else if name.ends_with("+#") {
-            match parse_variable_index(parts) {
-                Ok((index, v)) => {
-                    edit_type = EditType::InsertAt(index);
-                    match v {
-                        Some(v) => value = v,
-                        None => return Err(ActionError::VariableBadEditIndexValue),
-                    }
-                }
-                Err(err) => return Err(err),
-            }
-            name = &name[0..name.len() - 2];
-        } else if name.ends_with("-#") {
-            match parse_variable_index(parts) {
-                Ok((index, _)) => {
-                    edit_type = EditType::RemoveAt(index);
-                    value = "".to_string();
-                }
-                Err(err) => return Err(err),
-            }
-            name = &name[0..name.len() - 2];
+        (name, EditType::Overwrite(), rest)
+    };
+    let (fields, value) = parse_fields(value)?;
+    let mut value = parse_variable_value(value)?;
+    // List items added from chat remember who added them and where.
+    if let VariableValue::StringList(items) = &mut value {
+        for item in items.iter_mut() {
+            item.added_by = message.sender.username.clone();
+            item.added_in = message.reply_target();
+            item.fields = fields.clone();
         }
+    }
+    Ok((name.to_string(), value, edit_type))
+}
 
-        if value.starts_with("[") && value.ends_with("]") {
-            let vec = if value == "[]" {
-                Vec::new()
-            } else {
-                vec![StringItem::new(&value[1..value.len() - 1])]
-            };
+// <name>.<key> <text> sets a key of a map, <name>.<key>- removes it.
+fn parse_key_edit(
+    name: &str,
+    key: &str,
+    rest: &str,
+) -> Result<(String, VariableValue, EditType), ActionError> {
+    if name.is_empty() || key.is_empty() || key == "-" {
+        return Err(ActionError::BadVariable(format!("{}.{}", name, key)));
+    }
+    if key.ends_with('-') && rest.is_empty() {
+        let key = key[..key.len() - 1].to_string();
+        let placeholder = VariableValue::Text("".to_string());
+        return Ok((name.to_string(), placeholder, EditType::RemoveKey(key)));
+    }
+    match parse_variable_value(rest)? {
+        VariableValue::Text(text) if !text.is_empty() => Ok((
+            name.to_string(),
+            VariableValue::Text(text),
+            EditType::SetKey(key.to_string()),
+        )),
+        VariableValue::Text(_) => Err(ActionError::BadVariable(rest.to_string())),
+        _ => Err(ActionError::VariableWrongType),
+    }
+}
 
-            Ok((name.to_string(), VariableValue::StringList(vec), edit_type))
+// Leading "--field key=value" options for list items, where the value can be quoted.
+fn parse_fields(text: &str) -> Result<(BTreeMap<String, String>, &str), ActionError> {
+    let mut fields = BTreeMap::new();
+    let mut text = text;
+    while text.starts_with("--field ") {
+        let bad = || ActionError::BadVariable(text.to_string());
+        let option = text["--field ".len()..].trim_start();
+        let equals = option.find('=').ok_or_else(bad)?;
+        let key = &option[..equals];
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(bad());
+        }
+        let rest = &option[equals + 1..];
+        let (value, rest) = if rest.starts_with('"') || rest.starts_with('\'') {
+            let mut chars = rest.chars().peekable();
+            let value = parse_quoted(&mut chars).ok_or_else(bad)?;
+            let remaining: String = chars.collect();
+            (value, &rest[rest.len() - remaining.len()..])
         } else {
-            Ok((name.to_string(), VariableValue::Text(value), edit_type))
+            let (value, rest) = split_first_word(rest);
+            (value.to_string(), rest)
+        };
+        fields.insert(key.to_lowercase(), value);
+        text = rest.trim_start();
+    }
+    Ok((fields, text))
+}
+
+fn split_first_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+// Values are taken as:
+//     --item <text>          a list of one item, verbatim, for things like !quote add
+//     ["a", "b"] or "a"      JSON, a list of strings or a string
+//     {"key": "value"}       JSON, a map of keys to text
+//     [a, "b, c", d\,e]      a list; items can be quoted, and \ escapes the next character
+//     "some text"            text, without the quotes, if the quotes wrap all of it
+//     anything else          text, as is
+fn parse_variable_value(text: &str) -> Result<VariableValue, ActionError> {
+    let text = text.trim();
+    if text == "--item" || text.starts_with("--item ") {
+        let item = text["--item".len()..].trim();
+        return Ok(VariableValue::StringList(vec![StringItem::new(item)]));
+    }
+    if text.starts_with('[') || text.starts_with('"') || text.starts_with('{') {
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(text) {
+            return json_variable_value(value, text);
         }
     }
+    if text.starts_with('[') && text.ends_with(']') {
+        let items = parse_list(&text[1..text.len() - 1])
+            .ok_or_else(|| ActionError::BadVariable(text.to_string()))?;
+        return Ok(VariableValue::StringList(items));
+    }
+    let mut chars = text.chars().peekable();
+    match chars.peek().copied() {
+        Some('"') | Some('\'') => match parse_quoted(&mut chars) {
+            Some(unquoted) if chars.next().is_none() => Ok(VariableValue::Text(unquoted)),
+            _ => Ok(VariableValue::Text(text.to_string())),
+        },
+        _ => Ok(VariableValue::Text(text.to_string())),
+    }
 }
 
-fn parse_variable_index(parts: Vec<&str>) -> Result<(usize, Option<String>), ActionError> {
-    match parts[1].parse::<usize>() {
-        Ok(index) => {
-            if parts.len() == 2 {
-                Ok((index, None))
-            } else {
-                Ok((index, Some(parts[2..].join(" "))))
+fn json_variable_value(value: serde_json::Value, text: &str) -> Result<VariableValue, ActionError> {
+    let bad = || ActionError::BadVariable(text.to_string());
+    match value {
+        serde_json::Value::String(value) => Ok(VariableValue::Text(value)),
+        serde_json::Value::Array(values) => {
+            let mut items = Vec::new();
+            for value in values {
+                items.push(StringItem::new(&match value {
+                    serde_json::Value::String(value) => value,
+                    serde_json::Value::Number(value) => value.to_string(),
+                    serde_json::Value::Bool(value) => value.to_string(),
+                    _ => return Err(bad()),
+                }));
+            }
+            Ok(VariableValue::StringList(items))
+        }
+        serde_json::Value::Object(values) => {
+            let mut map = BTreeMap::new();
+            for (key, value) in values {
+                map.insert(
+                    key,
+                    match value {
+                        serde_json::Value::String(value) => value,
+                        serde_json::Value::Number(value) => value.to_string(),
+                        serde_json::Value::Bool(value) => value.to_string(),
+                        _ => return Err(bad()),
+                    },
+                );
             }
+            Ok(VariableValue::Map(map))
+        }
+        _ => Err(bad()),
+    }
+}
+
+// The inside of [a, "b, c", d], or None when it's malformed. Empty unquoted items are skipped,
+// so a trailing comma is fine.
+fn parse_list(text: &str) -> Option<Vec<StringItem>> {
+    let mut items = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        let item = match chars.peek().copied() {
+            None => break,
+            Some('"') | Some('\'') => {
+                let item = parse_quoted(&mut chars)?;
+                while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                    chars.next();
+                }
+                Some(item)
+            }
+            Some(_) => {
+                let mut item = String::new();
+                while let Some(&c) = chars.peek() {
+                    match c {
+                        ',' => break,
+                        '\\' => {
+                            chars.next();
+                            item.push(chars.next()?);
+                        }
+                        _ => item.push(chars.next()?),
+                    }
+                }
+                let item = item.trim().to_string();
+                if item.is_empty() {
+                    None
+                } else {
+                    Some(item)
+                }
+            }
+        };
+        if let Some(item) = item {
+            items.push(StringItem::new(&item));
+        }
+        match chars.next() {
+            Some(',') | None => {}
+            Some(_) => return None,
+        }
+    }
+    Some(items)
+}
+
+// Reads a string wrapped in matching ' or " quotes, where \ escapes the next character.
+fn parse_quoted<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>) -> Option<String> {
+    let quote = chars.next()?;
+    let mut text = String::new();
+    loop {
+        match chars.next()? {
+            '\\' => text.push(chars.next()?),
+            c if c == quote => return Some(text),
+            c => text.push(c),
         }
-        Err(_) => Err(ActionError::VariableBadEditIndex),
+    }
+}
+
+// !module add <name> <source>, scripts then use it with import "<name>";
+fn add_module(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    let (name, source) = parse_module_message(command, message)?;
+    if source.is_empty() {
+        return Err(ActionError::BadModule(name));
+    }
+    script_source::check_module(&source).map_err(ActionError::BadModuleSource)?;
+    Ok(Action::AddModule(ScriptModule::new(
+        name,
+        source,
+        message.sender_identity(),
+    )))
+}
+
+fn edit_module(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    let (name, source) = parse_module_message(command, message)?;
+    if source.is_empty() {
+        return Err(ActionError::BadModule(name));
+    }
+    script_source::check_module(&source).map_err(ActionError::BadModuleSource)?;
+    Ok(Action::EditModule(ScriptModule::new(
+        name,
+        source,
+        message.sender_identity(),
+    )))
+}
+
+fn delete_module(command: &Command, message: &Message) -> Result<Action, ActionError> {
+    let (name, _) = parse_module_message(command, message)?;
+    Ok(Action::DeleteModule(ScriptModule::new(
+        name,
+        "".to_string(),
+        message.sender_identity(),
+    )))
+}
+
+fn parse_module_message(
+    command: &Command,
+    message: &Message,
+) -> Result<(String, String), ActionError> {
+    let module = message.after_trigger(&command.trigger);
+    let mut parts = module.splitn(2, ' ');
+    let name = parts.next().unwrap_or("");
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Err(ActionError::BadModule(name.to_string()))
+    } else {
+        Ok((
+            name.to_string(),
+            parts.next().unwrap_or("").trim().to_string(),
+        ))
     }
 }
 
@@ -215,3 +919,254 @@ fn send_live_notification(_: &Command, message: &Message) -> Result<Action, Acti
         _ => Err(ActionError::None),
     }
 }
+
+#[cfg(test)]
+fn parse_variable(text: &str) -> Result<(String, VariableValue, EditType), ActionError> {
+    let command = Command::new("!variable edit".to_string(), "".to_string());
+    parse_variable_message(&command, &Message::new(text.to_string()))
+}
+
+#[cfg(test)]
+fn list_values(value: VariableValue) -> Vec<String> {
+    match value {
+        VariableValue::StringList(items) => items.into_iter().map(|item| item.value).collect(),
+        other => panic!("Expected a list, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_variable_text() {
+    let (name, value, edit_type) =
+        parse_variable("!variable edit  greeting   hi   there ").unwrap();
+    assert_eq!(name, "greeting");
+    assert_eq!(value, VariableValue::Text("hi   there".to_string()));
+    assert_eq!(edit_type, EditType::Overwrite());
+
+    let (_, value, _) = parse_variable("!variable edit greeting \"  padded \"").unwrap();
+    assert_eq!(value, VariableValue::Text("  padded ".to_string()));
+    let (_, value, _) = parse_variable("!variable edit greeting 'it\\'s'").unwrap();
+    assert_eq!(value, VariableValue::Text("it's".to_string()));
+    // Quotes that don't wrap the whole value are kept.
+    let (_, value, _) = parse_variable("!variable edit greeting \"Hi\" she said").unwrap();
+    assert_eq!(value, VariableValue::Text("\"Hi\" she said".to_string()));
+}
+
+#[test]
+fn test_parse_variable_lists() {
+    let (_, value, _) = parse_variable("!variable edit list [a, b,  c ]").unwrap();
+    assert_eq!(list_values(value), vec!["a", "b", "c"]);
+    let (_, value, _) = parse_variable("!variable edit list [\"a, b\", 'c]', d\\,e,]").unwrap();
+    assert_eq!(list_values(value), vec!["a, b", "c]", "d,e"]);
+    let (_, value, _) = parse_variable("!variable edit list [\"a\", \"b \\\"c\\\"\", 3]").unwrap();
+    assert_eq!(list_values(value), vec!["a", "b \"c\"", "3"]);
+    let (_, value, _) = parse_variable("!variable edit list []").unwrap();
+    assert_eq!(list_values(value), Vec::<String>::new());
+    let (_, value, _) = parse_variable("!variable edit quotes+ --item [a, b], c").unwrap();
+    assert_eq!(list_values(value), vec!["[a, b], c"]);
+
+    assert!(parse_variable("!variable edit list [\"a, b]").is_err());
+    assert!(parse_variable("!variable edit list [\"a\" b]").is_err());
+    assert!(parse_variable("!variable edit list [{\"a\": 1}]").is_err());
+}
+
+#[test]
+fn test_parse_variable_edits() {
+    let (name, value, edit_type) = parse_variable("!variable edit list+# 2 [x, y]").unwrap();
+    assert_eq!(name, "list");
+    assert_eq!(list_values(value), vec!["x", "y"]);
+    assert_eq!(edit_type, EditType::InsertAt(2));
+
+    let (name, _, edit_type) = parse_variable("!variable edit list-# 0").unwrap();
+    assert_eq!(name, "list");
+    assert_eq!(edit_type, EditType::RemoveAt(0));
+
+    let (name, value, edit_type) = parse_variable("!variable edit list- [x]").unwrap();
+    assert_eq!(name, "list");
+    assert_eq!(list_values(value), vec!["x"]);
+    assert_eq!(edit_type, EditType::Remove());
+
+    assert!(parse_variable("!variable edit list+# two [x]").is_err());
+    assert!(parse_variable("!variable edit list+# 2").is_err());
+}
+
+#[test]
+fn test_parse_variable_fields() {
+    let (_, value, _) = parse_variable(
+        "!variable edit quotes+ --field game=\"Elden Ring\" --field Mood=good --item Hi",
+    )
+    .unwrap();
+    let items = match value {
+        VariableValue::StringList(items) => items,
+        other => panic!("Expected a list, got {:?}", other),
+    };
+    assert_eq!(items[0].value, "Hi");
+    assert_eq!(items[0].added_by, "foo");
+    assert_eq!(items[0].fields.get("game"), Some(&"Elden Ring".to_string()));
+    assert_eq!(items[0].fields.get("mood"), Some(&"good".to_string()));
+    assert!(parse_variable("!variable edit quotes+ --field game --item Hi").is_err());
+}
+
+#[test]
+fn test_parse_variable_counters() {
+    let (name, _, edit_type) = parse_variable("!variable edit deaths++").unwrap();
+    assert_eq!(name, "deaths");
+    assert_eq!(edit_type, EditType::Increment(1));
+    let (_, _, edit_type) = parse_variable("!variable edit deaths-- 3").unwrap();
+    assert_eq!(edit_type, EditType::Decrement(3));
+    let (name, _, edit_type) = parse_variable("!variable edit deaths=0").unwrap();
+    assert_eq!(name, "deaths");
+    assert_eq!(edit_type, EditType::Reset());
+    assert!(parse_variable("!variable edit deaths++ lots").is_err());
+}
+
+#[cfg(test)]
+fn parse_edit(text: &str) -> (String, VariableValue, EditType) {
+    let command = Command::new("!variable edit".to_string(), "".to_string());
+    match edit_variable(&command, &Message::new(text.to_string())) {
+        Ok(Action::EditVariable(variable, edit_type)) => (variable.name, variable.value, edit_type),
+        _ => panic!("Expected EditVariable"),
+    }
+}
+
+#[test]
+fn test_parse_variable_keys() {
+    let (name, value, edit_type) = parse_edit("!variable edit socials.twitter @stovoy");
+    assert_eq!(name, "socials");
+    assert_eq!(value, VariableValue::Text("@stovoy".to_string()));
+    assert_eq!(edit_type, EditType::SetKey("twitter".to_string()));
+    let (name, _, edit_type) = parse_edit("!variable edit socials.twitter-");
+    assert_eq!(name, "socials");
+    assert_eq!(edit_type, EditType::RemoveKey("twitter".to_string()));
+    // Dashes inside a key are kept when a value follows.
+    let (_, _, edit_type) = parse_edit("!variable edit socials.x- @stovoy");
+    assert_eq!(edit_type, EditType::SetKey("x-".to_string()));
+
+    let (_, value, _) =
+        parse_variable("!variable edit socials {\"twitter\": \"@stovoy\"}").unwrap();
+    let mut map = BTreeMap::new();
+    map.insert("twitter".to_string(), "@stovoy".to_string());
+    assert_eq!(value, VariableValue::Map(map));
+
+    // Anything that can't be a key edit is an edit of a variable with a dot in its name.
+    for text in &[
+        "!variable edit .twitter @stovoy",
+        "!variable edit socials. @stovoy",
+        "!variable edit socials.twitter [a, b]",
+    ] {
+        let (name, _, edit_type) = parse_edit(text);
+        assert!(name.contains('.'));
+        assert_eq!(edit_type, EditType::Overwrite());
+    }
+    let (name, _, edit_type) = parse_variable("!variable edit v1.2 3").unwrap();
+    assert_eq!(name, "v1.2");
+    assert_eq!(edit_type, EditType::Overwrite());
+    let command = Command::new("!variable delete".to_string(), "".to_string());
+    match delete_variable(&command, &Message::new("!variable delete v1.2".to_string())) {
+        Ok(Action::DeleteVariable(variable)) => assert_eq!(variable.name, "v1.2"),
+        _ => panic!("Expected DeleteVariable"),
+    }
+}
+
+#[test]
+fn test_add_variable_ttl() {
+    let command = Command::new("!variable add".to_string(), "".to_string());
+    let now = time::get_time().sec;
+    match add_variable(
+        &command,
+        &Message::new("!variable add --ttl 2h keyword dragon".to_string()),
+    ) {
+        Ok(Action::AddVariable(variable)) => {
+            assert_eq!(variable.name, "keyword");
+            assert_eq!(variable.value, VariableValue::Text("dragon".to_string()));
+            let time_expires = variable.time_expires.unwrap();
+            assert!(time_expires >= now + 7200 && time_expires <= now + 7201);
+        }
+        _ => panic!("Expected AddVariable"),
+    }
+    match add_variable(
+        &command,
+        &Message::new("!variable add --ttl soon keyword dragon".to_string()),
+    ) {
+        Err(ActionError::BadVariableTtl(_)) => {}
+        _ => panic!("Expected BadVariableTtl"),
+    }
+    // Longer than anything can be scheduled.
+    match add_variable(
+        &command,
+        &Message::new("!variable add --ttl 30d keyword dragon".to_string()),
+    ) {
+        Ok(Action::AddVariable(variable)) => {
+            assert!(variable.time_expires.unwrap() >= now + 30 * 24 * 60 * 60)
+        }
+        _ => panic!("Expected AddVariable"),
+    }
+    match add_variable(
+        &command,
+        &Message::new("!variable add --ttl 400d keyword dragon".to_string()),
+    ) {
+        Err(ActionError::BadVariableTtl(ttl)) => assert_eq!(ttl, "400d"),
+        _ => panic!("Expected BadVariableTtl"),
+    }
+}
+
+#[test]
+fn test_add_watcher() {
+    let command = Command::new("!variable watch".to_string(), "".to_string());
+    let admin_message = |text: &str| Message {
+        sender: User {
+            username: "stovoy".to_string(),
+        },
+        text: text.to_string(),
+        source: Source::Admin,
+    };
+    match add_watcher(
+        &command,
+        &admin_message("!variable watch deaths --to discord:general $new deaths"),
+    ) {
+        Ok(Action::AddWatcher(watcher)) => {
+            assert_eq!(watcher.variable, "deaths");
+            assert_eq!(watcher.template, "$new deaths");
+            assert_eq!(
+                watcher.target,
+                MessageTarget::Discord("general".to_string())
+            );
+            assert_eq!(watcher.author, "admin");
+        }
+        _ => panic!("Expected AddWatcher"),
+    }
+    match add_watcher(&command, &admin_message("!variable watch goal Goal: $new")) {
+        Ok(Action::AddWatcher(watcher)) => {
+            assert_eq!(watcher.template, "Goal: $new");
+            assert_eq!(watcher.target, MessageTarget::Admin);
+        }
+        _ => panic!("Expected AddWatcher"),
+    }
+    match add_watcher(
+        &command,
+        &admin_message("!variable watch goal --to irc:foo Goal"),
+    ) {
+        Err(ActionError::BadMessageTarget(_)) => {}
+        _ => panic!("Expected BadMessageTarget"),
+    }
+    match add_watcher(
+        &command,
+        &Message::new("!variable watch goal Goal: $new".to_string()),
+    ) {
+        Err(ActionError::PermissionDenied) => {}
+        _ => panic!("Expected PermissionDenied"),
+    }
+}
+
+#[test]
+fn test_add_module() {
+    let command = Command::new("!module add".to_string(), "".to_string());
+    let add = |text: &str| add_module(&command, &Message::new(text.to_string()));
+    match add("!module add greet fn greet(x) { \"Hi \" + x }") {
+        Ok(Action::AddModule(module)) => assert_eq!(module.name, "greet"),
+        _ => panic!("Expected AddModule"),
+    }
+    match add("!module add greet fn greet(x) { x }\nset(\"x\", 1);") {
+        Err(ActionError::BadModuleSource(_)) => {}
+        _ => panic!("Expected BadModuleSource"),
+    }
+}
@@ -7,7 +7,9 @@ extern crate clap;
 
 use admin::AdminEvent;
 use bot::{Bot, BotEvent};
-use clap::{App, Arg, ArgMatches};
+use bundle::{Bundle, BundleError, ImportStrategy};
+use chatbot_import::ChatBot;
+use clap::{App, Arg, ArgMatches, SubCommand};
 use crossbeam::channel::{bounded, Receiver, Sender};
 use discord::DiscordEvent;
 use futures::executor::block_on;
@@ -25,13 +27,20 @@ use twitch::TwitchEvent;
 
 mod admin;
 mod bot;
+mod bundle;
+mod chatbot_import;
 mod client;
 mod command;
 pub mod database;
+mod dice;
 mod discord;
 mod gui;
 pub mod models;
+mod output_cache;
+mod schedule;
 mod script_runner;
+mod script_source;
+mod search;
 mod server;
 mod special_command;
 mod twitch;
@@ -84,14 +93,100 @@ fn parse_args<'a>() -> ArgMatches<'a> {
                 .help("Connects to discord")
                 .takes_value(false),
         )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Writes commands and variables to a .toml or .json file")
+                .arg(Arg::with_name("file").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Reads commands and variables from a file written by export, or another bot")
+                .arg(Arg::with_name("file").required(true))
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .help("Reads another bot's command export instead")
+                        .possible_values(&["nightbot", "streamelements", "streamlabs"])
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("strategy")
+                        .long("strategy")
+                        .help("merge adds what's missing, overwrite also replaces")
+                        .possible_values(&["merge", "overwrite"])
+                        .default_value("merge"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Shows what would change without changing anything")
+                        .takes_value(false),
+                ),
+        )
         .get_matches()
 }
 
+fn export(path: &str) -> Result<String, BundleError> {
+    let database = database::Database::new()?;
+    let bundle = Bundle::export(&database)?;
+    bundle.write(path)?;
+    Ok(format!(
+        "Exported {} commands and {} variables to {}",
+        bundle.commands.len(),
+        bundle.variables.len(),
+        path
+    ))
+}
+
+fn import(args: &ArgMatches) -> Result<String, BundleError> {
+    let strategy = ImportStrategy::parse(args.value_of("strategy").unwrap_or("merge"))?;
+    let dry_run = args.is_present("dry-run");
+    let path = args.value_of("file").unwrap();
+    let (bundle, problems) = match args.value_of("from") {
+        Some(chat_bot) => {
+            let text = std::fs::read_to_string(path)?;
+            let translation = chatbot_import::translate(&text, ChatBot::parse(chat_bot)?)?;
+            (translation.bundle, translation.problems)
+        }
+        None => (Bundle::read(path)?, Vec::new()),
+    };
+    let database = database::Database::new()?;
+    let report = bundle.import(&database, strategy, dry_run)?;
+    let mut summary = if dry_run {
+        format!("Dry run, nothing was changed: {}", report)
+    } else {
+        report.to_string()
+    };
+    if !problems.is_empty() {
+        summary += &format!(
+            "\nNot translated, fix these by hand:\n{}",
+            problems.join("\n")
+        );
+    }
+    Ok(summary)
+}
+
 fn main() -> Result<(), ConnectError> {
     env_logger::init();
 
     let args = parse_args();
 
+    let result = match args.subcommand() {
+        ("export", Some(args)) => Some(export(args.value_of("file").unwrap())),
+        ("import", Some(args)) => Some(import(args)),
+        _ => None,
+    };
+    if let Some(result) = result {
+        match result {
+            Ok(summary) => println!("{}", summary),
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
     if args.is_present("gui") {
         gui::run();
     } else {
@@ -338,6 +433,17 @@ impl Debug for Event {
                     BotEvent::DeleteVariable(variable, user) => {
                         format!("Delete Variable by {}: {}", user.username, variable.name)
                     }
+                    BotEvent::AddModule(module, user) => format!(
+                        "Add Module by {}: {}: {}",
+                        user.username, module.name, module.source
+                    ),
+                    BotEvent::EditModule(module, user) => format!(
+                        "Edit Module by {}: {}: {}",
+                        user.username, module.name, module.source
+                    ),
+                    BotEvent::DeleteModule(module, user) => {
+                        format!("Delete Module by {}: {}", user.username, module.name)
+                    }
                 },
                 Event::TwitchEvent(e) => match e {
                     TwitchEvent::Ready(_) => "Twitch - Ready".to_string(),